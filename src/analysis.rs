@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::disasm::{cowgod, decode_at};
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use crate::memory::{PROGRAM_START, XO_MEMORY_SIZE};
+use crate::quirks::Platform;
+
+/// How a basic block ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator
+{
+  /// Runs into the block at the address, which something else jumps to.
+  Fall(u16),
+  /// 1NNN.
+  Jump(u16),
+  /// A skip: continues at `next`, or at `skip` if the skip is taken.
+  Branch { next:u16, skip:u16 },
+  /// 2NNN, returning to `next`.
+  Call { target:u16, next:u16 },
+  /// 00EE.
+  Return,
+  /// 00FD.
+  Exit,
+  /// BNNN, whose target depends on a register.
+  Computed { base:u16 },
+  /// An undefined opcode (including 0NNN, which we can't run), or the
+  /// end of the ROM.
+  Invalid,
+}
+
+/// A run of instructions entered only at the top and left only at the
+/// bottom.
+#[derive(Debug, Clone)]
+pub struct Block
+{
+  pub start:       u16,
+  pub end:         u16, // One past the last instruction
+  pub instructions:Vec<(u16, Instruction)>,
+  pub terminator:  Terminator,
+}
+
+impl Block
+{
+  /// Blocks control can continue at within the same subroutine; a call
+  /// continues after it returns.
+  pub fn successors(&self) -> Vec<u16>
+  {
+    match self.terminator
+    {
+      Terminator::Fall(next) | Terminator::Jump(next) | Terminator::Call { next, .. } => vec![next],
+      Terminator::Branch { next, skip } => vec![next, skip],
+      _ => Vec::new(),
+    }
+  }
+}
+
+/// A subroutine: the entry point or a call target, with the blocks reached
+/// from it and the subroutines it calls.
+#[derive(Debug, Clone)]
+pub struct Function
+{
+  pub entry: u16,
+  pub blocks:BTreeSet<u16>,
+  pub calls: BTreeSet<u16>,
+}
+
+/// What a byte of the ROM most likely is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteKind
+{
+  /// Part of an instruction reachable from 0x200.
+  Code,
+  /// Not code, and at or after an address loaded into I.
+  Data,
+  /// Neither reached nor pointed at.
+  Unknown,
+}
+
+/// The static structure of a ROM: its basic blocks, call graph and which
+/// bytes are code. Only statically known control flow is followed, so code
+/// reached through BNNN alone shows up as unknown.
+pub struct Analysis
+{
+  pub blocks:    BTreeMap<u16, Block>, // By start address
+  pub functions: BTreeMap<u16, Function>, // By entry point
+  pub data:      BTreeSet<u16>, // Addresses loaded into I
+  pub unresolved:Vec<u16>, // BNNN instructions
+  pub invalid:   Vec<u16>, // Where execution runs into something undefined
+  rom:           Vec<u8>,
+  code:          Vec<bool>, // By ROM offset
+}
+
+// The ROM offset of `addr`, if it's in the ROM.
+fn offset(rom:&[u8], addr:u16) -> Option<usize>
+{
+  (addr as usize).checked_sub(PROGRAM_START).filter(|&offset| offset < rom.len())
+}
+
+fn is_skip(instruction:Instruction) -> bool
+{
+  matches!(instruction, Instruction::SkipEqImm { .. } | Instruction::SkipNeImm { .. } |
+                        Instruction::SkipEqReg { .. } | Instruction::SkipNeReg { .. } |
+                        Instruction::SkipKeyPressed { .. } | Instruction::SkipKeyNotPressed { .. })
+}
+
+impl Analysis
+{
+  /// Analyze a ROM loaded at 0x200 for `platform`, following jumps, calls,
+  /// returns and skips from the entry point. Fails if the ROM runs past
+  /// the 16 bit address space.
+  pub fn new(rom:&[u8], platform:Platform) -> Result<Self, Chip8Error>
+  {
+    let max = XO_MEMORY_SIZE - PROGRAM_START;
+
+    if rom.len() > max
+    {
+      return Err(Chip8Error::RomTooLarge { size:rom.len(), max });
+    }
+
+    let decode = |addr:u16| offset(rom, addr).and_then(|offset| decode_at(rom, offset, platform));
+
+    let mut leaders   = BTreeSet::new();
+    let mut entries   = BTreeSet::new();
+    let mut code      = vec![false; rom.len()];
+    let mut data      = BTreeSet::new();
+    let mut traced    = BTreeSet::new();
+    let mut worklist  = vec![PROGRAM_START as u16];
+
+    entries.insert(PROGRAM_START as u16);
+
+    // Find the leaders, marking the code on the way.
+    while let Some(start) = worklist.pop()
+    {
+      leaders.insert(start);
+
+      let mut addr = start;
+
+      while traced.insert(addr)
+      {
+        let (instruction, size) = match decode(addr)
+        {
+          Some((Instruction::Undefined { .. }, _)) | Some((Instruction::Sys { .. }, _)) | None => break,
+          Some(decoded) => decoded,
+        };
+
+        let at = addr as usize - PROGRAM_START;
+
+        code[at..at + size].iter_mut().for_each(|byte| *byte = true);
+
+        let next = addr.wrapping_add(size as u16);
+
+        match instruction
+        {
+          Instruction::Jump { nnn } =>
+          {
+            worklist.push(nnn);
+            break;
+          },
+          Instruction::Call { nnn } =>
+          {
+            entries.insert(nnn);
+            worklist.push(nnn);
+            worklist.push(next);
+            break;
+          },
+          Instruction::Ret | Instruction::Exit | Instruction::JumpOffset { .. } => break,
+          _ if is_skip(instruction) =>
+          {
+            let skipped = decode(next).map_or(2, |(_, size)| size as u16);
+
+            worklist.push(next);
+            worklist.push(next.wrapping_add(skipped));
+            break;
+          },
+          Instruction::LoadIndex { nnn } => { data.insert(nnn); },
+          Instruction::LoadLongIndex => { data.insert((rom[at + 2] as u16) << 8 | rom[at + 3] as u16); },
+          _ => {},
+        }
+
+        addr = next;
+      }
+    }
+
+    // Cut the traced code into blocks at the leaders.
+    let mut blocks     = BTreeMap::new();
+    let mut unresolved = Vec::new();
+    let mut invalid    = Vec::new();
+
+    for &start in leaders.iter()
+    {
+      let mut instructions = Vec::new();
+      let mut addr         = start;
+
+      let terminator = loop
+      {
+        if addr != start && leaders.contains(&addr)
+        {
+          break Terminator::Fall(addr);
+        }
+
+        let (instruction, size) = match decode(addr)
+        {
+          Some((Instruction::Undefined { .. }, _)) | Some((Instruction::Sys { .. }, _)) | None =>
+          {
+            invalid.push(addr);
+            break Terminator::Invalid;
+          },
+          Some(decoded) => decoded,
+        };
+
+        instructions.push((addr, instruction));
+
+        let next = addr.wrapping_add(size as u16);
+
+        match instruction
+        {
+          Instruction::Jump { nnn }       => break Terminator::Jump(nnn),
+          Instruction::Call { nnn }       => break Terminator::Call { target:nnn, next },
+          Instruction::Ret                => break Terminator::Return,
+          Instruction::Exit               => break Terminator::Exit,
+          Instruction::JumpOffset { nnn } =>
+          {
+            unresolved.push(addr);
+            break Terminator::Computed { base:nnn };
+          },
+          _ if is_skip(instruction) =>
+          {
+            let skipped = decode(next).map_or(2, |(_, size)| size as u16);
+
+            break Terminator::Branch { next, skip:next.wrapping_add(skipped) };
+          },
+          _ => addr = next,
+        }
+      };
+
+      let end = instructions.last().map_or(start, |&(addr, instruction)| addr.wrapping_add(instruction.size()));
+
+      blocks.insert(start, Block { start, end, instructions, terminator });
+    }
+
+    // Group the blocks into subroutines.
+    let mut functions = BTreeMap::new();
+
+    for &entry in entries.iter()
+    {
+      let mut function = Function { entry, blocks:BTreeSet::new(), calls:BTreeSet::new() };
+      let mut pending  = vec![entry];
+
+      while let Some(start) = pending.pop()
+      {
+        let block:&Block = match blocks.get(&start)
+        {
+          Some(block) if function.blocks.insert(start) => block,
+          _ => continue,
+        };
+
+        if let Terminator::Call { target, .. } = block.terminator
+        {
+          function.calls.insert(target);
+        }
+
+        pending.extend(block.successors());
+      }
+
+      functions.insert(entry, function);
+    }
+
+    Ok(Analysis { blocks, functions, data, unresolved, invalid, rom:rom.to_vec(), code })
+  }
+
+  pub fn byte_kind(&self, addr:u16) -> ByteKind
+  {
+    let at = match offset(&self.rom, addr)
+    {
+      Some(at) => at,
+      None     => return ByteKind::Unknown,
+    };
+
+    if self.code[at]
+    {
+      return ByteKind::Code;
+    }
+
+    // Data runs from an address loaded into I up to the next code.
+    let after_data = self.data.range(..=addr).next_back()
+                                             .and_then(|&data| offset(&self.rom, data))
+                                             .is_some_and(|data| !self.code[data..at].contains(&true));
+
+    if after_data { ByteKind::Data } else { ByteKind::Unknown }
+  }
+
+  /// The ROM as runs of bytes of the same kind: (start, end, kind), with
+  /// `end` exclusive, so it may be 0x10000.
+  pub fn ranges(&self) -> Vec<(usize, usize, ByteKind)>
+  {
+    let mut ranges:Vec<(usize, usize, ByteKind)> = Vec::new();
+
+    for at in 0..self.rom.len()
+    {
+      let addr = PROGRAM_START + at;
+      let kind = self.byte_kind(addr as u16);
+
+      match ranges.last_mut()
+      {
+        Some((_, end, last)) if *last == kind => *end = addr + 1,
+        _ => ranges.push((addr, addr + 1, kind)),
+      }
+    }
+
+    ranges
+  }
+
+  /// The instruction at `addr` as classic mnemonics.
+  pub fn mnemonic(&self, addr:u16, instruction:Instruction) -> String
+  {
+    let long = offset(&self.rom, addr).and_then(|at| self.rom.get(at + 2..at + 4))
+                                      .map_or(0, |nnnn| (nnnn[0] as u16) << 8 | nnnn[1] as u16);
+
+    cowgod(instruction, long)
+  }
+
+  /// The control-flow graph in Graphviz DOT: a node per basic block, with
+  /// dashed edges to the subroutines it calls.
+  pub fn to_dot(&self) -> String
+  {
+    let mut dot = String::from("digraph cfg {\n  node [shape=box, fontname=\"monospace\"];\n");
+
+    for block in self.blocks.values()
+    {
+      let mut label = String::new();
+
+      for &(addr, instruction) in block.instructions.iter()
+      {
+        let _ = write!(label, "{:#05X}  {}\\l", addr, self.mnemonic(addr, instruction));
+      }
+
+      let style = match block.terminator
+      {
+        Terminator::Computed { .. } | Terminator::Invalid => ", color=red",
+        _ if self.functions.contains_key(&block.start)     => ", peripheries=2",
+        _                                                  => "",
+      };
+
+      let _ = writeln!(dot, "  b{:03X} [label=\"{}\"{}];", block.start, label, style);
+
+      let edges = match block.terminator
+      {
+        Terminator::Fall(next)             => vec![(next, "")],
+        Terminator::Jump(target)           => vec![(target, "")],
+        Terminator::Branch { next, skip }  => vec![(next, "next"), (skip, "skip")],
+        Terminator::Call { target, next }  => vec![(target, "call"), (next, "return")],
+        _                                  => Vec::new(),
+      };
+
+      for (to, kind) in edges
+      {
+        let attributes = match kind
+        {
+          ""     => String::new(),
+          "call" => " [label=\"call\", style=dashed]".to_string(),
+          _      => format!(" [label=\"{}\"]", kind),
+        };
+
+        let _ = writeln!(dot, "  b{:03X} -> b{:03X}{};", block.start, to, attributes);
+      }
+    }
+
+    dot.push_str("}\n");
+    dot
+  }
+
+  /// The call graph in Graphviz DOT: a node per subroutine.
+  pub fn call_graph_dot(&self) -> String
+  {
+    let mut dot = String::from("digraph calls {\n  node [shape=box, fontname=\"monospace\"];\n");
+
+    for function in self.functions.values()
+    {
+      let _ = writeln!(dot, "  f{:03X} [label=\"{:#05X}\"];", function.entry, function.entry);
+
+      for callee in function.calls.iter()
+      {
+        let _ = writeln!(dot, "  f{:03X} -> f{:03X};", function.entry, callee);
+      }
+    }
+
+    dot.push_str("}\n");
+    dot
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  // Call a subroutine that points I at a sprite, then branch on V0 and
+  // loop. 0x20A and 0x210 are never reached.
+  const ROM:[u8;21] = [0x22, 0x0C, 0x30, 0x00, 0x12, 0x08, 0x70, 0x01, 0x12, 0x08, 0xFF, 0xFF,
+                       0xA2, 0x12, 0x00, 0xEE, 0x00, 0x00, 0xF0, 0x90, 0xF0];
+
+  #[test]
+  fn blocks_end_at_control_flow()
+  {
+    let analysis = Analysis::new(&ROM, Platform::CosmacVip).unwrap();
+
+    let terminators:Vec<(u16, u16, Terminator)> = analysis.blocks.values()
+                                                          .map(|block| (block.start, block.end, block.terminator))
+                                                          .collect();
+
+    assert_eq!(terminators, vec![(0x200, 0x202, Terminator::Call { target:0x20C, next:0x202 }),
+                                 (0x202, 0x204, Terminator::Branch { next:0x204, skip:0x206 }),
+                                 (0x204, 0x206, Terminator::Jump(0x208)),
+                                 (0x206, 0x208, Terminator::Fall(0x208)),
+                                 (0x208, 0x20A, Terminator::Jump(0x208)),
+                                 (0x20C, 0x210, Terminator::Return)]);
+
+    assert!(analysis.unresolved.is_empty() && analysis.invalid.is_empty());
+  }
+
+  #[test]
+  fn functions_collect_their_blocks_and_calls()
+  {
+    let analysis = Analysis::new(&ROM, Platform::CosmacVip).unwrap();
+
+    let main = &analysis.functions[&0x200];
+    assert_eq!(main.blocks.iter().cloned().collect::<Vec<_>>(), vec![0x200, 0x202, 0x204, 0x206, 0x208]);
+    assert_eq!(main.calls.iter().cloned().collect::<Vec<_>>(), vec![0x20C]);
+
+    let sub = &analysis.functions[&0x20C];
+    assert_eq!(sub.blocks.iter().cloned().collect::<Vec<_>>(), vec![0x20C]);
+    assert!(sub.calls.is_empty());
+
+    assert_eq!(analysis.functions.len(), 2);
+  }
+
+  #[test]
+  fn bytes_are_classified()
+  {
+    let analysis = Analysis::new(&ROM, Platform::CosmacVip).unwrap();
+
+    assert_eq!(analysis.ranges(), vec![(0x200, 0x20A, ByteKind::Code),
+                                       (0x20A, 0x20C, ByteKind::Unknown),
+                                       (0x20C, 0x210, ByteKind::Code),
+                                       (0x210, 0x212, ByteKind::Unknown),
+                                       (0x212, 0x215, ByteKind::Data)]);
+  }
+
+  #[test]
+  fn roms_may_fill_but_not_pass_the_address_space()
+  {
+    let mut rom = vec![0x0;XO_MEMORY_SIZE - PROGRAM_START];
+    rom[..2].copy_from_slice(&[0x12, 0x00]);
+
+    let analysis = Analysis::new(&rom, Platform::XoChip).unwrap();
+    assert_eq!(analysis.ranges(), vec![(0x200, 0x202, ByteKind::Code), (0x202, 0x10000, ByteKind::Unknown)]);
+
+    rom.push(0x0);
+    assert_eq!(Analysis::new(&rom, Platform::XoChip).err(), Some(Chip8Error::RomTooLarge { size:0xFE01, max:0xFE00 }));
+  }
+}
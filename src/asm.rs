@@ -0,0 +1,1150 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+use crate::instruction::Instruction;
+use crate::memory::{PROGRAM_START, XO_MEMORY_SIZE};
+use crate::opcode::OpCode;
+use crate::symbols::{SourceLine, SymbolMap};
+
+// Tokens macro calls may expand to in total, so a macro calling itself
+// fails instead of running out of memory.
+const MAX_EXPANSION:usize = 1_000_000;
+
+/// Why a source file couldn't be assembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError
+{
+  pub line:   u32, // 1-based
+  pub message:String,
+}
+
+impl fmt::Display for AsmError
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+impl Error for AsmError {}
+
+/// An assembled program: the ROM image, loaded at 0x200, and its symbols.
+#[derive(Debug, Clone)]
+pub struct Assembly
+{
+  pub rom:    Vec<u8>,
+  pub symbols:SymbolMap,
+}
+
+#[derive(Debug, Clone)]
+struct Token
+{
+  text:String,
+  line:u32,
+}
+
+struct Macro
+{
+  params:Vec<String>,
+  body:  Vec<Token>,
+}
+
+// An operand that's either a register or a byte.
+enum Operand
+{
+  Reg(u8),
+  Byte(u8),
+}
+
+// Where a name used before its definition goes once it's known.
+enum Patch
+{
+  Nnn,  // The low 12 bits of the instruction
+  Long, // The 16 bit address following F000
+}
+
+struct Fixup
+{
+  at:   usize,
+  patch:Patch,
+  name: String,
+  line: u32,
+}
+
+// Open control flow structures, innermost last. The addresses are those of
+// jumps to patch once their target is known.
+enum Flow
+{
+  Begin { jump:usize, line:u32 },
+  Else  { jump:usize, line:u32 },
+  Loop  { start:usize, breaks:Vec<usize>, line:u32 },
+}
+
+struct Assembler
+{
+  file:       String,
+  tokens:     Vec<Token>,
+  pos:        usize,
+  line:       u32, // Of the last token read
+  stmt_line:  u32, // Of the statement being assembled
+  expanded:   usize,
+  rom:        Vec<u8>,
+  here:       usize,
+  entry_jump: Option<bool>, // Whether 0x200 jumps to main, once that's known
+  labels:     BTreeMap<String, u16>,
+  consts:     HashMap<String, i64>,
+  aliases:    HashMap<String, u8>,
+  macros:     HashMap<String, Macro>,
+  breakpoints:BTreeMap<String, u16>,
+  fixups:     Vec<Fixup>,
+  flow:       Vec<Flow>,
+  lines:      Vec<SourceLine>,
+}
+
+// Split the source into whitespace separated tokens, dropping comments.
+fn tokenize(source:&str) -> Vec<Token>
+{
+  let mut tokens = Vec::new();
+
+  for (idx, line) in source.lines().enumerate()
+  {
+    let code = match line.find('#') { Some(at) => &line[..at], None => line };
+
+    for word in code.split_whitespace()
+    {
+      tokens.push(Token { text:word.to_string(), line:idx as u32 + 1 });
+    }
+  }
+
+  tokens
+}
+
+// A number literal: decimal, 0x hex or 0b binary, optionally negative.
+fn literal(text:&str) -> Option<i64>
+{
+  let (negative, digits) = match text.strip_prefix('-') { Some(digits) => (true, digits), None => (false, text) };
+
+  let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X"))
+  {
+    i64::from_str_radix(hex, 16).ok()?
+  }
+  else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B"))
+  {
+    i64::from_str_radix(bin, 2).ok()?
+  }
+  else if digits.starts_with(|c:char| c.is_ascii_digit())
+  {
+    digits.parse().ok()?
+  }
+  else
+  {
+    return None;
+  };
+
+  Some(if negative { -value } else { value })
+}
+
+fn register(text:&str) -> Option<u8>
+{
+  let idx = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+
+  match idx.len()
+  {
+    1 => u8::from_str_radix(idx, 16).ok(),
+    _ => None,
+  }
+}
+
+fn is_name(text:&str) -> bool
+{
+  text.starts_with(|c:char| c.is_ascii_alphabetic() || c == '_') &&
+    text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') &&
+    register(text).is_none()
+}
+
+// The skip that tests the opposite condition.
+fn inverse(skip:Instruction) -> Instruction
+{
+  match skip
+  {
+    Instruction::SkipEqImm { x, nn }       => Instruction::SkipNeImm { x, nn },
+    Instruction::SkipNeImm { x, nn }       => Instruction::SkipEqImm { x, nn },
+    Instruction::SkipEqReg { x, y }        => Instruction::SkipNeReg { x, y },
+    Instruction::SkipNeReg { x, y }        => Instruction::SkipEqReg { x, y },
+    Instruction::SkipKeyPressed { x }      => Instruction::SkipKeyNotPressed { x },
+    Instruction::SkipKeyNotPressed { x }   => Instruction::SkipKeyPressed { x },
+    other                                  => other,
+  }
+}
+
+// A binary operator of :calc.
+fn apply(op:&str, left:i64, right:i64) -> Result<i64, String>
+{
+  match op
+  {
+    "+"  => Ok(left.wrapping_add(right)),
+    "-"  => Ok(left.wrapping_sub(right)),
+    "*"  => Ok(left.wrapping_mul(right)),
+    "/" | "%" if right == 0 => Err("division by zero".to_string()),
+    "/"  => Ok(left / right),
+    "%"  => Ok(left % right),
+    "&"  => Ok(left & right),
+    "|"  => Ok(left | right),
+    "^"  => Ok(left ^ right),
+    "<<" | ">>" if !(0..64).contains(&right) => Err(format!("can't shift by {}", right)),
+    "<<" => Ok(left << right),
+    ">>" => Ok(left >> right),
+    _    => Err(format!("unknown operator '{}'", op)),
+  }
+}
+
+const BINARY_OPERATORS:&[&str] = &["+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>"];
+
+impl Assembler
+{
+  fn new(tokens:Vec<Token>, file:&str) -> Self
+  {
+    Assembler { file:       file.to_string(),
+                tokens,
+                pos:        0,
+                line:       1,
+                stmt_line:  1,
+                expanded:   0,
+                rom:        Vec::new(),
+                here:       PROGRAM_START,
+                entry_jump: None,
+                labels:     BTreeMap::new(),
+                consts:     HashMap::new(),
+                aliases:    HashMap::new(),
+                macros:     HashMap::new(),
+                breakpoints:BTreeMap::new(),
+                fixups:     Vec::new(),
+                flow:       Vec::new(),
+                lines:      Vec::new(),
+              }
+  }
+
+  fn error<T>(&self, message:String) -> Result<T, AsmError>
+  {
+    Err(AsmError { line:self.line, message })
+  }
+
+  fn next(&mut self) -> Result<String, AsmError>
+  {
+    match self.tokens.get(self.pos)
+    {
+      Some(token) =>
+      {
+        self.line = token.line;
+        self.pos += 1;
+
+        Ok(token.text.clone())
+      },
+      None => self.error("unexpected end of file".to_string()),
+    }
+  }
+
+  fn peek(&self) -> Option<&str>
+  {
+    self.tokens.get(self.pos).map(|token| token.text.as_str())
+  }
+
+  fn expect(&mut self, text:&str) -> Result<(), AsmError>
+  {
+    match self.next()?
+    {
+      ref found if found == text => Ok(()),
+      found => self.error(format!("expected '{}', found '{}'", text, found)),
+    }
+  }
+
+  fn name(&mut self) -> Result<String, AsmError>
+  {
+    match self.next()?
+    {
+      ref name if is_name(name) => Ok(name.clone()),
+      name => self.error(format!("'{}' isn't a valid name", name)),
+    }
+  }
+
+  fn register_of(&self, text:&str) -> Option<u8>
+  {
+    self.aliases.get(text).cloned().or_else(|| register(text))
+  }
+
+  fn register(&mut self) -> Result<u8, AsmError>
+  {
+    let text = self.next()?;
+
+    match self.register_of(&text)
+    {
+      Some(x) => Ok(x),
+      None    => self.error(format!("expected a register, found '{}'", text)),
+    }
+  }
+
+  // The value of `text` if it's already known: a literal, a constant, a
+  // label defined earlier, or a :calc expression if it opens one.
+  fn known_value(&mut self, text:&str) -> Result<Option<i64>, AsmError>
+  {
+    if text == "{"
+    {
+      return self.calc().map(Some);
+    }
+
+    Ok(literal(text).or_else(|| self.consts.get(text).cloned())
+                    .or_else(|| self.labels.get(text).map(|&addr| addr as i64)))
+  }
+
+  fn value(&mut self) -> Result<i64, AsmError>
+  {
+    let text = self.next()?;
+
+    match self.known_value(&text)?
+    {
+      Some(value) => Ok(value),
+      None        => self.error(format!("unknown name '{}'", text)),
+    }
+  }
+
+  fn value_in(&mut self, min:i64, max:i64, what:&str) -> Result<i64, AsmError>
+  {
+    match self.value()?
+    {
+      value if value >= min && value <= max => Ok(value),
+      value => self.error(format!("{} doesn't fit in {}", value, what)),
+    }
+  }
+
+  fn byte(&mut self) -> Result<u8, AsmError>
+  {
+    self.value_in(-128, 255, "a byte").map(|value| value as u8)
+  }
+
+  fn nibble(&mut self) -> Result<u8, AsmError>
+  {
+    self.value_in(0, 15, "4 bits").map(|value| value as u8)
+  }
+
+  fn operand(&mut self) -> Result<Operand, AsmError>
+  {
+    match self.peek().and_then(|text| self.register_of(text))
+    {
+      Some(y) =>
+      {
+        self.next()?;
+        Ok(Operand::Reg(y))
+      },
+      None => self.byte().map(Operand::Byte),
+    }
+  }
+
+  // A :calc expression, after its opening brace. Like Octo's, operators
+  // have no precedence and group right to left; use parentheses.
+  fn calc(&mut self) -> Result<i64, AsmError>
+  {
+    let value = self.expression()?;
+
+    self.expect("}")?;
+
+    Ok(value)
+  }
+
+  fn expression(&mut self) -> Result<i64, AsmError>
+  {
+    let left = self.term()?;
+
+    match self.peek()
+    {
+      Some("}") | Some(")") => Ok(left),
+      Some(op) if BINARY_OPERATORS.contains(&op) =>
+      {
+        let op    = self.next()?;
+        let right = self.expression()?;
+
+        apply(&op, left, right).or_else(|message| self.error(message))
+      },
+      Some(text) => { let text = text.to_string(); self.error(format!("expected an operator, found '{}'", text)) },
+      None       => self.error("unexpected end of file".to_string()),
+    }
+  }
+
+  fn term(&mut self) -> Result<i64, AsmError>
+  {
+    let text = self.next()?;
+
+    match text.as_str()
+    {
+      "(" =>
+      {
+        let value = self.expression()?;
+
+        self.expect(")")?;
+
+        Ok(value)
+      },
+      "-"    => Ok(self.term()?.wrapping_neg()),
+      "~"    => Ok(!self.term()?),
+      "HERE" =>
+      {
+        self.settle_entry(true)?;
+
+        Ok(self.here as i64)
+      },
+      "{"    => self.error("unexpected '{'".to_string()),
+      _      => match self.known_value(&text)?
+      {
+        Some(value) => Ok(value),
+        None        => self.error(format!("unknown name '{}' in expression", text)),
+      },
+    }
+  }
+
+  fn put(&mut self, byte:u8) -> Result<(), AsmError>
+  {
+    if self.here >= XO_MEMORY_SIZE
+    {
+      return self.error("program doesn't fit in 64K".to_string());
+    }
+
+    let offset = self.here - PROGRAM_START;
+
+    if offset >= self.rom.len()
+    {
+      self.rom.resize(offset + 1, 0);
+    }
+
+    self.rom[offset] = byte;
+    self.here += 1;
+
+    Ok(())
+  }
+
+  fn emit(&mut self, instruction:Instruction) -> Result<(), AsmError>
+  {
+    let opcode = instruction.encode();
+
+    // Whatever we emit must run as what was written.
+    debug_assert_eq!(OpCode::new(opcode).decode(), instruction);
+
+    self.lines.push(SourceLine { file:self.file.clone(), line:self.stmt_line, addr:self.here as u16 });
+
+    self.put((opcode >> 8) as u8)?;
+    self.put(opcode as u8)
+  }
+
+  // Emit the instruction `build` makes from a 12 bit address, `text`. A
+  // label that isn't defined yet is filled in at the end.
+  fn emit_nnn(&mut self, text:String, build:fn(u16) -> Instruction) -> Result<(), AsmError>
+  {
+    match self.known_value(&text)?
+    {
+      Some(nnn) if (0..=0xFFF).contains(&nnn) => self.emit(build(nnn as u16)),
+      Some(nnn) => self.error(format!("address {:#X} doesn't fit in 12 bits", nnn)),
+      None if is_name(&text) =>
+      {
+        self.fixups.push(Fixup { at:self.here, patch:Patch::Nnn, name:text, line:self.line });
+        self.emit(build(0))
+      },
+      None => self.error(format!("expected an address, found '{}'", text)),
+    }
+  }
+
+  fn emit_jump(&mut self, target:usize) -> Result<(), AsmError>
+  {
+    if target > 0xFFF
+    {
+      return self.error(format!("can't jump to {:#X}, past 12 bits", target));
+    }
+
+    self.emit(Instruction::Jump { nnn:target as u16 })
+  }
+
+  // Point the jump at `at` to `target`.
+  fn patch_jump(&mut self, at:usize, target:usize) -> Result<(), AsmError>
+  {
+    if target > 0xFFF
+    {
+      return self.error(format!("can't jump to {:#X}, past 12 bits", target));
+    }
+
+    let opcode = Instruction::Jump { nnn:target as u16 }.encode();
+    let offset = at - PROGRAM_START;
+
+    self.rom[offset]     = (opcode >> 8) as u8;
+    self.rom[offset + 1] = opcode as u8;
+
+    Ok(())
+  }
+
+  // Settle whether execution needs a jump from 0x200 to main, as it does
+  // unless main is defined before anything else takes up memory. Until
+  // then only names are defined, all of them at 0x200.
+  fn settle_entry(&mut self, jump:bool) -> Result<(), AsmError>
+  {
+    if self.entry_jump.is_some()
+    {
+      return Ok(());
+    }
+
+    self.entry_jump = Some(jump);
+
+    if jump
+    {
+      for addr in self.labels.values_mut().chain(self.breakpoints.values_mut())
+      {
+        *addr += 2;
+      }
+
+      self.fixups.push(Fixup { at:PROGRAM_START, patch:Patch::Nnn, name:"main".to_string(), line:1 });
+      self.put(0x10)?;
+      self.put(0x00)?;
+    }
+
+    Ok(())
+  }
+
+  fn define(&mut self, name:&str) -> Result<(), AsmError>
+  {
+    if self.labels.contains_key(name) || self.consts.contains_key(name)
+    {
+      return self.error(format!("'{}' is already defined", name));
+    }
+
+    Ok(())
+  }
+
+  // The instructions testing a condition, the last of them skipping the
+  // next instruction when the condition is false.
+  fn condition(&mut self) -> Result<Vec<Instruction>, AsmError>
+  {
+    let x  = self.register()?;
+    let op = self.next()?;
+
+    let tests = match op.as_str()
+    {
+      "key"  => vec![Instruction::SkipKeyNotPressed { x }],
+      "-key" => vec![Instruction::SkipKeyPressed { x }],
+      "==" => match self.operand()?
+      {
+        Operand::Reg(y)   => vec![Instruction::SkipNeReg { x, y }],
+        Operand::Byte(nn) => vec![Instruction::SkipNeImm { x, nn }],
+      },
+      "!=" => match self.operand()?
+      {
+        Operand::Reg(y)   => vec![Instruction::SkipEqReg { x, y }],
+        Operand::Byte(nn) => vec![Instruction::SkipEqImm { x, nn }],
+      },
+      // These subtract in VF and test the borrow flag.
+      "<" | ">" | "<=" | ">=" =>
+      {
+        let load = match self.operand()?
+        {
+          Operand::Reg(y) if x != 0xF && y != 0xF => Instruction::Move { x:0xF, y },
+          Operand::Byte(nn) if x != 0xF           => Instruction::LoadImm { x:0xF, nn },
+          _ => return self.error(format!("'{}' uses vf as scratch, so it can't compare vf", op)),
+        };
+
+        let subtract = match op.as_str()
+        {
+          "<" | ">=" => Instruction::SubReverse { x:0xF, y:x },
+          _          => Instruction::Sub { x:0xF, y:x },
+        };
+
+        let borrow = match op.as_str() { "<" | ">" => 1, _ => 0 };
+
+        vec![load, subtract, Instruction::SkipEqImm { x:0xF, nn:borrow }]
+      },
+      _ => return self.error(format!("unknown comparison '{}'", op)),
+    };
+
+    Ok(tests)
+  }
+
+  // Emit `tests` inverted, followed by a jump that's taken when their
+  // condition is false, and return the jump's address.
+  fn branch_unless(&mut self, mut tests:Vec<Instruction>) -> Result<usize, AsmError>
+  {
+    let last = tests.len() - 1;
+
+    tests[last] = inverse(tests[last]);
+
+    for instruction in tests
+    {
+      self.emit(instruction)?;
+    }
+
+    let jump = self.here;
+
+    self.emit(Instruction::Jump { nnn:0 })?;
+
+    Ok(jump)
+  }
+
+  fn assignment(&mut self, x:u8) -> Result<(), AsmError>
+  {
+    let op = self.next()?;
+
+    let instruction = match op.as_str()
+    {
+      ":=" => match self.peek()
+      {
+        Some("random") => { self.next()?; Instruction::Random { x, nn:self.byte()? } },
+        Some("delay")  => { self.next()?; Instruction::GetDelay { x } },
+        Some("key")    => { self.next()?; Instruction::WaitKey { x } },
+        _ => match self.operand()?
+        {
+          Operand::Reg(y)   => Instruction::Move { x, y },
+          Operand::Byte(nn) => Instruction::LoadImm { x, nn },
+        },
+      },
+      "+=" => match self.operand()?
+      {
+        Operand::Reg(y)   => Instruction::Add { x, y },
+        Operand::Byte(nn) => Instruction::AddImm { x, nn },
+      },
+      "-=" => match self.operand()?
+      {
+        Operand::Reg(y)   => Instruction::Sub { x, y },
+        Operand::Byte(nn) => Instruction::AddImm { x, nn:nn.wrapping_neg() },
+      },
+      "=-"  => Instruction::SubReverse { x, y:self.register()? },
+      "|="  => Instruction::Or { x, y:self.register()? },
+      "&="  => Instruction::And { x, y:self.register()? },
+      "^="  => Instruction::Xor { x, y:self.register()? },
+      ">>=" => Instruction::ShiftRight { x, y:self.register()? },
+      "<<=" => Instruction::ShiftLeft { x, y:self.register()? },
+      _ => return self.error(format!("unknown operator '{}'", op)),
+    };
+
+    self.emit(instruction)
+  }
+
+  fn index(&mut self) -> Result<(), AsmError>
+  {
+    let op = self.next()?;
+
+    match op.as_str()
+    {
+      "+=" => { let x = self.register()?; self.emit(Instruction::AddIndex { x }) },
+      ":=" => match self.next()?.as_str()
+      {
+        "hex"    => { let x = self.register()?; self.emit(Instruction::FontChar { x }) },
+        "bighex" => { let x = self.register()?; self.emit(Instruction::BigFontChar { x }) },
+        "long" =>
+        {
+          self.emit(Instruction::LoadLongIndex)?;
+
+          let text = self.next()?;
+
+          let addr = match self.known_value(&text)?
+          {
+            Some(addr) if (0..=0xFFFF).contains(&addr) => addr as u16,
+            Some(addr) => return self.error(format!("address {:#X} doesn't fit in 16 bits", addr)),
+            None if is_name(&text) =>
+            {
+              self.fixups.push(Fixup { at:self.here, patch:Patch::Long, name:text, line:self.line });
+              0
+            },
+            None => return self.error(format!("expected an address, found '{}'", text)),
+          };
+
+          self.put((addr >> 8) as u8)?;
+          self.put(addr as u8)
+        },
+        text => self.emit_nnn(text.to_string(), |nnn| Instruction::LoadIndex { nnn }),
+      },
+      _ => self.error(format!("unknown operator '{}'", op)),
+    }
+  }
+
+  // Replace a macro call with the macro's body.
+  fn expand(&mut self, name:&str) -> Result<(), AsmError>
+  {
+    let line = self.line;
+    let mut args = Vec::new();
+
+    for _ in 0..self.macros[name].params.len()
+    {
+      args.push(self.next()?);
+    }
+
+    let mac  = &self.macros[name];
+    let body:Vec<Token> = mac.body.iter()
+                                  .map(|token| match mac.params.iter().position(|param| *param == token.text)
+                                  {
+                                    Some(idx) => Token { text:args[idx].clone(), line },
+                                    None      => Token { text:token.text.clone(), line },
+                                  })
+                                  .collect();
+
+    self.expanded += body.len();
+
+    if self.expanded > MAX_EXPANSION
+    {
+      return self.error(format!("macro '{}' expands without end", name));
+    }
+
+    self.tokens.splice(self.pos..self.pos, body);
+
+    Ok(())
+  }
+
+  fn define_macro(&mut self) -> Result<(), AsmError>
+  {
+    let name   = self.name()?;
+    let mut params = Vec::new();
+
+    loop
+    {
+      match self.next()?
+      {
+        ref brace if brace == "{" => break,
+        ref param if is_name(param) => params.push(param.clone()),
+        param => return self.error(format!("'{}' isn't a valid macro parameter", param)),
+      }
+    }
+
+    let mut body  = Vec::new();
+    let mut depth = 0;
+
+    loop
+    {
+      let text = self.next()?;
+
+      match text.as_str()
+      {
+        "{" => depth += 1,
+        "}" if depth == 0 => break,
+        "}" => depth -= 1,
+        _   => {},
+      }
+
+      body.push(Token { text, line:self.line });
+    }
+
+    self.macros.insert(name, Macro { params, body });
+
+    Ok(())
+  }
+
+  fn statement(&mut self) -> Result<(), AsmError>
+  {
+    let text = self.next()?;
+
+    self.stmt_line = self.line;
+
+    // Anything but a definition or a macro call takes up memory.
+    let defines = matches!(text.as_str(), ":" | ":alias" | ":const" | ":calc" | ":macro" | ":breakpoint");
+
+    if !defines && !self.macros.contains_key(&text)
+    {
+      self.settle_entry(true)?;
+    }
+
+    match text.as_str()
+    {
+      ":" =>
+      {
+        let name = self.name()?;
+
+        if name == "main"
+        {
+          self.settle_entry(false)?;
+        }
+
+        self.define(&name)?;
+        self.labels.insert(name, self.here as u16);
+
+        Ok(())
+      },
+      ":alias" =>
+      {
+        let name = self.name()?;
+        let x    = self.register()?;
+
+        self.aliases.insert(name, x);
+
+        Ok(())
+      },
+      ":const" =>
+      {
+        let name  = self.name()?;
+        let value = self.value()?;
+
+        self.define(&name)?;
+        self.consts.insert(name, value);
+
+        Ok(())
+      },
+      ":calc" =>
+      {
+        let name = self.name()?;
+
+        self.expect("{")?;
+
+        let value = self.calc()?;
+
+        if self.labels.contains_key(&name)
+        {
+          return self.error(format!("'{}' is already defined", name));
+        }
+
+        self.consts.insert(name, value);
+
+        Ok(())
+      },
+      ":macro"      => self.define_macro(),
+      ":byte"       => { let byte = self.byte()?; self.put(byte) },
+      ":org"        =>
+      {
+        self.here = self.value_in(PROGRAM_START as i64, XO_MEMORY_SIZE as i64 - 1, "memory past 0x200")? as usize;
+
+        Ok(())
+      },
+      ":breakpoint" =>
+      {
+        let name = self.name()?;
+
+        self.breakpoints.insert(name, self.here as u16);
+
+        Ok(())
+      },
+      ":call"       => { let text = self.next()?; self.emit_nnn(text, |nnn| Instruction::Call { nnn }) },
+      "return" | ";"=> self.emit(Instruction::Ret),
+      "clear"       => self.emit(Instruction::Cls),
+      "exit"        => self.emit(Instruction::Exit),
+      "lores"       => self.emit(Instruction::LowRes),
+      "hires"       => self.emit(Instruction::HighRes),
+      "scroll-left" => self.emit(Instruction::ScrollLeft),
+      "scroll-right"=> self.emit(Instruction::ScrollRight),
+      "scroll-down" => { let n = self.nibble()?; self.emit(Instruction::ScrollDown { n }) },
+      "audio"       => self.emit(Instruction::LoadAudio),
+      "plane"       => { let n = self.nibble()?; self.emit(Instruction::SelectPlanes { n }) },
+      "jump"        => { let text = self.next()?; self.emit_nnn(text, |nnn| Instruction::Jump { nnn }) },
+      "jump0"       => { let text = self.next()?; self.emit_nnn(text, |nnn| Instruction::JumpOffset { nnn }) },
+      "sprite"      =>
+      {
+        let x = self.register()?;
+        let y = self.register()?;
+        let n = self.nibble()?;
+
+        self.emit(Instruction::Draw { x, y, n })
+      },
+      "bcd"         => { let x = self.register()?; self.emit(Instruction::Bcd { x }) },
+      "saveflags"   => { let x = self.register()?; self.emit(Instruction::SaveFlags { x }) },
+      "loadflags"   => { let x = self.register()?; self.emit(Instruction::LoadFlags { x }) },
+      "save" | "load" =>
+      {
+        let x = self.register()?;
+
+        let instruction = match (text.as_str(), self.peek() == Some("-"))
+        {
+          ("save", true)  => { self.next()?; Instruction::SaveRange { x, y:self.register()? } },
+          ("load", true)  => { self.next()?; Instruction::LoadRange { x, y:self.register()? } },
+          ("save", false) => Instruction::Store { x },
+          _               => Instruction::Load { x },
+        };
+
+        self.emit(instruction)
+      },
+      "delay" | "buzzer" | "pitch" =>
+      {
+        self.expect(":=")?;
+
+        let x = self.register()?;
+
+        match text.as_str()
+        {
+          "delay"  => self.emit(Instruction::SetDelay { x }),
+          "buzzer" => self.emit(Instruction::SetSound { x }),
+          _        => self.emit(Instruction::SetPitch { x }),
+        }
+      },
+      "i" => self.index(),
+      "if" =>
+      {
+        let tests = self.condition()?;
+
+        match self.next()?.as_str()
+        {
+          "then" =>
+          {
+            for instruction in tests
+            {
+              self.emit(instruction)?;
+            }
+
+            Ok(())
+          },
+          "begin" =>
+          {
+            let jump = self.branch_unless(tests)?;
+
+            self.flow.push(Flow::Begin { jump, line:self.stmt_line });
+
+            Ok(())
+          },
+          other => self.error(format!("expected 'then' or 'begin', found '{}'", other)),
+        }
+      },
+      "else" => match self.flow.pop()
+      {
+        Some(Flow::Begin { jump, line }) =>
+        {
+          let skip = self.here;
+
+          self.emit(Instruction::Jump { nnn:0 })?;
+          self.patch_jump(jump, self.here)?;
+          self.flow.push(Flow::Else { jump:skip, line });
+
+          Ok(())
+        },
+        _ => self.error("'else' without 'if ... begin'".to_string()),
+      },
+      "end" => match self.flow.pop()
+      {
+        Some(Flow::Begin { jump, .. }) | Some(Flow::Else { jump, .. }) => self.patch_jump(jump, self.here),
+        _ => self.error("'end' without 'if ... begin'".to_string()),
+      },
+      "loop" =>
+      {
+        self.flow.push(Flow::Loop { start:self.here, breaks:Vec::new(), line:self.stmt_line });
+
+        Ok(())
+      },
+      "while" =>
+      {
+        if !self.flow.iter().any(|flow| matches!(flow, Flow::Loop { .. }))
+        {
+          return self.error("'while' outside a loop".to_string());
+        }
+
+        let tests = self.condition()?;
+        let jump  = self.branch_unless(tests)?;
+
+        match self.flow.iter_mut().rev().find(|flow| matches!(flow, Flow::Loop { .. }))
+        {
+          Some(Flow::Loop { breaks, .. }) => breaks.push(jump),
+          _ => unreachable!(),
+        }
+
+        Ok(())
+      },
+      "again" => match self.flow.pop()
+      {
+        Some(Flow::Loop { start, breaks, .. }) =>
+        {
+          self.emit_jump(start)?;
+
+          for jump in breaks
+          {
+            self.patch_jump(jump, self.here)?;
+          }
+
+          Ok(())
+        },
+        _ => self.error("'again' without 'loop'".to_string()),
+      },
+      _ => self.word(text),
+    }
+  }
+
+  // A statement that doesn't start with a keyword.
+  fn word(&mut self, text:String) -> Result<(), AsmError>
+  {
+    if let Some(x) = self.register_of(&text)
+    {
+      return self.assignment(x);
+    }
+
+    if self.macros.contains_key(&text)
+    {
+      return self.expand(&text);
+    }
+
+    if text.starts_with(':')
+    {
+      return self.error(format!("unsupported directive '{}'", text));
+    }
+
+    // Numbers on their own are data, such as sprites.
+    if literal(&text).is_some() || text == "{"
+    {
+      self.pos -= 1;
+
+      let byte = self.byte()?;
+
+      return self.put(byte);
+    }
+
+    // Anything else calls a subroutine.
+    self.emit_nnn(text, |nnn| Instruction::Call { nnn })
+  }
+
+  fn finish(mut self) -> Result<Assembly, AsmError>
+  {
+    match self.flow.last()
+    {
+      Some(Flow::Begin { line, .. }) | Some(Flow::Else { line, .. })
+          => return Err(AsmError { line:*line, message:"'if ... begin' without 'end'".to_string() }),
+      Some(Flow::Loop { line, .. })
+          => return Err(AsmError { line:*line, message:"'loop' without 'again'".to_string() }),
+      None => {},
+    }
+
+    for fixup in std::mem::take(&mut self.fixups)
+    {
+      let value = self.consts.get(&fixup.name).cloned()
+                      .or_else(|| self.labels.get(&fixup.name).map(|&addr| addr as i64));
+
+      let max = match fixup.patch { Patch::Nnn => 0xFFF, Patch::Long => 0xFFFF };
+
+      let value = match value
+      {
+        Some(value) if (0..=max).contains(&value) => value as u16,
+        Some(value) => return Err(AsmError { line:fixup.line, message:format!("address {:#X} of '{}' is out of range", value, fixup.name) }),
+        None        => return Err(AsmError { line:fixup.line, message:format!("unknown name '{}'", fixup.name) }),
+      };
+
+      let offset = fixup.at - PROGRAM_START;
+
+      match fixup.patch
+      {
+        Patch::Nnn  => self.rom[offset] |= (value >> 8) as u8,
+        Patch::Long => self.rom[offset] = (value >> 8) as u8,
+      }
+
+      self.rom[offset + 1] = value as u8;
+    }
+
+    self.lines.sort_by_key(|line| line.addr);
+
+    let mut symbols = SymbolMap::new();
+
+    symbols.labels      = self.labels;
+    symbols.breakpoints = self.breakpoints;
+    symbols.lines       = self.lines;
+
+    Ok(Assembly { rom:self.rom, symbols })
+  }
+}
+
+/// Assemble Octo source into a ROM. Execution starts at 0x200, so unless
+/// `: main` is defined before any code or data a jump to `main` is put
+/// there, as Octo does. `file` names the source in the symbol map's lines.
+pub fn assemble(source:&str, file:&str) -> Result<Assembly, AsmError>
+{
+  let mut asm = Assembler::new(tokenize(source), file);
+
+  while asm.pos < asm.tokens.len()
+  {
+    asm.statement()?;
+  }
+
+  if !asm.labels.contains_key("main")
+  {
+    return Err(AsmError { line:1, message:"the program has no ': main'".to_string() });
+  }
+
+  asm.finish()
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::disasm::octo;
+  use crate::instruction::decode;
+
+  fn rom(source:&str) -> Vec<u8>
+  {
+    match assemble(source, "test.8o")
+    {
+      Ok(assembly) => assembly.rom,
+      Err(err)     => panic!("{}: {}", source, err),
+    }
+  }
+
+  #[test]
+  fn main_first_needs_no_jump()
+  {
+    assert_eq!(rom(": main v0 := 1"), [0x60, 0x01]);
+    assert_eq!(rom(":alias x v3 :const five 5 : main x := five"), [0x63, 0x05]);
+    assert_eq!(rom(":macro nop { } : start : main clear"), [0x00, 0xE0]);
+  }
+
+  #[test]
+  fn main_later_is_jumped_to()
+  {
+    let assembly = assemble(": sub return : main sub", "test.8o").unwrap();
+
+    assert_eq!(assembly.rom, [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+    assert_eq!(assembly.symbols.labels["sub"], 0x202);
+    assert_eq!(assembly.symbols.labels["main"], 0x204);
+
+    assert_eq!(rom("v0 := 1 : main"), [0x12, 0x04, 0x60, 0x01]);
+  }
+
+  #[test]
+  fn if_then()
+  {
+    assert_eq!(rom(": main if v0 == 1 then v1 := 2"), [0x40, 0x01, 0x61, 0x02]);
+    assert_eq!(rom(": main if v0 key then v1 := v2"), [0xE0, 0xA1, 0x81, 0x20]);
+  }
+
+  #[test]
+  fn if_begin_else_end()
+  {
+    // SNE v0, v1 at 0x200 falls into the jump to the else branch at 0x208.
+    assert_eq!(rom(": main if v0 != v1 begin v2 := 1 else v2 := 2 end"),
+               [0x90, 0x10, 0x12, 0x08, 0x62, 0x01, 0x12, 0x0A, 0x62, 0x02]);
+  }
+
+  #[test]
+  fn loop_while_again()
+  {
+    assert_eq!(rom(": main loop v0 += 1 while v0 != 5 again"), [0x70, 0x01, 0x40, 0x05, 0x12, 0x08, 0x12, 0x00]);
+  }
+
+  #[test]
+  fn comparisons_use_vf()
+  {
+    assert_eq!(rom(": main if v1 < 3 then v2 := 0"), [0x6F, 0x03, 0x8F, 0x17, 0x3F, 0x01, 0x62, 0x00]);
+  }
+
+  #[test]
+  fn macros_expand_their_arguments()
+  {
+    assert_eq!(rom(":macro swap A B { vf := A A := B B := vf } : main swap v1 v2"),
+               [0x8F, 0x10, 0x81, 0x20, 0x82, 0xF0]);
+  }
+
+  #[test]
+  fn calc_groups_right_to_left()
+  {
+    assert_eq!(rom(":calc n { 2 * 3 + 1 } : main v0 := n"), [0x60, 0x08]);
+    assert_eq!(rom(":calc n { ( 2 * 3 ) + 1 } : main v0 := n"), [0x60, 0x07]);
+    assert_eq!(rom(": main clear :calc at { HERE } i := at"), [0x00, 0xE0, 0xA2, 0x02]);
+  }
+
+  #[test]
+  fn long_index()
+  {
+    assert_eq!(rom(": main i := long 0x1234"), [0xF0, 0x00, 0x12, 0x34]);
+    assert_eq!(rom(": main i := long data : data 0x12"), [0xF0, 0x00, 0x02, 0x04, 0x12]);
+  }
+
+  #[test]
+  fn disassembly_reassembles()
+  {
+    for val in 0..=0xFFFF
+    {
+      let text  = octo(decode(val), 0);
+      let bytes = rom(&format!(": main {}", text));
+
+      assert_eq!(bytes[..2], val.to_be_bytes(), "{:#06X} disassembled to '{}'", val, text);
+    }
+  }
+}
@@ -0,0 +1,73 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chip8::cli::{fail, option_value};
+use chip8::assemble;
+
+const USAGE:&str = "usage: chip8-asm [-o <rom>] [--symbols <file>] <source>
+
+Assembles Octo source into a ROM, <source> with the extension .ch8 unless
+-o says otherwise, and writes a symbol map of its labels, breakpoints and
+source lines for the debuggers, by default the ROM's name with .sym.json.";
+
+// The source path as the symbol map records it. Debuggers look it up
+// relative to the symbol map, so it's just the file name when the two sit
+// side by side.
+fn source_name(source:&Path, symbols:&Path) -> String
+{
+  let dir = |path:&Path| path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+  match source.file_name()
+  {
+    Some(name) if dir(source) == dir(symbols) => name.to_string_lossy().into_owned(),
+    _ => fs::canonicalize(source).unwrap_or_else(|_| source.to_path_buf()).to_string_lossy().into_owned(),
+  }
+}
+
+fn main()
+{
+  let args:Vec<String> = env::args().skip(1).collect();
+
+  let mut rom_path     = None;
+  let mut symbols_path = None;
+  let mut source_path  = None;
+
+  let mut idx = 0;
+
+  while idx < args.len()
+  {
+    match args[idx].as_str()
+    {
+      "-o"        => rom_path     = Some(option_value(&args, &mut idx, USAGE)),
+      "--symbols" => symbols_path = Some(option_value(&args, &mut idx, USAGE)),
+      "-h" | "--help" =>
+      {
+        println!("{}", USAGE);
+        process::exit(0);
+      },
+      source => source_path = Some(PathBuf::from(source)),
+    }
+
+    idx += 1;
+  }
+
+  let source_path  = source_path.unwrap_or_else(|| fail(USAGE.to_string()));
+  let rom_path     = rom_path.unwrap_or_else(|| source_path.with_extension("ch8"));
+  let symbols_path = symbols_path.unwrap_or_else(|| rom_path.with_extension("sym.json"));
+
+  let source = fs::read_to_string(&source_path)
+                 .unwrap_or_else(|err| fail(format!("Can't read {}: {}", source_path.display(), err)));
+
+  let assembly = assemble(&source, &source_name(&source_path, &symbols_path))
+                   .unwrap_or_else(|err| fail(format!("{}: {}", source_path.display(), err)));
+
+  fs::write(&rom_path, &assembly.rom)
+    .unwrap_or_else(|err| fail(format!("Can't write {}: {}", rom_path.display(), err)));
+
+  assembly.symbols.save(&symbols_path)
+    .unwrap_or_else(|err| fail(format!("Can't write {}: {}", symbols_path.display(), err)));
+
+  println!("{}: {} bytes, {} labels", rom_path.display(), assembly.rom.len(), assembly.symbols.labels.len());
+}
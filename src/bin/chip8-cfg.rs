@@ -0,0 +1,94 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chip8::cli::{fail, option_value};
+use chip8::{Analysis, ByteKind, Platform, Terminator};
+
+const USAGE:&str = "usage: chip8-cfg [--platform vip|chip48|schip-legacy|schip-modern|xochip]
+                 [--dot cfg|calls] <rom>
+
+Follows the control flow of the ROM from 0x200 and reports its subroutines,
+which bytes are code and which likely data, and the jumps it couldn't
+follow. With --dot, prints the control-flow or call graph for Graphviz.";
+
+fn report(analysis:&Analysis)
+{
+  println!("Blocks:     {}", analysis.blocks.len());
+  println!("Subroutines:");
+
+  for function in analysis.functions.values()
+  {
+    let calls:Vec<String> = function.calls.iter().map(|callee| format!("{:#05X}", callee)).collect();
+
+    println!("  {:#05X}  {} blocks, calls [{}]", function.entry, function.blocks.len(), calls.join(", "));
+  }
+
+  println!("Bytes:");
+
+  for (start, end, kind) in analysis.ranges()
+  {
+    let kind = match kind
+    {
+      ByteKind::Code    => "code",
+      ByteKind::Data    => "data",
+      ByteKind::Unknown => "unknown",
+    };
+
+    println!("  {:#05X}-{:#05X}  {}", start, end - 1, kind);
+  }
+
+  for block in analysis.blocks.values()
+  {
+    if let (Terminator::Computed { .. }, Some(&(addr, instruction))) = (block.terminator, block.instructions.last())
+    {
+      println!("Unresolved: {:#05X}  {}", addr, analysis.mnemonic(addr, instruction));
+    }
+  }
+
+  for addr in analysis.invalid.iter()
+  {
+    println!("Invalid:    {:#05X}  an undefined opcode or the end of the ROM", addr);
+  }
+}
+
+fn main()
+{
+  let args:Vec<String> = env::args().skip(1).collect();
+
+  let mut platform = Platform::CosmacVip;
+  let mut dot      = None;
+  let mut rom_path = None;
+
+  let mut idx = 0;
+
+  while idx < args.len()
+  {
+    match args[idx].as_str()
+    {
+      "--platform" => platform = option_value(&args, &mut idx, USAGE),
+      "--dot"      => dot      = Some(option_value::<String>(&args, &mut idx, USAGE)),
+      "-h" | "--help" =>
+      {
+        println!("{}", USAGE);
+        process::exit(0);
+      },
+      rom => rom_path = Some(rom.to_string()),
+    }
+
+    idx += 1;
+  }
+
+  let rom_path = rom_path.unwrap_or_else(|| fail(USAGE.to_string()));
+  let rom      = fs::read(&rom_path).unwrap_or_else(|err| fail(format!("Can't read {}: {}", rom_path, err)));
+
+  let analysis = Analysis::new(&rom, platform).unwrap_or_else(|err| fail(err.to_string()));
+
+  match dot.as_deref()
+  {
+    None          => report(&analysis),
+    Some("cfg")   => print!("{}", analysis.to_dot()),
+    Some("calls") => print!("{}", analysis.call_graph_dot()),
+    Some(other)   => fail(format!("unknown graph '{}', use cfg or calls", other)),
+  }
+}
@@ -0,0 +1,48 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chip8::cli::{fail, option_value};
+use chip8::{decompile, Platform, Style};
+
+const USAGE:&str = "usage: chip8-decompile [--style c|octo]
+                       [--platform vip|chip48|schip-legacy|schip-modern|xochip] <rom>
+
+Lifts the ROM's subroutines into structured pseudo-code with if, loops and
+named registers, spelling out idioms such as printing a number in decimal
+or waiting for a key. Code only reached through BNNN is left out.";
+
+fn main()
+{
+  let args:Vec<String> = env::args().skip(1).collect();
+
+  let mut style    = Style::C;
+  let mut platform = Platform::CosmacVip;
+  let mut rom_path = None;
+
+  let mut idx = 0;
+
+  while idx < args.len()
+  {
+    match args[idx].as_str()
+    {
+      "--style"    => style    = option_value(&args, &mut idx, USAGE),
+      "--platform" => platform = option_value(&args, &mut idx, USAGE),
+      "-h" | "--help" =>
+      {
+        println!("{}", USAGE);
+        process::exit(0);
+      },
+      rom => rom_path = Some(rom.to_string()),
+    }
+
+    idx += 1;
+  }
+
+  let rom_path = rom_path.unwrap_or_else(|| fail(USAGE.to_string()));
+  let rom      = fs::read(&rom_path).unwrap_or_else(|err| fail(format!("Can't read {}: {}", rom_path, err)));
+
+  let source   = decompile(&rom, platform, style).unwrap_or_else(|err| fail(err.to_string()));
+
+  print!("{}", source);
+}
@@ -0,0 +1,46 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chip8::cli::{fail, option_value};
+use chip8::{Disassembler, Platform, Syntax};
+
+const USAGE:&str = "usage: chip8-disasm [--syntax octo|cowgod]
+                    [--platform vip|chip48|schip-legacy|schip-modern|xochip] <rom>
+
+Prints a listing of the ROM: address, raw bytes, opcode pattern, mnemonic
+and what the instruction does. Opcodes the platform doesn't have are
+listed as data.";
+
+fn main()
+{
+  let args:Vec<String> = env::args().skip(1).collect();
+
+  let mut syntax   = Syntax::Cowgod;
+  let mut platform = Platform::CosmacVip;
+  let mut rom_path = None;
+
+  let mut idx = 0;
+
+  while idx < args.len()
+  {
+    match args[idx].as_str()
+    {
+      "--syntax"   => syntax   = option_value(&args, &mut idx, USAGE),
+      "--platform" => platform = option_value(&args, &mut idx, USAGE),
+      "-h" | "--help" =>
+      {
+        println!("{}", USAGE);
+        process::exit(0);
+      },
+      rom => rom_path = Some(rom.to_string()),
+    }
+
+    idx += 1;
+  }
+
+  let rom_path = rom_path.unwrap_or_else(|| fail(USAGE.to_string()));
+  let rom      = fs::read(&rom_path).unwrap_or_else(|err| fail(format!("Can't read {}: {}", rom_path, err)));
+
+  print!("{}", Disassembler::new(platform, syntax).listing(&rom));
+}
@@ -0,0 +1,60 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chip8::cli::{fail, option_value};
+use chip8::{lint, Platform};
+
+const USAGE:&str = "usage: chip8-lint [--platform vip|chip48|schip-legacy|schip-modern|xochip] <rom>...
+
+Checks the code reachable from 0x200 for undefined or unsupported opcodes,
+bad jump targets, unmatched returns, calls nested past the stack and
+sprites read past the end of memory. Exits with 1 if anything was found.";
+
+fn main()
+{
+  let args:Vec<String> = env::args().skip(1).collect();
+
+  let mut platform  = Platform::CosmacVip;
+  let mut rom_paths = Vec::new();
+
+  let mut idx = 0;
+
+  while idx < args.len()
+  {
+    match args[idx].as_str()
+    {
+      "--platform" => platform = option_value(&args, &mut idx, USAGE),
+      "-h" | "--help" =>
+      {
+        println!("{}", USAGE);
+        process::exit(0);
+      },
+      rom => rom_paths.push(rom.to_string()),
+    }
+
+    idx += 1;
+  }
+
+  if rom_paths.is_empty()
+  {
+    fail(USAGE.to_string());
+  }
+
+  let mut found = false;
+
+  for rom_path in rom_paths.iter()
+  {
+    let rom = fs::read(rom_path).unwrap_or_else(|err| fail(format!("Can't read {}: {}", rom_path, err)));
+
+    let lints = lint(&rom, platform).unwrap_or_else(|err| fail(format!("{}: {}", rom_path, err)));
+
+    for lint in lints
+    {
+      println!("{}: {}", rom_path, lint);
+      found = true;
+    }
+  }
+
+  process::exit(if found { 1 } else { 0 });
+}
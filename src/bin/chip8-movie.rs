@@ -0,0 +1,119 @@
+use std::env;
+use std::fs;
+
+use chip8::cli::fail;
+use chip8::{rom_hash, Chip8, Movie, MovieSession, Scheduler, Trace};
+
+const USAGE:&str = "usage: chip8-movie info <movie>
+       chip8-movie convert <movie> <out>
+       chip8-movie truncate <movie> <frames> [out]
+       chip8-movie append <movie> <more> [out]
+       chip8-movie play <movie> <rom> [--trace <file>]
+
+Movies ending in .json are written as JSON, anything else in the binary
+form. Without [out], truncate and append rewrite <movie> in place. With
+--trace, play logs every instruction it executes to <file>.";
+
+fn load(path:&str) -> Movie
+{
+  Movie::load(path).unwrap_or_else(|err| fail(format!("Can't load {}: {}", path, err)))
+}
+
+fn save(movie:&Movie, path:&str)
+{
+  movie.save(path).unwrap_or_else(|err| fail(format!("Can't save {}: {}", path, err)));
+}
+
+fn info(movie:&Movie)
+{
+  println!("ROM hash: {:016x}", movie.rom_hash);
+  println!("Platform: {}", movie.platform);
+  println!("Speed:    {:?}", movie.speed);
+  println!("Seed:     {}", movie.seed);
+  println!("Frames:   {}", movie.len());
+  println!("Quirks:   {:?}", movie.quirks);
+}
+
+// Replay the movie headlessly and print a hash of the final machine
+// state, so two runs (or two builds) can be compared.
+fn play(movie:Movie, rom_path:&str, trace_path:Option<&str>)
+{
+  let rom = fs::read(rom_path).unwrap_or_else(|err| fail(format!("Can't read {}: {}", rom_path, err)));
+
+  movie.check_rom(&rom).unwrap_or_else(|err| fail(err.to_string()));
+
+  let mut chip8 = Chip8::with_platform(movie.platform);
+
+  chip8.set_quirks(movie.quirks);
+  chip8.reseed(movie.seed);
+  chip8.initialize();
+  chip8.load_rom(&rom).unwrap_or_else(|err| fail(err.to_string()));
+
+  if let Some(path) = trace_path
+  {
+    chip8.set_trace(Trace::create(path).unwrap_or_else(|err| fail(format!("Can't create trace {}: {}", path, err))));
+  }
+
+  let mut scheduler = Scheduler::new(movie.speed);
+  let mut session   = MovieSession::play(movie);
+
+  while let Some(keypad) = session.next_frame(0x0)
+  {
+    chip8.set_keypad(keypad);
+
+    if let Err(err) = scheduler.run_frame(&mut chip8)
+    {
+      println!("Stopped at frame {}: {}", session.frame() - 1, err);
+      break;
+    }
+  }
+
+  println!("Frames:     {}", scheduler.frames());
+  println!("State hash: {:016x}", rom_hash(chip8.save_state().to_json().as_bytes()));
+
+  if let (Some(trace), Some(path)) = (chip8.take_trace(), trace_path)
+  {
+    let lines = trace.finish().unwrap_or_else(|err| fail(format!("Can't write trace {}: {}", path, err)));
+
+    println!("Traced:     {} instructions", lines);
+  }
+}
+
+fn main()
+{
+  let args:Vec<String> = env::args().skip(1).collect();
+  let args:Vec<&str>   = args.iter().map(|arg| arg.as_str()).collect();
+
+  match args.as_slice()
+  {
+    ["info", path] => info(&load(path)),
+
+    ["convert", path, out] => save(&load(path), out),
+
+    ["truncate", path, frames, rest @ ..] if rest.len() <= 1 =>
+    {
+      let frames = frames.parse().unwrap_or_else(|_| fail(format!("invalid frame count '{}'", frames)));
+
+      let mut movie = load(path);
+
+      movie.truncate(frames);
+
+      save(&movie, rest.first().unwrap_or(path));
+    },
+
+    ["append", path, more, rest @ ..] if rest.len() <= 1 =>
+    {
+      let mut movie = load(path);
+
+      movie.append(&load(more)).unwrap_or_else(|err| fail(err.to_string()));
+
+      save(&movie, rest.first().unwrap_or(path));
+    },
+
+    ["play", path, rom] => play(load(path), rom, None),
+
+    ["play", path, rom, "--trace", trace] => play(load(path), rom, Some(trace)),
+
+    _ => fail(USAGE.to_string()),
+  }
+}
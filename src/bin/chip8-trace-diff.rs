@@ -0,0 +1,148 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chip8::cli::{fail, option_value};
+use chip8::{field_width, TraceLine};
+
+const USAGE:&str = "usage: chip8-trace-diff [--context <n>] [--ignore <key>,..] <ours> <theirs>
+
+Compares two instruction traces, as written by chip8 --trace, and reports
+the first instruction after which the machines differ, with both states
+side by side. Fields only one trace has are skipped, so a trace from
+another emulator only needs the KEY=VALUE fields it can give, e.g.
+PC=0200 OP=6A02 VA=02 I=0000. --ignore skips fields such as DT,ST whose
+timing differs between emulators. Blank lines and lines starting with #
+are skipped. Exits with 1 if the traces diverge.";
+
+// The trace's instructions, each with its line number.
+fn load(path:&str, ignore:&[String]) -> Vec<(usize, TraceLine)>
+{
+  let text = fs::read_to_string(path).unwrap_or_else(|err| fail(format!("Can't read {}: {}", path, err)));
+
+  text.lines().enumerate()
+              .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+              .map(|(idx, line)|
+              {
+                let mut parsed = line.parse::<TraceLine>().unwrap_or_else(|err| fail(format!("{}:{}: {}", path, idx + 1, err)));
+
+                parsed.fields.retain(|(key, _)| !ignore.contains(key));
+
+                (idx + 1, parsed)
+              })
+              .collect()
+}
+
+fn value(line:&TraceLine, key:&str) -> String
+{
+  line.get(key).map_or("-".to_string(), |value| format!("{:0width$X}", value, width = field_width(key)))
+}
+
+fn report(ours:&[(usize, TraceLine)], theirs:&[(usize, TraceLine)], at:usize, context:usize)
+{
+  let (our_line, our_state)     = &ours[at];
+  let (their_line, their_state) = &theirs[at];
+
+  println!("Traces diverge at instruction {} (line {} of ours, line {} of theirs)", at + 1, our_line, their_line);
+
+  if at > 0 && context > 0
+  {
+    println!();
+    println!("Before it, both:");
+
+    for (_, state) in ours[at.saturating_sub(context)..at].iter()
+    {
+      println!("  {}", state);
+    }
+  }
+
+  println!();
+  println!("        ours        theirs");
+
+  // Their extra fields come after ours.
+  let keys    = our_state.fields.iter().chain(their_state.fields.iter()).map(|(key, _)| key.as_str());
+  let differs = our_state.differences(their_state);
+
+  let mut shown = Vec::new();
+
+  for key in keys
+  {
+    if shown.contains(&key)
+    {
+      continue;
+    }
+
+    shown.push(key);
+
+    let marker = if differs.iter().any(|differing| differing == key) { "  <--" } else { "" };
+
+    println!("  {:<6}{:<12}{}{}", key, value(our_state, key), value(their_state, key), marker);
+  }
+
+  println!();
+
+  // Not every emulator names the instruction.
+  for (side, mnemonic) in [("ours:  ", &our_state.mnemonic), ("theirs:", &their_state.mnemonic)]
+  {
+    if !mnemonic.is_empty()
+    {
+      println!("  {} {}", side, mnemonic);
+    }
+  }
+}
+
+fn main()
+{
+  let args:Vec<String> = env::args().skip(1).collect();
+
+  let mut context = 3;
+  let mut ignore  = Vec::new();
+  let mut paths   = Vec::new();
+
+  let mut idx = 0;
+
+  while idx < args.len()
+  {
+    match args[idx].as_str()
+    {
+      "--context" => context = option_value(&args, &mut idx, USAGE),
+      "--ignore"  =>
+      {
+        let keys:String = option_value(&args, &mut idx, USAGE);
+
+        ignore.extend(keys.split(',').map(|key| key.trim().to_uppercase()));
+      },
+      "-h" | "--help" =>
+      {
+        println!("{}", USAGE);
+        process::exit(0);
+      },
+      path => paths.push(path.to_string()),
+    }
+
+    idx += 1;
+  }
+
+  let (ours, theirs) = match paths.as_slice()
+  {
+    [ours, theirs] => (load(ours, &ignore), load(theirs, &ignore)),
+    _ => fail(USAGE.to_string()),
+  };
+
+  let common = ours.len().min(theirs.len());
+
+  if let Some(at) = (0..common).find(|&at| !ours[at].1.differences(&theirs[at].1).is_empty())
+  {
+    report(&ours, &theirs, at, context);
+    process::exit(1);
+  }
+
+  println!("Traces agree for {} instruction{}", common, if common == 1 { "" } else { "s" });
+
+  if ours.len() != theirs.len()
+  {
+    let (longer, extra) = if ours.len() > theirs.len() { ("Ours", ours.len() - common) } else { ("Theirs", theirs.len() - common) };
+
+    println!("{} goes on for {} more", longer, extra);
+  }
+}
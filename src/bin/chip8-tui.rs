@@ -0,0 +1,521 @@
+#![allow(non_snake_case)]
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chip8::cli::{fail, option_value};
+use chip8::{Chip8, Debugger, Platform, RunOutcome, Scheduler, Speed, DEBUGGER_HELP, DEFAULT_JOURNAL_BUDGET, NUM_KEYS};
+
+const USAGE:&str = "usage: chip8-tui [--platform vip|chip48|schip-legacy|schip-modern|xochip]
+                 [--speed <n>ips|<n>ipf] [--seed <n>] <rom>
+
+A full-screen debugger for terminals. Commands are those of chip8 --debug,
+plus press/release <key> for the keypad. An empty line steps, quit exits.";
+
+const TUI_HELP:&str = "\
+press <key>         hold down hex key 0-F
+release <key>       let go of hex key 0-F
+quit                exit
+An empty line steps one instruction.";
+
+// ANSI escapes.
+const CLEAR:&str    = "\x1b[H\x1b[2J";
+const REVERSE:&str  = "\x1b[7m";
+const BOLD:&str     = "\x1b[1m";
+const RESET:&str    = "\x1b[0m";
+
+// Columns of the screen pane; a high resolution screen is scaled down.
+const SCREEN_COLUMNS:usize      = 64;
+
+// Rows of the memory pane, 16 bytes each.
+const MEMORY_ROWS:usize         = 8;
+
+// Instructions shown before and after PC.
+const DISASM_CONTEXT:usize      = 7;
+
+// Lines of command output kept below the panes.
+const MESSAGE_LINES:usize       = 6;
+
+// How often the panes are redrawn while running.
+const REDRAW_INTERVAL:Duration  = Duration::from_millis(100);
+
+// Hex keys in the layout of the COSMAC VIP keypad.
+const KEYPAD_LAYOUT:[[u8;4];4]  = [[0x1, 0x2, 0x3, 0xC],
+                                   [0x4, 0x5, 0x6, 0xD],
+                                   [0x7, 0x8, 0x9, 0xE],
+                                   [0xA, 0x0, 0xB, 0xF]];
+
+// Length of `line` on the terminal, not counting escapes.
+fn visible_len(line:&str) -> usize
+{
+  let mut len       = 0;
+  let mut in_escape = false;
+
+  for ch in line.chars()
+  {
+    match ch
+    {
+      '\x1b'                => in_escape = true,
+      'm' if in_escape      => in_escape = false,
+      _ if in_escape        => {},
+      _                     => len += 1,
+    }
+  }
+
+  len
+}
+
+// A titled box of lines.
+struct Pane
+{
+  title:String,
+  lines:Vec<String>,
+}
+
+impl Pane
+{
+  fn new(title:&str) -> Self
+  {
+    Pane { title:title.to_string(), lines:Vec::new() }
+  }
+
+  fn width(&self) -> usize
+  {
+    self.lines.iter().map(|line| visible_len(line)).chain(Some(self.title.len())).max().unwrap_or(0)
+  }
+
+  // The pane as lines of exactly `width` + 4 columns, framed.
+  fn framed(&self, width:usize) -> Vec<String>
+  {
+    let mut out = vec![format!("┌─{}{}─┐", self.title, "─".repeat(width - self.title.len()))];
+
+    for line in self.lines.iter()
+    {
+      out.push(format!("│ {}{} │", line, " ".repeat(width - visible_len(line))));
+    }
+
+    out.push(format!("└{}┘", "─".repeat(width + 2)));
+
+    out
+  }
+}
+
+// Panes stacked on top of each other, all as wide as the widest.
+fn column(panes:&[Pane]) -> Vec<String>
+{
+  let width = panes.iter().map(Pane::width).max().unwrap_or(0);
+
+  panes.iter().flat_map(|pane| pane.framed(width)).collect()
+}
+
+// Columns side by side.
+fn side_by_side(left:Vec<String>, right:Vec<String>) -> Vec<String>
+{
+  let width = left.iter().map(|line| visible_len(line)).max().unwrap_or(0);
+  let rows  = left.len().max(right.len());
+
+  (0..rows).map(|row|
+  {
+    let left = left.get(row).map(String::as_str).unwrap_or("");
+
+    format!("{}{} {}", left, " ".repeat(width - visible_len(left)), right.get(row).map(String::as_str).unwrap_or(""))
+  })
+  .collect()
+}
+
+// The display in half blocks, two pixel rows per character row.
+fn screen_pane(chip8:&Chip8) -> Pane
+{
+  let graphics = chip8.graphics();
+  let scale    = graphics.width() / SCREEN_COLUMNS;
+
+  // A scaled down pixel is lit if any pixel it covers is.
+  let lit = |x:usize, y:usize| (0..scale).any(|dy| (0..scale).any(|dx| graphics.pixel(x*scale + dx, y*scale + dy)));
+
+  let mut pane = Pane::new(if graphics.hires() { "Screen (hires, scaled)" } else { "Screen" });
+
+  for y in (0..graphics.height()/scale).step_by(2)
+  {
+    pane.lines.push((0..SCREEN_COLUMNS).map(|x| match (lit(x, y), lit(x, y + 1))
+    {
+      (true, true)   => '█',
+      (true, false)  => '▀',
+      (false, true)  => '▄',
+      (false, false) => ' ',
+    })
+    .collect());
+  }
+
+  pane
+}
+
+// Memory around I, with the byte at I highlighted.
+fn memory_pane(chip8:&Chip8) -> Pane
+{
+  let memory  = &chip8.memory().memory;
+  let I       = chip8.regs().I as usize;
+
+  let last    = memory.len().saturating_sub(16*MEMORY_ROWS);
+  let start   = (I & !0xF).saturating_sub(16*(MEMORY_ROWS/2 - 1)).min(last);
+
+  let mut pane = Pane::new("Memory");
+
+  for row in (start..memory.len()).step_by(16).take(MEMORY_ROWS)
+  {
+    let mut line = format!("{:04X} ", row);
+
+    for (addr, byte) in memory[row..row + 16].iter().enumerate().map(|(idx, byte)| (row + idx, byte))
+    {
+      if addr == I
+      {
+        let _ = write!(line, " {}{:02X}{}", REVERSE, byte, RESET);
+      }
+      else
+      {
+        let _ = write!(line, " {:02X}", byte);
+      }
+    }
+
+    pane.lines.push(line);
+  }
+
+  pane
+}
+
+fn registers_pane(chip8:&Chip8) -> Pane
+{
+  let regs = chip8.regs();
+
+  let mut pane = Pane::new("Registers");
+
+  for (row, values) in regs.V.chunks(4).enumerate()
+  {
+    pane.lines.push(values.iter().enumerate()
+                          .map(|(idx, value)| format!("V{:X}={:02X}", 4*row + idx, value))
+                          .collect::<Vec<_>>().join(" "));
+  }
+
+  pane.lines.push(format!("PC={:04X} I={:04X}", regs.PC, regs.I));
+  pane.lines.push(format!("DT={:02X} ST={:02X} SP={:X}", regs.DELAY_TIMER, regs.SOUND_TIMER, chip8.stack().sp));
+
+  pane
+}
+
+// All 16 levels, the live ones with their return addresses.
+fn stack_pane(chip8:&Chip8) -> Pane
+{
+  let stack = chip8.stack();
+
+  let mut pane = Pane::new("Stack");
+
+  let levels:Vec<String> = stack.stack.iter().enumerate().map(|(level, &call)|
+  {
+    if level < stack.sp as usize
+    {
+      format!("{:X}:{:04X}", level, call.wrapping_add(2))
+    }
+    else
+    {
+      format!("{:X}:----", level)
+    }
+  })
+  .collect();
+
+  for row in levels.chunks(4)
+  {
+    pane.lines.push(row.join(" "));
+  }
+
+  pane
+}
+
+fn keypad_pane(chip8:&Chip8) -> Pane
+{
+  let keypad = chip8.keypad();
+
+  let mut pane = Pane::new("Keypad");
+
+  for row in KEYPAD_LAYOUT.iter()
+  {
+    pane.lines.push(row.iter().map(|&key|
+    {
+      if keypad & (0x1 << key) != 0 { format!("{}{:X}{}", REVERSE, key, RESET) } else { format!("{:X}", key) }
+    })
+    .collect::<Vec<_>>().join(" "));
+  }
+
+  if let Some(wait) = chip8.key_wait()
+  {
+    pane.lines.push(format!("waiting (V{:X})", wait.x));
+  }
+
+  pane
+}
+
+// Instructions around PC, with PC highlighted and breakpoints marked.
+fn disassembly_pane(chip8:&Chip8, debugger:&Debugger) -> Pane
+{
+  let PC    = chip8.regs().PC as usize;
+  let start = PC.saturating_sub(2*DISASM_CONTEXT);
+
+  let mut pane = Pane::new("Disassembly");
+  let mut addr = start;
+
+  while pane.lines.len() < 2*DISASM_CONTEXT + 1
+  {
+    let instruction = match chip8.instruction_at(addr)
+    {
+      Some(instruction) => instruction,
+      None              => break,
+    };
+
+    let marker = if debugger.breakpoints().any(|bp| bp as usize == addr) { '*' } else { ' ' };
+    let line   = format!("{}{:04X}  {:04X}  {}", marker, addr, instruction.encode(), instruction);
+
+    // Instructions before PC may be misaligned data, but PC itself lines up.
+    if addr == PC
+    {
+      pane.lines.push(format!("{}{}{}", REVERSE, line, RESET));
+    }
+    else
+    {
+      pane.lines.push(line);
+    }
+
+    addr += instruction.size() as usize;
+  }
+
+  pane
+}
+
+struct Tui
+{
+  chip8:    Chip8,
+  scheduler:Scheduler,
+  debugger: Debugger,
+  commands: Receiver<String>,
+  messages: Vec<String>, // Recent command output
+}
+
+impl Tui
+{
+  fn draw(&self)
+  {
+    let left  = column(&[screen_pane(&self.chip8), memory_pane(&self.chip8)]);
+    let right = column(&[disassembly_pane(&self.chip8, &self.debugger), registers_pane(&self.chip8),
+                         stack_pane(&self.chip8), keypad_pane(&self.chip8)]);
+
+    let mut frame = String::from(CLEAR);
+
+    for line in side_by_side(left, right)
+    {
+      frame.push_str(&line);
+      frame.push('\n');
+    }
+
+    let state = if self.debugger.paused() { "paused" } else { "running" };
+
+    let _ = writeln!(frame, "{}{}{} - frame {}", BOLD, state, RESET, self.scheduler.frames());
+
+    for message in self.messages.iter()
+    {
+      frame.push_str(message);
+      frame.push('\n');
+    }
+
+    frame.push_str("(chip8) ");
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let _ = stdout.write_all(frame.as_bytes());
+    let _ = stdout.flush();
+  }
+
+  fn message(&mut self, text:&str)
+  {
+    self.messages.extend(text.lines().map(str::to_string));
+
+    let excess = self.messages.len().saturating_sub(MESSAGE_LINES);
+
+    self.messages.drain(..excess);
+  }
+
+  // Run one command line. Returns false on quit.
+  fn execute(&mut self, line:&str) -> bool
+  {
+    let args:Vec<&str> = line.split_whitespace().collect();
+
+    let key = |arg:&str| u8::from_str_radix(arg, 16).ok().filter(|&key| (key as usize) < NUM_KEYS);
+
+    let result = match args.as_slice()
+    {
+      ["quit"] | ["q"] => return false,
+
+      [] => self.debugger.execute("step", &mut self.chip8, &mut self.scheduler),
+
+      ["help"] | ["h"] => Ok(format!("{}\n{}", DEBUGGER_HELP, TUI_HELP)),
+
+      ["press", arg] => match key(arg)
+      {
+        Some(key) => { self.chip8.press(key); Ok(String::new()) },
+        None      => Err(format!("invalid key '{}'", arg)),
+      },
+
+      ["release", arg] => match key(arg)
+      {
+        Some(key) => { self.chip8.release(key); Ok(String::new()) },
+        None      => Err(format!("invalid key '{}'", arg)),
+      },
+
+      _ => self.debugger.execute(line, &mut self.chip8, &mut self.scheduler),
+    };
+
+    match result
+    {
+      Ok(out)  => self.message(&out),
+      Err(err) => self.message(&err),
+    }
+
+    true
+  }
+
+  fn run(&mut self)
+  {
+    let mut last_update = Instant::now();
+    let mut last_draw   = Instant::now();
+
+    self.draw();
+
+    loop
+    {
+      let mut redraw = false;
+
+      loop
+      {
+        match self.commands.try_recv()
+        {
+          Ok(line) =>
+          {
+            if !self.execute(line.trim())
+            {
+              return;
+            }
+
+            redraw = true;
+          },
+          Err(TryRecvError::Empty)        => break,
+          Err(TryRecvError::Disconnected) => return,
+        }
+      }
+
+      let now = Instant::now();
+
+      for _ in 0..self.scheduler.frames_due(now.duration_since(last_update).as_secs_f64())
+      {
+        match self.debugger.run_frame(&mut self.chip8, &mut self.scheduler)
+        {
+          RunOutcome::Completed       => {},
+          RunOutcome::Paused          => break,
+          RunOutcome::Stopped(reason) =>
+          {
+            self.message(&reason);
+            redraw = true;
+            break;
+          },
+        }
+      }
+
+      last_update = now;
+
+      if self.chip8.halted()
+      {
+        self.message("Program exited");
+        self.draw();
+        println!();
+        return;
+      }
+
+      if redraw || (!self.debugger.paused() && last_draw.elapsed() >= REDRAW_INTERVAL)
+      {
+        self.draw();
+        last_draw = Instant::now();
+      }
+
+      thread::sleep(Duration::from_millis(5));
+    }
+  }
+}
+
+fn main()
+{
+  let args:Vec<String> = env::args().skip(1).collect();
+
+  let mut platform = None;
+  let mut speed    = Speed::default();
+  let mut seed     = None;
+  let mut rom_path = None;
+
+  let mut idx = 0;
+
+  while idx < args.len()
+  {
+    match args[idx].as_str()
+    {
+      "--platform" => platform = Some(option_value::<Platform>(&args, &mut idx, USAGE)),
+      "--speed"    => speed    = option_value(&args, &mut idx, USAGE),
+      "--seed"     => seed     = Some(option_value(&args, &mut idx, USAGE)),
+      "-h" | "--help" =>
+      {
+        println!("{}", USAGE);
+        process::exit(0);
+      },
+      rom => rom_path = Some(rom.to_string()),
+    }
+
+    idx += 1;
+  }
+
+  let rom_path = rom_path.unwrap_or_else(|| fail(USAGE.to_string()));
+  let rom      = fs::read(&rom_path).unwrap_or_else(|err| fail(format!("Can't read {}: {}", rom_path, err)));
+
+  let mut chip8 = platform.map_or_else(Chip8::new, Chip8::with_platform);
+
+  if let Some(seed) = seed
+  {
+    chip8.reseed(seed);
+  }
+
+  chip8.initialize();
+  chip8.load_rom(&rom).unwrap_or_else(|err| fail(format!("Can't load {}: {}", rom_path, err)));
+
+  chip8.set_journal_budget(DEFAULT_JOURNAL_BUDGET);
+
+  let (sender, commands) = mpsc::channel();
+
+  thread::spawn(move ||
+  {
+    for line in io::stdin().lock().lines().map_while(Result::ok)
+    {
+      if sender.send(line).is_err()
+      {
+        break;
+      }
+    }
+  });
+
+  let mut tui = Tui { chip8,
+                      scheduler:Scheduler::new(speed),
+                      debugger: Debugger::new(),
+                      commands,
+                      messages: vec!["Paused at the first instruction. Type help for commands.".to_string()],
+                    };
+
+  tui.run();
+}
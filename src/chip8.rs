@@ -0,0 +1,1914 @@
+use std::mem;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{Chip8Error, UndefinedOpcodePolicy};
+use crate::graphics::{Graphics, ALL_PLANES, PLANE_1, PLANE_2};
+use crate::instruction::{decode, Instruction};
+use crate::journal::{Journal, UndoEntry};
+use crate::memory::{Memory, MEMORY_SIZE, PROGRAM_START};
+use crate::opcode::OpCode;
+use crate::quirks::{Platform, Quirks};
+use crate::registers::Registers;
+use crate::rng::{RandomSource, XorShiftRng};
+use crate::savestate::{SaveState, SaveStateError, SAVE_STATE_FORMAT, SAVE_STATE_VERSION};
+use crate::stack::Stack;
+use crate::trace::{Trace, TraceLine};
+use crate::watch::{Access, WatchHit, WatchTarget, Watchpoint};
+
+/// Number of keys on the hex keypad (0x0-0xF).
+pub const NUM_KEYS:usize = 16;
+
+/// Address of the SUPER-CHIP 8x10 font, right after the 4x5 one.
+pub const BIG_FONTSET_START:usize = 0x50;
+
+// XO-CHIP pitch at power on, playing the audio pattern at 4000 Hz.
+pub(crate) const DEFAULT_PITCH:u8 = 64;
+
+const CHIP8_FONTSET:[u8;80] =
+[ 
+  0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+  0x20, 0x60, 0x20, 0x20, 0x70, // 1
+  0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+  0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+  0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+  0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+  0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+  0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+  0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+  0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+  0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+  0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+  0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+  0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+  0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+  0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+];
+
+const SUPERCHIP_FONTSET:[u8;160] =
+[
+  0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+  0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+  0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+  0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+  0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+  0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+  0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+  0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+  0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+  0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+  0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+  0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+  0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
+/// A pending `_FX0A`. While set the CPU is suspended; timers and the
+/// display keep running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyWait
+{
+  /// The register that receives the key.
+  pub x:u8,
+  /// With the `key_wait_on_release` quirk, the key that was pressed and
+  /// whose release will complete the wait.
+  pub pressed:Option<u8>,
+}
+
+/// A complete CHIP-8 machine: CPU registers, memory, stack, framebuffer
+/// and keypad. It has no notion of windows or wall-clock time; a frontend
+/// feeds it keys, runs it with a `Scheduler` (or calls `emulate_cycle` and
+/// `tick_timers` itself) and reads back the framebuffer.
+///
+/// ```no_run
+/// let rom = std::fs::read("pong.rom").unwrap();
+/// let mut chip8     = chip8::Chip8::new();
+/// let mut scheduler = chip8::Scheduler::default();
+///
+/// chip8.initialize();
+/// chip8.load_rom(&rom).unwrap();
+///
+/// loop
+/// {
+///   scheduler.run_frame(&mut chip8).unwrap();
+///
+///   if chip8.draw_flag()
+///   {
+///     // present chip8.framebuffer() ...
+///     chip8.clear_draw_flag();
+///   }
+/// }
+/// ```
+pub struct Chip8
+{
+  draw_flag:    bool,
+  regs:         Registers,
+  memory:       Memory,
+  stack:        Stack,
+  curr_opcode:  OpCode,
+  graphics:     Graphics,
+  key:          [u8;NUM_KEYS], // HEX based 0x0-0xF
+  platform:     Platform,
+  quirks:       Quirks,
+  wait_vblank:  bool, // Set by _DXYN with the display_wait quirk
+  halted:       bool, // Set by the SUPER-CHIP _00FD exit instruction
+  rpl:          [u8;16], // SUPER-CHIP RPL user flags, kept across resets
+  planes:       u8, // XO-CHIP bitplanes selected by _FN01
+  audio_pattern:[u8;16], // XO-CHIP 1-bit audio samples loaded by _F002
+  pitch:        u8, // XO-CHIP playback rate set by _FX3A
+  on_undefined: UndefinedOpcodePolicy,
+  skipped:      Vec<Chip8Error>, // Undefined opcodes logged and skipped, until taken
+  decode_cache: Vec<Option<Instruction>>, // Decoded instruction per address
+  key_wait:     Option<KeyWait>, // Set while _FX0A waits for a key
+  rng:          Box<dyn RandomSource>, // Source of _CXNN
+  watchpoints:  Vec<Watchpoint>,
+  watch_hits:   Vec<WatchHit>, // Watchpoints hit by the last instruction
+  journal:      Journal, // Undo entries of the last instructions run
+  undo:         Option<UndoEntry>, // Being recorded by the current instruction
+  trace:        Option<Trace>, // Logs every instruction executed
+}
+
+impl Chip8
+{
+  /// Create a machine with the COSMAC VIP instruction set and the
+  /// interpreter's original, `Quirks::legacy`, behavior.
+  pub fn new() -> Self
+  {
+    Chip8::with_quirks(Quirks::default())
+  }
+
+  /// Create a machine emulating `platform`, with that platform's
+  /// instruction set and preset quirks.
+  pub fn with_platform(platform:Platform) -> Self
+  {
+    let mut chip8 = Chip8::with_quirks(Quirks::preset(platform));
+
+    chip8.platform = platform;
+
+    chip8.memory   = Memory::with_size(platform.memory_size());
+
+    chip8.flush_decode_cache();
+
+    chip8
+  }
+
+  /// Create a machine that interprets the ambiguous instructions
+  /// according to `quirks`.
+  pub fn with_quirks(quirks:Quirks) -> Self
+  {
+    Chip8 { draw_flag:false,
+            regs:Registers::new(),
+            memory:Memory::new(),
+            stack:Stack::new(),
+            curr_opcode:OpCode::new(0x0),
+            graphics:Graphics::new(),
+            key:[0x0;NUM_KEYS],
+            platform:Platform::CosmacVip,
+            quirks,
+            wait_vblank:false,
+            halted:false,
+            rpl:[0x0;16],
+            planes:PLANE_1,
+            audio_pattern:[0x0;16],
+            pitch:DEFAULT_PITCH,
+            on_undefined:UndefinedOpcodePolicy::default(),
+            skipped:Vec::new(),
+            decode_cache:vec![None;MEMORY_SIZE],
+            key_wait:None,
+            rng:Box::new(XorShiftRng::from_entropy()),
+            watchpoints:Vec::new(),
+            watch_hits:Vec::new(),
+            journal:Journal::new(0),
+            undo:None,
+            trace:None,
+          }
+  }
+
+  /// Create a machine whose `_CXNN` sequence is fixed by `seed`, so runs
+  /// are reproducible.
+  pub fn with_seed(seed:u64) -> Self
+  {
+    Chip8::with_rng(Box::new(XorShiftRng::new(seed)))
+  }
+
+  /// Create a machine that draws `_CXNN` values from `rng`.
+  pub fn with_rng(rng:Box<dyn RandomSource>) -> Self
+  {
+    let mut chip8 = Chip8::new();
+
+    chip8.rng = rng;
+
+    chip8
+  }
+
+  fn init_fontset(&mut self)
+  {
+    self.memory.memory[..CHIP8_FONTSET.len()].copy_from_slice(&CHIP8_FONTSET);
+
+    self.memory.memory[BIG_FONTSET_START..BIG_FONTSET_START + SUPERCHIP_FONTSET.len()]
+      .copy_from_slice(&SUPERCHIP_FONTSET);
+  }
+
+  /// Reset the machine to its power-on state and load the font set.
+  pub fn initialize(&mut self)
+  {
+    // Initialize registers and memory once.
+    self.curr_opcode = OpCode::new(0x0); // Reset current opcode. 
+
+    // Clear stack
+    self.stack.clear(); // Reset stack and stack pointer.
+    
+    // Clear registers V0-VF, PC, I, timers(delay, sound).
+    self.regs.clear();
+    
+    // Clear memory
+    self.memory.clear();
+ 
+    // Clear display and go back to low resolution
+    self.graphics.set_hires(false);
+
+    // Load fontset
+    self.init_fontset();
+
+    self.flush_decode_cache();
+
+    // Clear the screen
+    self.draw_flag = true;
+
+    self.wait_vblank = false;
+
+    self.halted = false;
+
+    self.key_wait = None;
+
+    self.planes = PLANE_1;
+
+    self.audio_pattern = [0x0;16];
+
+    self.pitch = DEFAULT_PITCH;
+
+    self.journal.clear();
+  }
+
+  /// Copy a ROM image into memory at 0x200. Fails without touching memory
+  /// if the ROM does not fit (4K, or 64K on XO-CHIP). Returns the number
+  /// of bytes loaded.
+  pub fn load_rom(&mut self, rom:&[u8]) -> Result<usize, Chip8Error>
+  {
+    let max = self.memory.size() - PROGRAM_START;
+
+    if rom.len() > max
+    {
+      return Err(Chip8Error::RomTooLarge { size:rom.len(), max });
+    }
+
+    self.memory.memory[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
+
+    self.flush_decode_cache();
+
+    self.journal.clear();
+
+    Ok(rom.len())
+  }
+
+  // Every cycle, the method emulateCycle is called which emulates
+  // one cycle of the Chip 8 CPU. During this cycle, 
+  // the emulator will Fetch, Decode and Execute one opcode.
+  
+  // Fetch opcode:
+  // =============
+  // During this step, the system will fetch one opcode from the
+  // memory at the location specified by the program counter (pc).
+  // In our Chip 8 emulator, data is stored in an array in which
+  // each address contains one byte. As one opcode is 2 bytes long,
+  // we will need to fetch two successive bytes and merge them to
+  // get the actual opcode.
+  //
+  // Decoding is cached per address; the cache entries covering a byte are
+  // dropped whenever that byte is written.
+  fn fetch_opcode(&mut self) -> Result<Instruction, Chip8Error>
+  {
+    let PC = self.regs.PC as usize;
+
+    let instruction = match self.decode_cache.get(PC).cloned().flatten()
+    {
+      Some(instruction) => instruction,
+      None =>
+      {
+        let instruction = decode(self.read_word(PC)?);
+
+        self.decode_cache[PC] = Some(instruction);
+
+        instruction
+      },
+    };
+
+    self.curr_opcode = OpCode::new(instruction.encode());
+
+    Ok(instruction)
+  }
+
+  fn flush_decode_cache(&mut self)
+  {
+    self.decode_cache = vec![None;self.memory.size()];
+  }
+
+  // Record the watchpoints hit by an access of the current instruction.
+  fn watch_access(&mut self, addr:usize, access:Access, val:u16)
+  {
+    for watchpoint in self.watchpoints.iter()
+    {
+      if watchpoint.matches_memory(addr, access, val)
+      {
+        self.watch_hits.push(WatchHit { watchpoint:*watchpoint, pc:self.regs.PC, value:val });
+      }
+    }
+  }
+
+  // Every memory read of an instruction's data goes through here, so
+  // read watchpoints see it.
+  fn read_byte(&mut self, addr:usize) -> u8
+  {
+    let val = self.memory.memory[addr];
+
+    self.watch_access(addr, Access::Read, val as u16);
+
+    val
+  }
+
+  // Every memory write from an instruction goes through here so the
+  // decode cache stays coherent with self-modifying code, and write
+  // watchpoints see it.
+  fn write_byte(&mut self, addr:usize, val:u8)
+  {
+    if let Some(undo) = self.undo.as_mut()
+    {
+      undo.memory.push((addr, self.memory.memory[addr]));
+    }
+
+    self.memory.memory[addr] = val;
+
+    self.watch_access(addr, Access::Write, val as u16);
+
+    self.invalidate_decode(addr);
+  }
+
+  fn invalidate_decode(&mut self, addr:usize)
+  {
+    // Both the instruction starting at addr and the one starting just
+    // before it contain this byte.
+    self.decode_cache[addr] = None;
+
+    if addr > 0
+    {
+      self.decode_cache[addr - 1] = None;
+    }
+  }
+
+  // Fail with MemoryOutOfBounds unless all of addr..addr+len is in memory.
+  fn check_range(&self, addr:usize, len:usize) -> Result<(), Chip8Error>
+  {
+    if addr + len > self.memory.size()
+    {
+      return Err(Chip8Error::MemoryOutOfBounds { addr:addr.max(self.memory.size()) });
+    }
+
+    Ok(())
+  }
+
+  fn read_word(&self, addr:usize) -> Result<u16, Chip8Error>
+  {
+    self.check_range(addr, 2)?;
+
+    let first_half:u8   = self.memory.memory[addr];
+    let second_half:u8  = self.memory.memory[addr + 1];
+
+    Ok(((first_half as u16 ) << 8) | (second_half as u16))
+  }
+
+  // Skip the next instruction. On XO-CHIP the 4 byte F000 NNNN counts as a
+  // single instruction. Like every PC advance, it wraps around the 64K
+  // address space.
+  fn skip_next(&mut self)
+  {
+    if self.platform == Platform::XoChip && self.read_word(self.regs.PC as usize + 2) == Ok(0xF000)
+    {
+      self.regs.PC = self.regs.PC.wrapping_add(6);
+    }
+    else
+    {
+      self.regs.PC = self.regs.PC.wrapping_add(4);
+    }
+  }
+
+  /// True if `instruction` does something on this platform, rather than
+  /// being left to the undefined opcode policy.
+  pub fn is_defined(&self, instruction:Instruction) -> bool
+  {
+    !matches!(instruction, Instruction::Sys { .. } | Instruction::Undefined { .. }) &&
+      self.platform.supports(instruction.symbol())
+  }
+
+  /// Decode the instruction stored at `addr`, or `None` past the end of
+  /// memory.
+  pub fn instruction_at(&self, addr:usize) -> Option<Instruction>
+  {
+    self.read_word(addr).ok().map(decode)
+  }
+
+  fn execute_opcode(&mut self, instruction:Instruction) -> Result<(), Chip8Error>
+  {
+    let mut instruction = instruction;
+
+    if !self.platform.supports(instruction.symbol())
+    {
+      instruction = Instruction::Undefined { opcode:instruction.encode() };
+    }
+
+    match instruction
+    {
+      Instruction::Cls =>
+      {
+        // clear the graphics screen (the selected planes on XO-CHIP).
+        self.journal_screen();
+
+        self.graphics.clear_planes(self.planes);
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+
+        self.draw_flag = true;
+      },
+
+      Instruction::Ret =>
+      {
+        // Return from a function call. This is my implementation.
+        if self.stack.sp == 0
+        {
+          return Err(Chip8Error::StackUnderflow { pc:self.regs.PC });
+        }
+
+        self.stack.sp -= 1;
+        self.regs.PC = self.stack.stack[self.stack.sp as usize];
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::ScrollDown { n } =>
+      {
+        // Scroll the display down N pixels.
+        self.journal_screen();
+
+        self.graphics.scroll_down(n as usize, self.planes);
+
+        self.draw_flag = true;
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::ScrollRight =>
+      {
+        // Scroll the display right 4 pixels.
+        self.journal_screen();
+
+        self.graphics.scroll_right(4, self.planes);
+
+        self.draw_flag = true;
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::ScrollLeft =>
+      {
+        // Scroll the display left 4 pixels.
+        self.journal_screen();
+
+        self.graphics.scroll_left(4, self.planes);
+
+        self.draw_flag = true;
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::Exit =>
+      {
+        // Exit the interpreter. PC stays on the 00FD.
+        self.halted = true;
+      },
+
+      Instruction::LowRes =>
+      {
+        // Low resolution (64x32).
+        self.journal_screen();
+
+        self.graphics.set_hires(false);
+
+        self.draw_flag = true;
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::HighRes =>
+      {
+        // High resolution (128x64).
+        self.journal_screen();
+
+        self.graphics.set_hires(true);
+
+        self.draw_flag = true;
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::Jump { nnn } =>
+      {
+        self.regs.PC = nnn;
+      },
+
+      Instruction::Call { nnn } =>
+      {
+        if self.stack.sp as usize >= self.stack.stack.len()
+        {
+          return Err(Chip8Error::StackOverflow { pc:self.regs.PC });
+        }
+
+        self.stack.stack[self.stack.sp as usize] = self.regs.PC;
+        self.stack.sp += 1;
+        self.regs.PC = nnn;
+      },
+
+      Instruction::SkipEqImm { x, nn } =>
+      {
+        if self.regs.V[x as usize] == nn
+        {
+          self.skip_next();
+        }
+        else
+        {
+          self.regs.PC = self.regs.PC.wrapping_add(2);
+        }
+      },
+
+      Instruction::SkipNeImm { x, nn } =>
+      {
+        if self.regs.V[x as usize] != nn
+        {
+          self.skip_next();
+        }
+        else
+        {
+          self.regs.PC = self.regs.PC.wrapping_add(2);
+        }
+      },
+
+      Instruction::SkipEqReg { x, y } =>
+      {
+        if self.regs.V[x as usize] == self.regs.V[y as usize]
+        {
+          self.skip_next();
+        }
+        else
+        {
+          self.regs.PC = self.regs.PC.wrapping_add(2);
+        }
+      },
+
+      Instruction::SaveRange { x, y } | Instruction::LoadRange { x, y } =>
+      {
+        // Save (5XY2) or load (5XY3) VX..VY at I, in either direction.
+        // I is not changed.
+        let (X, Y) = (x as usize, y as usize);
+
+        let count = if X <= Y { Y - X + 1 } else { X - Y + 1 };
+
+        self.check_range(self.regs.I as usize, count)?;
+
+        for idx in 0..count
+        {
+          let reg  = if X <= Y { X + idx } else { X - idx };
+          let addr = self.regs.I as usize + idx;
+
+          if let Instruction::SaveRange { .. } = instruction
+          {
+            self.write_byte(addr, self.regs.V[reg]);
+          }
+          else
+          {
+            self.regs.V[reg] = self.read_byte(addr);
+          }
+        }
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::LoadImm { x, nn } =>
+      {
+        // V[X] = NN
+        self.regs.V[x as usize] = nn;
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::AddImm { x, nn } =>
+      {
+        // V[X] += NN, no carry
+        self.regs.V[x as usize] = self.regs.V[x as usize].wrapping_add(nn);
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::Move { x, y } =>
+      {
+        // V[X] = V[Y]
+        self.regs.V[x as usize] = self.regs.V[y as usize];
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::Or { x, y } | Instruction::And { x, y } | Instruction::Xor { x, y } =>
+      {
+        let (vx, vy) = (self.regs.V[x as usize], self.regs.V[y as usize]);
+
+        // V[X] = V[X] |,&,^ V[Y] /* Bitwise Or, And, Xor */
+        self.regs.V[x as usize] = match instruction
+        {
+          Instruction::Or { .. }  => vx | vy,
+          Instruction::And { .. } => vx & vy,
+          _                       => vx ^ vy,
+        };
+
+        if self.quirks.logic_resets_vf
+        {
+          self.regs.V[0xF] = 0;
+        }
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::Add { x, y } =>
+      {
+        // VX += VY, VF is the carry. VF is written last so that 8FY4
+        // leaves the flag rather than the sum.
+        let (sum, carry) = self.regs.V[x as usize].overflowing_add(self.regs.V[y as usize]);
+
+        self.regs.V[x as usize] = sum;
+        self.regs.V[0xF]        = carry as u8;
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::Sub { x, y } =>
+      {
+        // VX -= VY, VF is 0 if there is a borrow
+        let (diff, borrow) = self.regs.V[x as usize].overflowing_sub(self.regs.V[y as usize]);
+
+        self.regs.V[x as usize] = diff;
+        self.regs.V[0xF]        = !borrow as u8;
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::ShiftRight { x, y } =>
+      {
+        // VX >>= 1 (or VX = VY >> 1 with the shift quirk)
+        let src = if self.quirks.shift_uses_vy { self.regs.V[y as usize] } else { self.regs.V[x as usize] };
+
+        // shift right, VF gets the bit shifted out (lsb 0b00000001)
+        self.regs.V[x as usize] = src >> 1;
+        self.regs.V[0xF]        = src & 0x1;
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::SubReverse { x, y } =>
+      {
+        // VX = VY - VX, VF is 0 if there is a borrow
+        let (diff, borrow) = self.regs.V[y as usize].overflowing_sub(self.regs.V[x as usize]);
+
+        self.regs.V[x as usize] = diff;
+        self.regs.V[0xF]        = !borrow as u8;
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::ShiftLeft { x, y } =>
+      {
+        // VX <<= 1 (or VX = VY << 1 with the shift quirk)
+        let src = if self.quirks.shift_uses_vy { self.regs.V[y as usize] } else { self.regs.V[x as usize] };
+
+        // shift left, VF gets the bit shifted out (msb 0b10000000)
+        self.regs.V[x as usize] = src << 1;
+        self.regs.V[0xF]        = src >> 7;
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::SkipNeReg { x, y } =>
+      {
+        if self.regs.V[x as usize] != self.regs.V[y as usize]
+        {
+          self.skip_next();
+        }
+        else
+        {
+          self.regs.PC = self.regs.PC.wrapping_add(2);
+        }
+      },
+
+      Instruction::LoadIndex { nnn } =>
+      {
+        self.regs.I = nnn;
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::JumpOffset { nnn } =>
+      {
+        if self.quirks.jump_uses_vx
+        {
+          let X = (nnn >> 8) as usize;
+          self.regs.PC = nnn + (self.regs.V[X] as u16); // XNN + VX
+        }
+        else
+        {
+          self.regs.PC = nnn + (self.regs.V[0] as u16); // NNN + V0
+        }
+      },
+
+      Instruction::Random { x, nn } =>
+      {
+        self.regs.V[x as usize] = self.rng.next_byte() & nn;
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      // Display pixel at position(X,Y)
+      Instruction::Draw { x, y, n } =>
+      {
+        if n == 0 && self.platform.supports_superchip()
+        {
+          // SUPER-CHIP 16x16 sprite, two bytes per row.
+          self.draw_sprite(x as usize, y as usize, 16, 2)?;
+        }
+        else
+        {
+          self.draw_sprite(x as usize, y as usize, n as usize, 1)?;
+        }
+
+        if self.quirks.display_wait
+        {
+          self.wait_vblank = true;
+        }
+
+        self.draw_flag = true;
+
+        // PC += 2
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::SkipKeyPressed { x } =>
+      {
+        if self.key[(self.regs.V[x as usize] & 0xF) as usize] != 0
+        {
+          self.skip_next();
+        }
+        else
+        {
+          self.regs.PC = self.regs.PC.wrapping_add(2);
+        }
+      },
+
+      Instruction::SkipKeyNotPressed { x } =>
+      {
+        if self.key[(self.regs.V[x as usize] & 0xF) as usize] == 0
+        {
+          self.skip_next();
+        }
+        else
+        {
+          self.regs.PC = self.regs.PC.wrapping_add(2);
+        }
+      },
+
+      Instruction::LoadLongIndex =>
+      {
+        // I = NNNN, the 16 bit word following the opcode.
+        self.regs.I = self.read_word(self.regs.PC as usize + 2)?;
+
+        self.regs.PC = self.regs.PC.wrapping_add(4);
+      },
+
+      Instruction::SelectPlanes { n } =>
+      {
+        // Select the bitplanes used by 00E0, 00CN/FB/FC and DXYN.
+        self.planes = n & ALL_PLANES;
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::LoadAudio =>
+      {
+        // Load the 16 byte audio pattern at I.
+        let I = self.regs.I as usize;
+
+        self.check_range(I, 16)?;
+
+        for idx in 0..16
+        {
+          self.audio_pattern[idx] = self.read_byte(I + idx);
+        }
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::GetDelay { x } =>
+      {
+        self.regs.V[x as usize] = self.regs.DELAY_TIMER;
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::WaitKey { x } =>
+      {
+        // get_key(): suspend the CPU until a key event completes the wait
+        // (see key_changed). PC stays on the FX0A until then.
+        self.key_wait = Some(KeyWait { x, pressed:None });
+      },
+
+      Instruction::SetDelay { x } =>
+      {
+        self.regs.DELAY_TIMER = self.regs.V[x as usize];
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::SetSound { x } =>
+      {
+        self.regs.SOUND_TIMER = self.regs.V[x as usize];
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::AddIndex { x } =>
+      {
+        let sum = self.regs.I as u32 + self.regs.V[x as usize] as u32;
+
+        if self.quirks.index_overflow_sets_vf
+        {
+          self.regs.V[0xF] = (sum > 0xFFF) as u8;
+        }
+
+        self.regs.I = sum as u16;
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::FontChar { x } =>
+      {
+        // Point I at the 4x5 font sprite for the digit in VX.
+        self.regs.I = ((self.regs.V[x as usize] & 0xF) as u16) * 0x5;
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::BigFontChar { x } =>
+      {
+        // Point I at the 8x10 big font sprite for the digit in VX.
+        self.regs.I = (BIG_FONTSET_START + ((self.regs.V[x as usize] & 0xF) as usize) * 10) as u16;
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::Bcd { x } =>
+      {
+        let I  = self.regs.I as usize;
+        let VX = self.regs.V[x as usize];
+
+        self.check_range(I, 3)?;
+
+        self.write_byte(I,     VX / 100);
+        self.write_byte(I + 1, (VX / 10) % 10);
+        self.write_byte(I + 2, VX % 10);
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::SetPitch { x } =>
+      {
+        // Set the audio pattern playback pitch.
+        self.pitch = self.regs.V[x as usize];
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::Store { x } =>
+      {
+        let I = self.regs.I as usize;
+        let X = x as usize;
+
+        self.check_range(I, X + 1)?;
+
+        for idx in 0..=X
+        {
+          self.write_byte(I + idx, self.regs.V[idx]);
+        }
+
+        if self.quirks.load_store_increments_i
+        {
+          self.regs.I = self.regs.I.wrapping_add(x as u16 + 1);
+        }
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::Load { x } =>
+      {
+        let I = self.regs.I as usize;
+        let X = x as usize;
+
+        self.check_range(I, X + 1)?;
+
+        for idx in 0..=X
+        {
+          self.regs.V[idx] = self.read_byte(I + idx);
+        }
+
+        if self.quirks.load_store_increments_i
+        {
+          self.regs.I = self.regs.I.wrapping_add(x as u16 + 1);
+        }
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::SaveFlags { x } =>
+      {
+        // Save V0..VX to the RPL user flags.
+        let X = x as usize;
+
+        self.rpl[..=X].copy_from_slice(&self.regs.V[..=X]);
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      Instruction::LoadFlags { x } =>
+      {
+        // Restore V0..VX from the RPL user flags.
+        let X = x as usize;
+
+        self.regs.V[..=X].copy_from_slice(&self.rpl[..=X]);
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+
+      // 0NNN calls a machine code routine on the COSMAC VIP, which we
+      // can't run, so it is treated like any other undefined opcode.
+      Instruction::Sys { .. } | Instruction::Undefined { .. } =>
+      {
+        match self.on_undefined
+        {
+          UndefinedOpcodePolicy::Halt =>
+          {
+            return Err(Chip8Error::InvalidOpcode { pc:self.regs.PC, opcode:self.curr_opcode.val });
+          },
+
+          UndefinedOpcodePolicy::Skip => {},
+
+          UndefinedOpcodePolicy::Log =>
+          {
+            self.skipped.push(Chip8Error::InvalidOpcode { pc:self.regs.PC, opcode:self.curr_opcode.val });
+          },
+        }
+
+        self.regs.PC = self.regs.PC.wrapping_add(2);
+      },
+    }
+
+    Ok(())
+  }
+
+  // XOR a sprite read from I onto the screen at (VX,VY). Each row is
+  // `row_bytes` bytes wide. With both XO-CHIP planes selected the sprite
+  // for the second plane follows the one for the first. VF is set if any
+  // lit pixel was turned off.
+  fn draw_sprite(&mut self, X:usize, Y:usize, rows:usize, row_bytes:usize) -> Result<(), Chip8Error>
+  {
+    let width   = self.graphics.width();
+    let height  = self.graphics.height();
+
+    // The start position always wraps around the screen.
+    let x       = (self.regs.V[X] as usize) % width;
+    let y       = (self.regs.V[Y] as usize) % height;
+
+    let mut addr = self.regs.I as usize;
+
+    let num_planes = (self.planes & ALL_PLANES).count_ones() as usize;
+
+    self.check_range(addr, rows*row_bytes*num_planes)?;
+
+    self.regs.V[0xF] = 0;
+
+    for plane in [PLANE_1, PLANE_2].iter().cloned()
+    {
+      if self.planes & plane == 0
+      {
+        continue;
+      }
+
+      for yline in 0..rows
+      {
+        for byte in 0..row_bytes
+        {
+          let pixel = self.read_byte(addr + yline*row_bytes + byte);
+
+          for xline in 0..8
+          {
+            if pixel & (0x80 >> xline) != 0
+            {
+              let mut px = x + byte*8 + xline;
+              let mut py = y + yline;
+
+              if self.quirks.clip_sprites
+              {
+                if px >= width || py >= height
+                {
+                  continue;
+                }
+              }
+              else
+              {
+                px %= width;
+                py %= height;
+              }
+
+              let gfx_idx = px + py * width;
+
+              if self.graphics.gfx[ gfx_idx ] & plane != 0
+              {
+                self.regs.V[0xF] = 1;
+              }
+
+              if let Some(undo) = self.undo.as_mut()
+              {
+                undo.pixels.push((gfx_idx, self.graphics.gfx[ gfx_idx ]));
+              }
+
+              // XOR
+              self.graphics.gfx[ gfx_idx ] ^= plane;
+            }
+          }
+        }
+      }
+
+      addr += rows*row_bytes;
+    }
+
+    Ok(())
+  }
+
+  /// Fetch, decode and execute a single instruction. The timers are not
+  /// touched; call `tick_timers` at 60 Hz of emulated time (a `Scheduler`
+  /// does both). On error the machine state is left as it was before the
+  /// failing instruction.
+  pub fn emulate_cycle(&mut self) -> Result<(), Chip8Error>
+  {
+    if self.idle()
+    {
+      return Ok(());
+    }
+
+    self.watch_hits.clear();
+
+    let curr_opcode = self.curr_opcode.val;
+    let instruction = self.fetch_opcode()?;
+
+    if self.journal.budget() > 0
+    {
+      self.undo = Some(self.undo_entry(curr_opcode));
+    }
+
+    // Register watchpoints compare the registers before and after.
+    let PC      = self.regs.PC;
+    let before  = if self.watchpoints.is_empty() { None } else { Some(self.regs.clone()) };
+
+    let result  = self.execute_opcode(instruction);
+    let undo    = self.undo.take();
+
+    result?;
+
+    if let Some(before) = before
+    {
+      for watchpoint in self.watchpoints.iter()
+      {
+        if let WatchTarget::Register(register) = watchpoint.target
+        {
+          let value = register.get(&self.regs);
+
+          if value != register.get(&before) && watchpoint.accepts(value)
+          {
+            self.watch_hits.push(WatchHit { watchpoint:*watchpoint, pc:PC, value });
+          }
+        }
+      }
+    }
+
+    if let Some(mut undo) = undo
+    {
+      undo.hits = self.watch_hits.clone();
+
+      self.journal.push(undo);
+    }
+
+    if self.trace.is_some()
+    {
+      let line = TraceLine::new(self, PC, instruction);
+
+      if let Some(trace) = self.trace.as_mut()
+      {
+        trace.record(&line);
+      }
+    }
+
+    Ok(())
+  }
+
+  // The state an instruction may change, before it runs.
+  fn undo_entry(&self, curr_opcode:u16) -> UndoEntry
+  {
+    UndoEntry { regs:         self.regs.clone(),
+                stack:        self.stack.clone(),
+                curr_opcode,
+                draw_flag:    self.draw_flag,
+                wait_vblank:  self.wait_vblank,
+                halted:       self.halted,
+                key_wait:     self.key_wait,
+                hires:        self.graphics.hires(),
+                planes:       self.planes,
+                pitch:        self.pitch,
+                audio_pattern:self.audio_pattern,
+                rpl:          self.rpl,
+                rng_state:    self.rng.state(),
+                memory:       Vec::new(),
+                pixels:       Vec::new(),
+                screen:       None,
+                hits:         Vec::new(),
+              }
+  }
+
+  // Keep the whole screen before an instruction that changes all of it.
+  fn journal_screen(&mut self)
+  {
+    if let Some(undo) = self.undo.as_mut().filter(|undo| undo.screen.is_none())
+    {
+      undo.screen = Some(self.graphics.gfx.to_vec());
+    }
+  }
+
+  /// Keep undo entries for the last instructions, up to `budget` bytes of
+  /// them, so they can be run backwards with `step_back`. 0, the default,
+  /// turns this off.
+  pub fn set_journal_budget(&mut self, budget:usize)
+  {
+    self.journal.set_budget(budget);
+  }
+
+  pub fn journal(&self) -> &Journal
+  {
+    &self.journal
+  }
+
+  /// Log every instruction executed from now on, with the machine state
+  /// after it. Instructions undone by `step_back` stay in the trace.
+  pub fn set_trace(&mut self, trace:Trace)
+  {
+    self.trace = Some(trace);
+  }
+
+  /// Stop tracing, handing back the trace to be finished.
+  pub fn take_trace(&mut self) -> Option<Trace>
+  {
+    self.trace.take()
+  }
+
+  /// Undo the last instruction in the journal, putting the machine back
+  /// the way it was just before it ran, timers included. Returns the
+  /// watchpoints that instruction hit, or None if there is no history
+  /// left. The keypad and the `Scheduler`'s place in the frame are not
+  /// part of the history.
+  pub fn step_back(&mut self) -> Option<Vec<WatchHit>>
+  {
+    let undo = self.journal.pop()?;
+
+    for &(addr, val) in undo.memory.iter().rev()
+    {
+      self.memory.memory[addr] = val;
+
+      self.invalidate_decode(addr);
+    }
+
+    if let Some(screen) = undo.screen.as_ref()
+    {
+      self.graphics.set_hires(undo.hires);
+      self.graphics.gfx.copy_from_slice(screen);
+    }
+
+    for &(idx, val) in undo.pixels.iter().rev()
+    {
+      self.graphics.gfx[idx] = val;
+    }
+
+    if let Some(rng_state) = undo.rng_state
+    {
+      self.rng.set_state(rng_state);
+    }
+
+    self.regs           = undo.regs;
+    self.stack          = undo.stack;
+    self.curr_opcode    = OpCode::new(undo.curr_opcode);
+    self.draw_flag      = undo.draw_flag || !undo.pixels.is_empty() || undo.screen.is_some();
+    self.wait_vblank    = undo.wait_vblank;
+    self.halted         = undo.halted;
+    self.key_wait       = undo.key_wait;
+    self.planes         = undo.planes;
+    self.pitch          = undo.pitch;
+    self.audio_pattern  = undo.audio_pattern;
+    self.rpl            = undo.rpl;
+
+    self.watch_hits.clear();
+
+    Some(undo.hits)
+  }
+
+  /// The execute watchpoint that matches the instruction at PC, if any.
+  /// Checked before the instruction runs, unlike the other watchpoints.
+  pub fn execute_watch_hit(&self) -> Option<WatchHit>
+  {
+    let PC      = self.regs.PC;
+    let opcode  = self.read_word(PC as usize).ok()?;
+
+    self.watchpoints.iter()
+        .find(|watchpoint| watchpoint.matches_memory(PC as usize, Access::Execute, opcode))
+        .map(|watchpoint| WatchHit { watchpoint:*watchpoint, pc:PC, value:opcode })
+  }
+
+  pub fn add_watchpoint(&mut self, watchpoint:Watchpoint)
+  {
+    self.watchpoints.push(watchpoint);
+  }
+
+  /// Remove the watchpoint at `idx` in `watchpoints()`.
+  pub fn remove_watchpoint(&mut self, idx:usize) -> Option<Watchpoint>
+  {
+    if idx < self.watchpoints.len() { Some(self.watchpoints.remove(idx)) } else { None }
+  }
+
+  pub fn clear_watchpoints(&mut self)
+  {
+    self.watchpoints.clear();
+  }
+
+  pub fn watchpoints(&self) -> &[Watchpoint]
+  {
+    &self.watchpoints
+  }
+
+  /// The watchpoints hit by the last instruction `emulate_cycle` ran.
+  pub fn watch_hits(&self) -> &[WatchHit]
+  {
+    &self.watch_hits
+  }
+
+  /// Decrement the delay and sound timers by one. Call at 60 Hz.
+  pub fn tick_timers(&mut self)
+  {
+    self.wait_vblank = false;
+
+    if self.regs.DELAY_TIMER > 0
+    {
+      self.regs.DELAY_TIMER -= 1;
+    }
+
+    if self.regs.SOUND_TIMER > 0
+    {
+      self.regs.SOUND_TIMER -= 1;
+    }
+  }
+
+  /// Set the state of a single key, 0x1 for pressed and 0x0 for released.
+  pub fn set_key(&mut self, key:usize, state:u8)
+  {
+    let was_pressed = self.key[key] != 0;
+
+    self.key[key] = state;
+
+    if was_pressed != (state != 0)
+    {
+      self.key_changed(key as u8, state != 0);
+    }
+  }
+
+  /// Key down for hex key 0x0-0xF. Other held keys stay held.
+  pub fn press(&mut self, key:u8)
+  {
+    self.set_key((key & 0xF) as usize, 0x1);
+  }
+
+  /// Key up for hex key 0x0-0xF.
+  pub fn release(&mut self, key:u8)
+  {
+    self.set_key((key & 0xF) as usize, 0x0);
+  }
+
+  /// Set the whole keypad at once, bit N set meaning key N is held. Useful
+  /// for frontends that poll, and for replaying recorded input.
+  pub fn set_keypad(&mut self, mask:u16)
+  {
+    for idx in 0..NUM_KEYS
+    {
+      self.set_key(idx, ((mask >> idx) & 0x1) as u8);
+    }
+  }
+
+  /// The held keys as a mask, bit N set meaning key N is held.
+  pub fn keypad(&self) -> u16
+  {
+    let mut mask = 0x0;
+
+    for idx in 0..NUM_KEYS
+    {
+      if self.key[idx] != 0
+      {
+        mask |= 0x1 << idx;
+      }
+    }
+
+    mask
+  }
+
+  // Complete a pending FX0A. Like the COSMAC VIP, the key_wait_on_release
+  // quirk waits for the key to go up again; otherwise the press is enough.
+  fn key_changed(&mut self, key:u8, pressed:bool)
+  {
+    let wait = match self.key_wait
+    {
+      Some(wait) => wait,
+      None       => return,
+    };
+
+    let done = match wait.pressed
+    {
+      None if pressed =>
+      {
+        if !self.quirks.key_wait_on_release
+        {
+          true
+        }
+        else
+        {
+          self.key_wait = Some(KeyWait { pressed:Some(key), ..wait });
+          false
+        }
+      },
+      Some(waiting_key) => !pressed && waiting_key == key,
+      None => false,
+    };
+
+    if done
+    {
+      self.regs.V[wait.x as usize] = key;
+
+      self.regs.PC = self.regs.PC.wrapping_add(2);
+
+      self.key_wait = None;
+    }
+  }
+
+  /// Replace the `_CXNN` random source.
+  pub fn set_rng(&mut self, rng:Box<dyn RandomSource>)
+  {
+    self.rng = rng;
+  }
+
+  /// Restart the random source from `seed`.
+  pub fn reseed(&mut self, seed:u64)
+  {
+    self.rng = Box::new(XorShiftRng::new(seed));
+  }
+
+  pub fn rng(&self) -> &dyn RandomSource
+  {
+    self.rng.as_ref()
+  }
+
+  pub fn rng_mut(&mut self) -> &mut dyn RandomSource
+  {
+    self.rng.as_mut()
+  }
+
+  /// Snapshot everything needed to resume the machine later.
+  pub fn save_state(&self) -> SaveState
+  {
+    SaveState { format:       SAVE_STATE_FORMAT.to_string(),
+                version:      SAVE_STATE_VERSION,
+                platform:     self.platform,
+                quirks:       self.quirks,
+                regs:         self.regs.clone(),
+                memory:       self.memory.memory.clone(),
+                stack:        self.stack.clone(),
+                gfx:          self.graphics.gfx.to_vec(),
+                hires:        self.graphics.hires(),
+                keypad:       self.keypad(),
+                key_wait:     self.key_wait,
+                rng_state:    self.rng.state(),
+                curr_opcode:  self.curr_opcode.val,
+                draw_flag:    self.draw_flag,
+                wait_vblank:  self.wait_vblank,
+                halted:       self.halted,
+                rpl:          self.rpl,
+                planes:       self.planes,
+                audio_pattern:self.audio_pattern,
+                pitch:        self.pitch,
+              }
+  }
+
+  /// Restore a snapshot taken by `save_state`. The machine is left
+  /// untouched if the snapshot is inconsistent. The undefined opcode
+  /// policy is a frontend setting and is kept.
+  pub fn load_state(&mut self, state:&SaveState) -> Result<(), SaveStateError>
+  {
+    if state.memory.len() != state.platform.memory_size()
+    {
+      return Err(SaveStateError::Inconsistent { reason:"memory size doesn't match the platform" });
+    }
+
+    if state.gfx.len() != self.graphics.gfx.len()
+    {
+      return Err(SaveStateError::Inconsistent { reason:"wrong framebuffer size" });
+    }
+
+    if state.stack.sp as usize > state.stack.stack.len()
+    {
+      return Err(SaveStateError::Inconsistent { reason:"stack pointer out of range" });
+    }
+
+    if state.key_wait.is_some_and(|wait| wait.x as usize >= self.regs.V.len() || wait.pressed.is_some_and(|key| key as usize >= NUM_KEYS))
+    {
+      return Err(SaveStateError::Inconsistent { reason:"key wait register or key out of range" });
+    }
+
+    if state.planes & !ALL_PLANES != 0
+    {
+      return Err(SaveStateError::Inconsistent { reason:"bitplane selection out of range" });
+    }
+
+    self.platform       = state.platform;
+    self.quirks         = state.quirks;
+    self.regs           = state.regs.clone();
+    self.memory.memory  = state.memory.clone();
+    self.stack          = state.stack.clone();
+
+    self.graphics.set_hires(state.hires);
+    self.graphics.gfx.copy_from_slice(&state.gfx);
+
+    // Restore the keypad as is; completing an FX0A here would replay a
+    // key event that already happened.
+    for idx in 0..NUM_KEYS
+    {
+      self.key[idx] = ((state.keypad >> idx) & 0x1) as u8;
+    }
+
+    self.key_wait       = state.key_wait;
+
+    if let Some(rng_state) = state.rng_state
+    {
+      self.rng.set_state(rng_state);
+    }
+
+    self.curr_opcode    = OpCode::new(state.curr_opcode);
+    self.draw_flag      = state.draw_flag;
+    self.wait_vblank    = state.wait_vblank;
+    self.halted         = state.halted;
+    self.rpl            = state.rpl;
+    self.planes         = state.planes;
+    self.audio_pattern  = state.audio_pattern;
+    self.pitch          = state.pitch;
+
+    self.flush_decode_cache();
+
+    // The history belongs to the timeline that was left.
+    self.journal.clear();
+
+    Ok(())
+  }
+
+  /// True if `emulate_cycle` won't execute anything: with the display_wait
+  /// quirk the CPU idles after a draw until the next timer tick, FX0A
+  /// idles it until a key is pressed, and 00FD stops it for good.
+  pub fn idle(&self) -> bool
+  {
+    self.wait_vblank || self.halted || self.key_wait.is_some()
+  }
+
+  /// True if a `_DXYN` with the display_wait quirk has the CPU idle until
+  /// the next timer tick.
+  pub fn waiting_for_vblank(&self) -> bool
+  {
+    self.wait_vblank
+  }
+
+  /// The pending `_FX0A`, if the CPU is waiting for a key.
+  pub fn key_wait(&self) -> Option<KeyWait>
+  {
+    self.key_wait
+  }
+
+  /// Returns true while the sound timer is running and the buzzer should
+  /// be audible.
+  pub fn sound_active(&self) -> bool
+  {
+    self.regs.SOUND_TIMER > 0
+  }
+
+  /// True if the framebuffer changed since the last `clear_draw_flag`.
+  pub fn draw_flag(&self) -> bool
+  {
+    self.draw_flag
+  }
+
+  pub fn clear_draw_flag(&mut self)
+  {
+    self.draw_flag = false;
+  }
+
+  /// The framebuffer of the active resolution (64x32 or 128x64), one
+  /// byte per pixel, row major.
+  pub fn framebuffer(&self) -> &[u8]
+  {
+    self.graphics.pixels()
+  }
+
+  pub fn graphics(&self) -> &Graphics
+  {
+    &self.graphics
+  }
+
+  pub fn regs(&self) -> &Registers
+  {
+    &self.regs
+  }
+
+  pub fn regs_mut(&mut self) -> &mut Registers
+  {
+    &mut self.regs
+  }
+
+  pub fn memory(&self) -> &Memory
+  {
+    &self.memory
+  }
+
+  /// Direct access to memory. The decode cache is flushed, since the
+  /// caller may change code.
+  pub fn memory_mut(&mut self) -> &mut Memory
+  {
+    self.flush_decode_cache();
+
+    &mut self.memory
+  }
+
+  pub fn stack(&self) -> &Stack
+  {
+    &self.stack
+  }
+
+  pub fn stack_mut(&mut self) -> &mut Stack
+  {
+    &mut self.stack
+  }
+
+  pub fn platform(&self) -> Platform
+  {
+    self.platform
+  }
+
+  /// The XO-CHIP 16 byte audio pattern, played as 128 1-bit samples.
+  pub fn audio_pattern(&self) -> &[u8;16]
+  {
+    &self.audio_pattern
+  }
+
+  /// The XO-CHIP audio pattern playback rate in Hz, derived from the
+  /// pitch register (4000 Hz at the default pitch of 64).
+  pub fn audio_sample_rate(&self) -> f64
+  {
+    4000.0 * 2f64.powf((self.pitch as f64 - DEFAULT_PITCH as f64) / 48.0)
+  }
+
+  /// True once a SUPER-CHIP program has executed 00FD (exit).
+  pub fn halted(&self) -> bool
+  {
+    self.halted
+  }
+
+  pub fn undefined_opcode_policy(&self) -> UndefinedOpcodePolicy
+  {
+    self.on_undefined
+  }
+
+  pub fn set_undefined_opcode_policy(&mut self, policy:UndefinedOpcodePolicy)
+  {
+    self.on_undefined = policy;
+  }
+
+  /// The undefined opcodes skipped under `UndefinedOpcodePolicy::Log`
+  /// since the last call, oldest first. They pile up until taken.
+  pub fn take_skipped_opcodes(&mut self) -> Vec<Chip8Error>
+  {
+    mem::take(&mut self.skipped)
+  }
+
+  pub fn quirks(&self) -> &Quirks
+  {
+    &self.quirks
+  }
+
+  pub fn set_quirks(&mut self, quirks:Quirks)
+  {
+    self.quirks = quirks;
+  }
+
+  /// The opcode executed by the most recent `step`.
+  pub fn curr_opcode(&self) -> OpCode
+  {
+    self.curr_opcode
+  }
+}
+
+impl Default for Chip8
+{
+  fn default() -> Self
+  {
+    Chip8::new()
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  // A machine with `rom` loaded that has run `cycles` instructions.
+  fn run(mut chip8:Chip8, rom:&[u8], cycles:usize) -> Chip8
+  {
+    chip8.initialize();
+    chip8.load_rom(rom).unwrap();
+
+    for _ in 0..cycles
+    {
+      chip8.emulate_cycle().unwrap();
+    }
+
+    chip8
+  }
+
+  #[test]
+  fn shift_quirk_picks_the_source_register()
+  {
+    // V0 = 0x05, V1 = 0x0C, V0 = V? >> 1
+    let rom = [0x60, 0x05, 0x61, 0x0C, 0x80, 0x16];
+
+    let chip8 = run(Chip8::with_quirks(Quirks { shift_uses_vy:false, ..Quirks::legacy() }), &rom, 3);
+    assert_eq!((chip8.regs().V[0x0], chip8.regs().V[0xF]), (0x02, 1));
+
+    let chip8 = run(Chip8::with_quirks(Quirks { shift_uses_vy:true, ..Quirks::legacy() }), &rom, 3);
+    assert_eq!((chip8.regs().V[0x0], chip8.regs().V[0xF]), (0x06, 0));
+  }
+
+  #[test]
+  fn jump_quirk_picks_the_offset_register()
+  {
+    // V0 = 0x10, V3 = 0x20, jump to 0x300 + V?
+    let rom = [0x60, 0x10, 0x63, 0x20, 0xB3, 0x00];
+
+    let chip8 = run(Chip8::with_quirks(Quirks { jump_uses_vx:false, ..Quirks::legacy() }), &rom, 3);
+    assert_eq!(chip8.regs().PC, 0x310);
+
+    let chip8 = run(Chip8::with_quirks(Quirks { jump_uses_vx:true, ..Quirks::legacy() }), &rom, 3);
+    assert_eq!(chip8.regs().PC, 0x320);
+  }
+
+  #[test]
+  fn store_quirk_decides_whether_i_advances()
+  {
+    // I = 0x300, V0 = 0xAA, V1 = 0xBB, store V0..V1
+    let rom = [0xA3, 0x00, 0x60, 0xAA, 0x61, 0xBB, 0xF1, 0x55];
+
+    let chip8 = run(Chip8::with_quirks(Quirks { load_store_increments_i:false, ..Quirks::legacy() }), &rom, 4);
+    assert_eq!(&chip8.memory().memory[0x300..0x302], &[0xAA, 0xBB]);
+    assert_eq!(chip8.regs().I, 0x300);
+
+    let chip8 = run(Chip8::with_quirks(Quirks { load_store_increments_i:true, ..Quirks::legacy() }), &rom, 4);
+    assert_eq!(&chip8.memory().memory[0x300..0x302], &[0xAA, 0xBB]);
+    assert_eq!(chip8.regs().I, 0x302);
+  }
+
+  #[test]
+  fn superchip_draws_16x16_sprites_and_scrolls()
+  {
+    // High resolution, I = sprite, V0 = V1 = 0, draw 16x16 at (0,0),
+    // scroll down 2, scroll right 4
+    let mut rom = vec![0x00, 0xFF, 0xA2, 0x0E, 0x60, 0x00, 0x61, 0x00, 0xD0, 0x10, 0x00, 0xC2, 0x00, 0xFB];
+    rom.extend_from_slice(&[0xFF;32]);
+
+    let mut chip8 = run(Chip8::with_platform(Platform::SuperChipModern), &rom, 5);
+    assert!(chip8.graphics().hires());
+    assert!(chip8.graphics().pixel(0, 0) && chip8.graphics().pixel(15, 15));
+    assert!(!chip8.graphics().pixel(16, 0) && !chip8.graphics().pixel(0, 16));
+    assert_eq!(chip8.regs().V[0xF], 0);
+
+    chip8.emulate_cycle().unwrap();
+    assert!(!chip8.graphics().pixel(0, 1));
+    assert!(chip8.graphics().pixel(0, 2) && chip8.graphics().pixel(0, 17));
+    assert!(!chip8.graphics().pixel(0, 18));
+
+    chip8.emulate_cycle().unwrap();
+    assert!(!chip8.graphics().pixel(3, 2));
+    assert!(chip8.graphics().pixel(4, 2) && chip8.graphics().pixel(19, 17));
+    assert!(!chip8.graphics().pixel(20, 2));
+  }
+
+  #[test]
+  fn xochip_draws_each_plane_and_skips_long_loads()
+  {
+    // I = 0x8000, select both planes, V0 = V1 = 0, draw one row, then skip
+    // over a whole F000 NNNN since V0 == 0
+    let rom = [0xF0, 0x00, 0x80, 0x00, 0xF3, 0x01, 0x60, 0x00, 0x61, 0x00, 0xD0, 0x11,
+               0x30, 0x00, 0xF0, 0x00, 0x12, 0x34];
+
+    let mut chip8 = run(Chip8::with_platform(Platform::XoChip), &rom, 1);
+    assert_eq!((chip8.regs().I, chip8.regs().PC), (0x8000, 0x204));
+
+    chip8.memory_mut().memory[0x8000..0x8002].copy_from_slice(&[0x80, 0xC0]);
+
+    for _ in 0..4
+    {
+      chip8.emulate_cycle().unwrap();
+    }
+
+    assert_eq!(chip8.graphics().pixel_value(0, 0), PLANE_1 | PLANE_2);
+    assert_eq!(chip8.graphics().pixel_value(1, 0), PLANE_2);
+    assert_eq!(chip8.graphics().pixel_value(2, 0), 0);
+
+    chip8.emulate_cycle().unwrap();
+    assert_eq!((chip8.regs().I, chip8.regs().PC), (0x8000, 0x212));
+  }
+
+  // The error `emulate_cycle` stops with once `rom` has run `cycles`
+  // instructions without one.
+  fn error_after(rom:&[u8], cycles:usize) -> Chip8Error
+  {
+    let mut chip8 = run(Chip8::new(), rom, cycles);
+
+    chip8.emulate_cycle().unwrap_err()
+  }
+
+  #[test]
+  fn errors_report_where_execution_stopped()
+  {
+    // Call itself until the stack is full
+    assert_eq!(error_after(&[0x22, 0x00], 16), Chip8Error::StackOverflow { pc:0x200 });
+    assert_eq!(error_after(&[0x00, 0xEE], 0), Chip8Error::StackUnderflow { pc:0x200 });
+    assert_eq!(error_after(&[0x60, 0x00, 0xFF, 0xFF], 1), Chip8Error::InvalidOpcode { pc:0x202, opcode:0xFFFF });
+
+    // I = 0xFFF, store V0..V1 across the end of memory
+    assert_eq!(error_after(&[0xAF, 0xFF, 0xF1, 0x55], 1), Chip8Error::MemoryOutOfBounds { addr:0x1000 });
+
+    // Jump to the last byte, so the fetch runs past the end
+    assert_eq!(error_after(&[0x1F, 0xFF], 1), Chip8Error::MemoryOutOfBounds { addr:0x1000 });
+
+    let mut chip8 = Chip8::new();
+    assert_eq!(chip8.load_rom(&[0x0;0xE01]), Err(Chip8Error::RomTooLarge { size:0xE01, max:0xE00 }));
+  }
+
+  #[test]
+  fn undefined_opcodes_follow_the_policy()
+  {
+    let rom = [0xFF, 0xFF, 0x60, 0x01];
+
+    let mut chip8 = Chip8::new();
+    chip8.set_undefined_opcode_policy(UndefinedOpcodePolicy::Skip);
+    let mut chip8 = run(chip8, &rom, 2);
+    assert_eq!(chip8.regs().V[0x0], 0x01);
+    assert!(chip8.take_skipped_opcodes().is_empty());
+
+    let mut chip8 = Chip8::new();
+    chip8.set_undefined_opcode_policy(UndefinedOpcodePolicy::Log);
+    let mut chip8 = run(chip8, &rom, 2);
+    assert_eq!(chip8.regs().V[0x0], 0x01);
+    assert_eq!(chip8.take_skipped_opcodes(), vec![Chip8Error::InvalidOpcode { pc:0x200, opcode:0xFFFF }]);
+    assert!(chip8.take_skipped_opcodes().is_empty());
+  }
+
+  #[test]
+  fn key_wait_suspends_until_a_key_event()
+  {
+    // Wait for a key into V3, then V0 = 0x01
+    let rom = [0xF3, 0x0A, 0x60, 0x01];
+
+    let mut chip8 = run(Chip8::with_quirks(Quirks { key_wait_on_release:false, ..Quirks::legacy() }), &rom, 3);
+    assert_eq!(chip8.key_wait(), Some(KeyWait { x:0x3, pressed:None }));
+    assert_eq!(chip8.regs().PC, 0x200);
+
+    chip8.press(0xB);
+    assert_eq!(chip8.key_wait(), None);
+    assert_eq!(chip8.regs().V[0x3], 0xB);
+
+    chip8.emulate_cycle().unwrap();
+    assert_eq!(chip8.regs().V[0x0], 0x01);
+
+    let mut chip8 = run(Chip8::with_quirks(Quirks { key_wait_on_release:true, ..Quirks::legacy() }), &rom, 1);
+
+    chip8.press(0xB);
+    chip8.press(0x2);
+    chip8.release(0x2);
+    chip8.emulate_cycle().unwrap();
+    assert_eq!(chip8.key_wait(), Some(KeyWait { x:0x3, pressed:Some(0xB) }));
+    assert_eq!(chip8.regs().PC, 0x200);
+
+    chip8.release(0xB);
+    assert_eq!(chip8.key_wait(), None);
+    assert_eq!((chip8.regs().V[0x3], chip8.regs().PC), (0xB, 0x202));
+  }
+
+  #[test]
+  fn keys_are_held_independently()
+  {
+    // VA = 0x1, VB = 0xF; V0 = 1 unless key VA is down, V1 = 1 unless key
+    // VB is down, V2 = 1 unless key VA is up
+    let rom = [0x6A, 0x01, 0x6B, 0x0F, 0xEA, 0x9E, 0x60, 0x01, 0xEB, 0x9E, 0x61, 0x01, 0xEA, 0xA1, 0x62, 0x01];
+
+    let mut chip8 = run(Chip8::new(), &rom, 0);
+
+    chip8.press(0x1);
+    chip8.press(0xF);
+    assert_eq!(chip8.keypad(), 0x8002);
+
+    chip8.release(0x1);
+    assert_eq!(chip8.keypad(), 0x8000);
+
+    chip8.press(0x1);
+
+    for _ in 0..6
+    {
+      chip8.emulate_cycle().unwrap();
+    }
+
+    assert_eq!(&chip8.regs().V[0x0..0x3], &[0x0, 0x0, 0x1]);
+
+    chip8.set_keypad(0x0003);
+    assert_eq!(chip8.keypad(), 0x0003);
+  }
+
+  // Always the same byte.
+  struct FixedRng(u8);
+
+  impl RandomSource for FixedRng
+  {
+    fn next_byte(&mut self) -> u8
+    {
+      self.0
+    }
+  }
+
+  #[test]
+  fn random_numbers_repeat_under_a_seed()
+  {
+    // V0..V3 = random, the last masked to 0x0F
+    let rom = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF, 0xC3, 0x0F];
+
+    let first   = run(Chip8::with_seed(42), &rom, 4);
+    let second  = run(Chip8::with_seed(42), &rom, 4);
+    assert_eq!(first.regs().V, second.regs().V);
+    assert!(first.regs().V[0x3] <= 0x0F);
+
+    let mut reseeded = Chip8::with_seed(7);
+    reseeded.reseed(42);
+    let reseeded = run(reseeded, &rom, 4);
+    assert_eq!(reseeded.regs().V, first.regs().V);
+
+    let other = run(Chip8::with_seed(43), &rom, 4);
+    assert_ne!(other.regs().V[0x0..0x3], first.regs().V[0x0..0x3]);
+
+    let fixed = run(Chip8::with_rng(Box::new(FixedRng(0xAB))), &rom, 4);
+    assert_eq!(&fixed.regs().V[0x0..0x4], &[0xAB, 0xAB, 0xAB, 0x0B]);
+  }
+}
@@ -0,0 +1,27 @@
+//! Argument handling shared by the command line tools.
+
+use std::fmt::Display;
+use std::process;
+use std::str::FromStr;
+
+/// Print `message` on stderr and exit with status 1.
+pub fn fail(message:String) -> !
+{
+  eprintln!("{}", message);
+  process::exit(1);
+}
+
+/// Parse the value following the option at args[*idx], failing with the
+/// parse error if it is malformed or with `usage` if it is missing.
+pub fn option_value<T>(args:&[String], idx:&mut usize, usage:&str) -> T
+  where T:FromStr, T::Err:Display
+{
+  *idx += 1;
+
+  match args.get(*idx).map(|arg| arg.parse::<T>())
+  {
+    Some(Ok(value)) => value,
+    Some(Err(err))  => fail(err.to_string()),
+    None            => fail(usage.to_string()),
+  }
+}
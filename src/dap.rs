@@ -0,0 +1,1012 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::chip8::Chip8;
+use crate::debugger::{parse_number, Debugger};
+use crate::instruction::Instruction;
+use crate::quirks::Platform;
+use crate::scheduler::Scheduler;
+use crate::symbols::SymbolMap;
+use crate::watch::Register;
+
+// The machine is presented as a single thread.
+const THREAD_ID:i64 = 1;
+
+// Variable references of the two scopes.
+const REGISTERS_REF:i64 = 1;
+const STACK_REF:i64     = 2;
+
+const BASE64_ALPHABET:&[u8;64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes:&[u8]) -> String
+{
+  let mut out = String::with_capacity(4*bytes.len().div_ceil(3));
+
+  for chunk in bytes.chunks(3)
+  {
+    let word = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+
+    for idx in 0..4
+    {
+      if idx <= chunk.len()
+      {
+        out.push(BASE64_ALPHABET[(word >> (18 - 6*idx) & 0x3F) as usize] as char);
+      }
+      else
+      {
+        out.push('=');
+      }
+    }
+  }
+
+  out
+}
+
+fn base64_decode(text:&str) -> Option<Vec<u8>>
+{
+  let mut out  = Vec::with_capacity(3*text.len()/4);
+  let mut word = 0u32;
+  let mut bits = 0;
+
+  for byte in text.bytes().filter(|&byte| byte != b'=')
+  {
+    let value = BASE64_ALPHABET.iter().position(|&digit| digit == byte)? as u32;
+
+    word  = word << 6 | value;
+    bits += 6;
+
+    if bits >= 8
+    {
+      bits -= 8;
+      out.push((word >> bits) as u8);
+    }
+  }
+
+  Some(out)
+}
+
+// Read one "Content-Length: N" framed message.
+fn read_message<R:BufRead>(reader:&mut R) -> io::Result<Option<Value>>
+{
+  let mut length = None;
+
+  loop
+  {
+    let mut header = String::new();
+
+    if reader.read_line(&mut header)? == 0
+    {
+      return Ok(None);
+    }
+
+    let header = header.trim();
+
+    if header.is_empty()
+    {
+      break;
+    }
+
+    if let Some(value) = header.strip_prefix("Content-Length:")
+    {
+      length = value.trim().parse::<usize>().ok();
+    }
+  }
+
+  let mut body = vec![0u8;length.unwrap_or(0)];
+
+  reader.read_exact(&mut body)?;
+
+  // A malformed body is dropped rather than ending the session.
+  Ok(Some(serde_json::from_slice(&body).unwrap_or(Value::Null)))
+}
+
+fn memory_reference(addr:u16) -> String
+{
+  format!("{:#X}", addr)
+}
+
+/// What a `launch` request asked to run.
+#[derive(Debug, Clone)]
+pub struct LaunchArgs
+{
+  pub program: String,
+  pub platform:Option<Platform>,
+}
+
+/// A Debug Adapter Protocol server on stdin/stdout, for debugging from an
+/// editor. Like `GdbStub` it drives the machine through a `Debugger`: the
+/// frontend polls it between frames and reports where `run_frame` stopped.
+/// With a symbol map from the assembler, breakpoints can be set on source
+/// lines and the call stack shows labels and lines.
+pub struct DapServer
+{
+  requests:               Receiver<Value>, // Messages read from stdin
+  output:                 Box<dyn Write>, // Where messages go, stdout but for tests
+  seq:                    i64,
+  symbols:                SymbolMap,
+  symbols_dir:            PathBuf, // Source paths in the map are relative to this
+  stop_on_entry:          bool,
+  source_breakpoints:     BTreeMap<String, Vec<u16>>, // By source path
+  function_breakpoints:   Vec<u16>,
+  instruction_breakpoints:Vec<u16>,
+  step_target:            Option<u16>, // Temporary breakpoint ending a step over or out
+  added_breakpoints:      BTreeSet<u16>, // Debugger breakpoints set by us, not the debug console
+  pending_stop:           Option<(&'static str, String)>, // Stopped event to send after the response
+  finished:               bool,
+}
+
+impl DapServer
+{
+  /// Start reading requests from stdin.
+  pub fn start() -> Self
+  {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move ||
+    {
+      let stdin = io::stdin();
+      let mut reader = stdin.lock();
+
+      while let Ok(Some(message)) = read_message(&mut reader)
+      {
+        if sender.send(message).is_err()
+        {
+          break;
+        }
+      }
+    });
+
+    DapServer::new(receiver, Box::new(io::stdout()))
+  }
+
+  // A server answering `requests` on `output`.
+  fn new(requests:Receiver<Value>, output:Box<dyn Write>) -> Self
+  {
+    DapServer { requests,
+                output,
+                seq:                    1,
+                symbols:                SymbolMap::new(),
+                symbols_dir:            PathBuf::new(),
+                stop_on_entry:          false,
+                source_breakpoints:     BTreeMap::new(),
+                function_breakpoints:   Vec::new(),
+                instruction_breakpoints:Vec::new(),
+                step_target:            None,
+                added_breakpoints:      BTreeSet::new(),
+                pending_stop:           None,
+                finished:               false,
+              }
+  }
+
+  /// True once the client disconnected.
+  pub fn finished(&self) -> bool
+  {
+    self.finished
+  }
+
+  fn send(&mut self, mut message:Value)
+  {
+    message["seq"] = json!(self.seq);
+    self.seq += 1;
+
+    let body = message.to_string();
+
+    // In one write, so status lines on stdout can't land mid-message.
+    let _ = self.output.write_all(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes());
+    let _ = self.output.flush();
+  }
+
+  fn send_event(&mut self, event:&str, body:Value)
+  {
+    self.send(json!({ "type":"event", "event":event, "body":body }));
+  }
+
+  fn respond(&mut self, request:&Value, result:Result<Value, String>)
+  {
+    let mut response = json!({ "type":        "response",
+                               "request_seq": request["seq"],
+                               "command":     request["command"],
+                               "success":     result.is_ok() });
+
+    match result
+    {
+      Ok(body)     => response["body"]    = body,
+      Err(message) => response["message"] = json!(message),
+    }
+
+    self.send(response);
+  }
+
+  fn capabilities() -> Value
+  {
+    json!({ "supportsConfigurationDoneRequest":   true,
+            "supportsFunctionBreakpoints":        true,
+            "supportsInstructionBreakpoints":     true,
+            "supportsSetVariable":                true,
+            "supportsReadMemoryRequest":          true,
+            "supportsWriteMemoryRequest":         true,
+            "supportsDisassembleRequest":         true,
+            "supportsTerminateRequest":           true,
+            "supportsStepBack":                   true,
+            "exceptionBreakpointFilters":
+              [{ "filter":"undefined", "label":"Undefined opcodes", "default":true }] })
+  }
+
+  /// Answer requests until the client launches a program, and return
+  /// what to launch. None if the client went away first.
+  pub fn wait_for_launch(&mut self) -> Option<LaunchArgs>
+  {
+    while let Ok(request) = self.requests.recv()
+    {
+      match request["command"].as_str()
+      {
+        Some("initialize") => self.respond(&request, Ok(DapServer::capabilities())),
+        Some("launch") =>
+        {
+          match self.launch(&request["arguments"])
+          {
+            Ok(launch) =>
+            {
+              self.respond(&request, Ok(json!({})));
+
+              // Breakpoints and the rest of the configuration follow.
+              self.send_event("initialized", json!({}));
+
+              return Some(launch);
+            },
+            Err(err) => self.respond(&request, Err(err)),
+          }
+        },
+        Some("disconnect") | Some("terminate") =>
+        {
+          self.respond(&request, Ok(json!({})));
+          return None;
+        },
+        Some(_) => self.respond(&request, Err("no program launched".to_string())),
+        None    => {},
+      }
+    }
+
+    None
+  }
+
+  // Launch arguments: "program" is the ROM, "symbols" an optional symbol
+  // map, "platform" the platform preset and "stopOnEntry" whether to
+  // pause before the first instruction.
+  fn launch(&mut self, args:&Value) -> Result<LaunchArgs, String>
+  {
+    let program = args["program"].as_str().ok_or("missing program")?.to_string();
+
+    if !Path::new(&program).is_file()
+    {
+      return Err(format!("Can't read {}", program));
+    }
+
+    let platform = match args["platform"].as_str()
+    {
+      Some(platform) => Some(platform.parse::<Platform>()?),
+      None           => None,
+    };
+
+    if let Some(path) = args["symbols"].as_str()
+    {
+      self.symbols     = SymbolMap::load(path).map_err(|err| format!("Can't load {}: {}", path, err))?;
+      self.symbols_dir = Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default();
+    }
+
+    self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+    Ok(LaunchArgs { program, platform })
+  }
+
+  /// Handle the requests that arrived since the last call.
+  pub fn poll(&mut self, chip8:&mut Chip8, scheduler:&mut Scheduler, debugger:&mut Debugger)
+  {
+    loop
+    {
+      let request = match self.requests.try_recv()
+      {
+        Ok(request)                     => request,
+        Err(TryRecvError::Empty)        => break,
+        Err(TryRecvError::Disconnected) => { self.finished = true; break; },
+      };
+
+      let command = match request["command"].as_str()
+      {
+        Some(command) => command.to_string(),
+        None          => continue,
+      };
+
+      let result = self.handle(&command, &request["arguments"], chip8, scheduler, debugger);
+
+      self.respond(&request, result);
+
+      if let Some((reason, description)) = self.pending_stop.take()
+      {
+        self.send_stopped(reason, &description);
+      }
+    }
+  }
+
+  fn send_stopped(&mut self, reason:&str, description:&str)
+  {
+    self.send_event("stopped", json!({ "reason":            reason,
+                                       "description":       description,
+                                       "text":              description,
+                                       "threadId":          THREAD_ID,
+                                       "allThreadsStopped": true }));
+  }
+
+  /// Tell the client the debugger stopped, `description` saying why.
+  pub fn notify_stop(&mut self, chip8:&Chip8, debugger:&mut Debugger, description:&str)
+  {
+    let PC = chip8.regs().PC;
+
+    let reason = if self.step_target.take().is_some_and(|target| target == PC)
+    {
+      "step"
+    }
+    else if !chip8.watch_hits().is_empty() || chip8.execute_watch_hit().is_some()
+    {
+      "data breakpoint"
+    }
+    else if debugger.breakpoints().any(|addr| addr == PC)
+    {
+      "breakpoint"
+    }
+    else
+    {
+      "exception"
+    };
+
+    // A step over or out is done, wherever it stopped.
+    self.sync_breakpoints(debugger);
+
+    self.send_stopped(reason, description);
+  }
+
+  /// Tell the client the program ended.
+  pub fn terminate(&mut self)
+  {
+    if !self.finished
+    {
+      self.send_event("terminated", json!({}));
+      self.send_event("exited", json!({ "exitCode":0 }));
+    }
+  }
+
+  // Make the debugger break on everything the client set, plus the
+  // target of a step in progress. Breakpoints set from the debug console
+  // are left alone: only the ones we added are ever removed.
+  fn sync_breakpoints(&mut self, debugger:&mut Debugger)
+  {
+    let all:BTreeSet<u16> = self.source_breakpoints.values().flatten()
+                                .chain(self.function_breakpoints.iter())
+                                .chain(self.instruction_breakpoints.iter())
+                                .chain(self.symbols.breakpoints.values())
+                                .chain(self.step_target.iter())
+                                .copied()
+                                .collect();
+
+    for &addr in self.added_breakpoints.difference(&all)
+    {
+      debugger.remove_breakpoint(addr);
+    }
+
+    let existing:BTreeSet<u16> = debugger.breakpoints().collect();
+
+    // Ours are the ones we add now, and the ones we added before that are
+    // still wanted.
+    self.added_breakpoints = all.iter()
+                                .copied()
+                                .filter(|addr| self.added_breakpoints.contains(addr) || !existing.contains(addr))
+                                .collect();
+
+    for &addr in all.iter()
+    {
+      debugger.add_breakpoint(addr);
+    }
+  }
+
+  // Resolve a function breakpoint name, a label or an address.
+  fn resolve(&self, name:&str) -> Option<u16>
+  {
+    match self.symbols.labels.get(name)
+    {
+      Some(&addr) => Some(addr),
+      None        => parse_number(name).ok().filter(|&addr| addr <= 0xFFFF).map(|addr| addr as u16),
+    }
+  }
+
+  // An address from a memory reference plus an optional byte offset.
+  fn address(args:&Value, reference:&str, offset:&str) -> Result<i64, String>
+  {
+    let base = parse_number(args[reference].as_str().ok_or("missing memory reference")?)?;
+
+    Ok(base as i64 + args[offset].as_i64().unwrap_or(0))
+  }
+
+  fn source(&self, file:&str) -> Value
+  {
+    let path = self.symbols_dir.join(file);
+
+    json!({ "name": Path::new(file).file_name().map(|name| name.to_string_lossy()),
+            "path": path.to_string_lossy() })
+  }
+
+  fn stack_frame(&self, chip8:&Chip8, id:usize, addr:u16) -> Value
+  {
+    let name = match chip8.instruction_at(addr as usize)
+    {
+      Some(instruction) => format!("{}: {}", self.symbols.describe(addr), instruction),
+      None              => self.symbols.describe(addr),
+    };
+
+    let mut frame = json!({ "id":                          id,
+                            "name":                        name,
+                            "line":                        0,
+                            "column":                      0,
+                            "instructionPointerReference": memory_reference(addr) });
+
+    if let Some(line) = self.symbols.line_of(addr)
+    {
+      frame["source"] = self.source(&line.file);
+      frame["line"]   = json!(line.line);
+      frame["column"] = json!(1);
+    }
+
+    frame
+  }
+
+  fn variables(chip8:&Chip8, reference:i64) -> Value
+  {
+    let regs = chip8.regs();
+
+    let variable = |name:String, value:String| json!({ "name":name, "value":value, "variablesReference":0 });
+
+    let pointer = |name:&str, addr:u16|
+    {
+      json!({ "name":name, "value":format!("{:#05X}", addr), "variablesReference":0,
+              "memoryReference":memory_reference(addr) })
+    };
+
+    let mut variables = Vec::new();
+
+    if reference == REGISTERS_REF
+    {
+      for (idx, value) in regs.V.iter().enumerate()
+      {
+        variables.push(variable(format!("V{:X}", idx), format!("{:#04X}", value)));
+      }
+
+      variables.push(pointer("I", regs.I));
+      variables.push(pointer("PC", regs.PC));
+      variables.push(variable("SP".to_string(), chip8.stack().sp.to_string()));
+      variables.push(variable("DT".to_string(), format!("{:#04X}", regs.DELAY_TIMER)));
+      variables.push(variable("ST".to_string(), format!("{:#04X}", regs.SOUND_TIMER)));
+    }
+    else if reference == STACK_REF
+    {
+      let stack = chip8.stack();
+
+      for (idx, &addr) in stack.stack[..stack.sp as usize].iter().enumerate()
+      {
+        variables.push(pointer(&idx.to_string(), addr));
+      }
+    }
+
+    json!({ "variables":variables })
+  }
+
+  fn handle(&mut self, command:&str, args:&Value, chip8:&mut Chip8, scheduler:&mut Scheduler,
+            debugger:&mut Debugger) -> Result<Value, String>
+  {
+    let PC = chip8.regs().PC;
+
+    let body = match command
+    {
+      "initialize" => DapServer::capabilities(),
+
+      "setBreakpoints" =>
+      {
+        let path = args["source"]["path"].as_str().ok_or("missing source path")?.to_string();
+
+        let mut addrs       = Vec::new();
+        let mut breakpoints = Vec::new();
+
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten()
+        {
+          let line = breakpoint["line"].as_u64().unwrap_or(0) as u32;
+
+          // A line without code gets the breakpoint of the next line that
+          // has some.
+          match self.symbols.code_line(&path, line)
+          {
+            Some(code) =>
+            {
+              addrs.push(code.addr);
+              breakpoints.push(json!({ "verified":true, "line":code.line,
+                                       "instructionReference":memory_reference(code.addr) }));
+            },
+            None => breakpoints.push(json!({ "verified":false, "line":line,
+                                             "message":"No code at or after this line" })),
+          }
+        }
+
+        self.source_breakpoints.insert(path, addrs);
+        self.sync_breakpoints(debugger);
+
+        json!({ "breakpoints":breakpoints })
+      },
+
+      "setFunctionBreakpoints" =>
+      {
+        self.function_breakpoints.clear();
+
+        let mut breakpoints = Vec::new();
+
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten()
+        {
+          let name = breakpoint["name"].as_str().unwrap_or("");
+
+          match self.resolve(name)
+          {
+            Some(addr) =>
+            {
+              self.function_breakpoints.push(addr);
+              breakpoints.push(json!({ "verified":true, "instructionReference":memory_reference(addr) }));
+            },
+            None => breakpoints.push(json!({ "verified":false, "message":format!("No label '{}'", name) })),
+          }
+        }
+
+        self.sync_breakpoints(debugger);
+
+        json!({ "breakpoints":breakpoints })
+      },
+
+      "setInstructionBreakpoints" =>
+      {
+        self.instruction_breakpoints.clear();
+
+        let mut breakpoints = Vec::new();
+
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten()
+        {
+          match DapServer::address(breakpoint, "instructionReference", "offset")
+          {
+            Ok(addr) if (0..=0xFFFF).contains(&addr) =>
+            {
+              self.instruction_breakpoints.push(addr as u16);
+              breakpoints.push(json!({ "verified":true, "instructionReference":memory_reference(addr as u16) }));
+            },
+            _ => breakpoints.push(json!({ "verified":false, "message":"Invalid address" })),
+          }
+        }
+
+        self.sync_breakpoints(debugger);
+
+        json!({ "breakpoints":breakpoints })
+      },
+
+      "setExceptionBreakpoints" =>
+      {
+        let filters = args["filters"].as_array().into_iter().flatten();
+
+        debugger.set_break_on_undefined(filters.filter_map(Value::as_str).any(|filter| filter == "undefined"));
+
+        json!({})
+      },
+
+      "configurationDone" =>
+      {
+        self.sync_breakpoints(debugger);
+
+        if self.stop_on_entry
+        {
+          debugger.pause();
+          self.pending_stop = Some(("entry", String::new()));
+        }
+        else
+        {
+          debugger.resume();
+        }
+
+        json!({})
+      },
+
+      "threads" => json!({ "threads":[{ "id":THREAD_ID, "name":"CHIP-8" }] }),
+
+      "stackTrace" =>
+      {
+        // The current instruction, then the calls that led to it.
+        let stack = chip8.stack();
+
+        let frames:Vec<Value> = std::iter::once(PC)
+                                  .chain(stack.stack[..stack.sp as usize].iter().rev().cloned())
+                                  .enumerate()
+                                  .map(|(id, addr)| self.stack_frame(chip8, id, addr))
+                                  .collect();
+
+        json!({ "totalFrames":frames.len(), "stackFrames":frames })
+      },
+
+      "scopes" =>
+      {
+        json!({ "scopes":[{ "name":"Registers", "presentationHint":"registers",
+                            "variablesReference":REGISTERS_REF, "expensive":false },
+                          { "name":"Stack", "variablesReference":STACK_REF, "expensive":false }] })
+      },
+
+      "variables" => DapServer::variables(chip8, args["variablesReference"].as_i64().unwrap_or(0)),
+
+      "setVariable" =>
+      {
+        let name  = args["name"].as_str().ok_or("missing name")?;
+        let value = args["value"].as_str().ok_or("missing value")?;
+
+        match args["variablesReference"].as_i64()
+        {
+          Some(STACK_REF) =>
+          {
+            let idx  = name.parse::<usize>().map_err(|_| "unknown stack entry")?;
+            let addr = parse_number(value)? as u16;
+
+            if idx >= chip8.stack().sp as usize
+            {
+              return Err("unknown stack entry".to_string());
+            }
+
+            chip8.stack_mut().stack[idx] = addr;
+
+            json!({ "value":format!("{:#05X}", addr) })
+          },
+          _ if name == "SP" =>
+          {
+            chip8.stack_mut().sp = (parse_number(value)? as u16).min(16);
+
+            json!({ "value":chip8.stack().sp.to_string() })
+          },
+          _ =>
+          {
+            debugger.execute(&format!("set {} {}", name, value), chip8, scheduler)?;
+
+            // Show the value as it was stored, i.e. truncated.
+            let variables = DapServer::variables(chip8, REGISTERS_REF);
+            let variable  = variables["variables"].as_array().into_iter().flatten().find(|var| var["name"] == name);
+
+            json!({ "value":variable.map(|var| var["value"].clone()).unwrap_or_default() })
+          },
+        }
+      },
+
+      "readMemory" =>
+      {
+        let addr   = DapServer::address(args, "memoryReference", "offset")?;
+        let count  = args["count"].as_i64().unwrap_or(0);
+        let memory = &chip8.memory().memory;
+
+        let start  = addr.clamp(0, memory.len() as i64);
+        let end    = (addr + count).clamp(start, memory.len() as i64);
+
+        json!({ "address":         format!("{:#X}", start),
+                "data":            base64_encode(&memory[start as usize..end as usize]),
+                "unreadableBytes": count - (end - start) })
+      },
+
+      "writeMemory" =>
+      {
+        let addr = DapServer::address(args, "memoryReference", "offset")?;
+        let data = base64_decode(args["data"].as_str().unwrap_or("")).ok_or("invalid base64 data")?;
+
+        if addr < 0 || addr as usize + data.len() > chip8.memory().size()
+        {
+          return Err("write outside of memory".to_string());
+        }
+
+        chip8.memory_mut().memory[addr as usize..addr as usize + data.len()].copy_from_slice(&data);
+
+        json!({ "bytesWritten":data.len() })
+      },
+
+      "disassemble" =>
+      {
+        // Instructions are taken to be 2 bytes apart, which only the
+        // 4 byte XO-CHIP F000 NNNN breaks.
+        let start = DapServer::address(args, "memoryReference", "offset")? +
+                    2*args["instructionOffset"].as_i64().unwrap_or(0);
+        let count = args["instructionCount"].as_i64().unwrap_or(0);
+
+        let instructions:Vec<Value> = (0..count).map(|idx|
+        {
+          let addr = start + 2*idx;
+
+          let instruction = if (0..=0xFFFF).contains(&addr) { chip8.instruction_at(addr as usize) } else { None };
+
+          match instruction
+          {
+            Some(instruction) =>
+            {
+              let addr = addr as u16;
+
+              let mut entry = json!({ "address":          memory_reference(addr),
+                                      "instructionBytes": format!("{:04X}", instruction.encode()),
+                                      "instruction":      instruction.to_string() });
+
+              if let Some((label, 0)) = self.symbols.label_before(addr)
+              {
+                entry["symbol"] = json!(label);
+              }
+
+              if let Some(line) = self.symbols.line_of(addr)
+              {
+                entry["location"] = self.source(&line.file);
+                entry["line"]     = json!(line.line);
+              }
+
+              entry
+            },
+            None => json!({ "address":format!("{:#X}", addr.max(0)), "instruction":"??",
+                            "presentationHint":"invalid" }),
+          }
+        }).collect();
+
+        json!({ "instructions":instructions })
+      },
+
+      "continue" =>
+      {
+        debugger.resume();
+
+        json!({ "allThreadsContinued":true })
+      },
+
+      "next" | "stepOut" =>
+      {
+        // Run to the instruction after a call, or after the call that got
+        // us here.
+        let target = match command
+        {
+          "next" => match chip8.instruction_at(PC as usize)
+          {
+            Some(Instruction::Call { .. }) => Some(PC.wrapping_add(2)),
+            _                             => None,
+          },
+          _ => chip8.stack().stack[..chip8.stack().sp as usize].last().map(|&call| call.wrapping_add(2)),
+        };
+
+        match target
+        {
+          Some(target) =>
+          {
+            self.step_target = Some(target);
+            self.sync_breakpoints(debugger);
+
+            debugger.resume();
+          },
+          None =>
+          {
+            debugger.step(chip8, scheduler)?;
+            self.pending_stop = Some(("step", String::new()));
+          },
+        }
+
+        json!({})
+      },
+
+      "stepIn" =>
+      {
+        debugger.step(chip8, scheduler)?;
+        self.pending_stop = Some(("step", String::new()));
+
+        json!({})
+      },
+
+      "stepBack" =>
+      {
+        debugger.reverse_step(chip8)?;
+        self.pending_stop = Some(("step", String::new()));
+
+        json!({})
+      },
+
+      "reverseContinue" =>
+      {
+        let description = debugger.reverse_continue(chip8);
+        let PC          = chip8.regs().PC;
+
+        let reason = if chip8.journal().is_empty()
+        {
+          "step"
+        }
+        else if debugger.breakpoints().any(|addr| addr == PC)
+        {
+          "breakpoint"
+        }
+        else
+        {
+          "data breakpoint"
+        };
+
+        self.pending_stop = Some((reason, description));
+
+        json!({})
+      },
+
+      "pause" =>
+      {
+        debugger.pause();
+        self.pending_stop = Some(("pause", String::new()));
+
+        json!({})
+      },
+
+      "evaluate" =>
+      {
+        let expression = args["expression"].as_str().unwrap_or("").trim();
+
+        if args["context"] == "repl"
+        {
+          // The debug console takes the same commands as --debug.
+          let was_paused = debugger.paused();
+          let result     = debugger.execute(expression, chip8, scheduler)?;
+
+          if chip8.regs().PC != PC
+          {
+            self.pending_stop = Some(("step", String::new()));
+          }
+          else if was_paused && !debugger.paused()
+          {
+            self.send_event("continued", json!({ "threadId":THREAD_ID, "allThreadsContinued":true }));
+          }
+
+          json!({ "result":result.trim(), "variablesReference":0 })
+        }
+        else
+        {
+          // Hovers and watches: a register or a label.
+          let value = match expression.to_uppercase().as_str()
+          {
+            "PC" => PC,
+            "SP" => chip8.stack().sp,
+            _    => match expression.parse::<Register>()
+            {
+              Ok(register) => register.get(chip8.regs()),
+              Err(_)       => *self.symbols.labels.get(expression).ok_or("not a register or label")?,
+            },
+          };
+
+          json!({ "result":format!("{:#X}", value), "variablesReference":0 })
+        }
+      },
+
+      "disconnect" | "terminate" =>
+      {
+        self.finished = true;
+
+        json!({})
+      },
+
+      _ => return Err(format!("unsupported request '{}'", command)),
+    };
+
+    Ok(body)
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use std::cell::RefCell;
+  use std::io::Cursor;
+  use std::rc::Rc;
+
+  use super::*;
+  use crate::scheduler::Speed;
+
+  // Output the test can read back after the server wrote it.
+  #[derive(Clone, Default)]
+  struct Output(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for Output
+  {
+    fn write(&mut self, buf:&[u8]) -> io::Result<usize>
+    {
+      self.0.borrow_mut().extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+      Ok(())
+    }
+  }
+
+  // Every message in `bytes`, decoded from its Content-Length framing.
+  fn read_all(bytes:&[u8]) -> Vec<Value>
+  {
+    let mut reader   = Cursor::new(bytes);
+    let mut messages = Vec::new();
+
+    while let Some(message) = read_message(&mut reader).unwrap()
+    {
+      messages.push(message);
+    }
+
+    messages
+  }
+
+  #[test]
+  fn base64_round_trips()
+  {
+    for len in 0..8
+    {
+      let bytes:Vec<u8> = (0..len).map(|idx| 0xF0u8.wrapping_add(idx * 37)).collect();
+
+      assert_eq!(base64_decode(&base64_encode(&bytes)), Some(bytes));
+    }
+
+    assert_eq!(base64_encode(b"CHIP"), "Q0hJUA==");
+    assert_eq!(base64_decode("Q0hJ!A=="), None);
+  }
+
+  #[test]
+  fn requests_get_responses_and_stopped_events()
+  {
+    let (sender, receiver) = mpsc::channel();
+    let output             = Output::default();
+
+    let mut server = DapServer::new(receiver, Box::new(output.clone()));
+
+    let mut chip8 = Chip8::new();
+    chip8.initialize();
+    chip8.load_rom(&[0x6A, 0x02, 0x12, 0x02]).unwrap();
+
+    let mut scheduler = Scheduler::new(Speed::InstructionsPerFrame(10));
+    let mut debugger  = Debugger::new();
+
+    // Requests arrive framed, as on stdin.
+    let requests = [json!({ "seq":1, "type":"request", "command":"readMemory",
+                            "arguments":{ "memoryReference":"0x200", "count":4 } }),
+                    json!({ "seq":2, "type":"request", "command":"stepIn", "arguments":{ "threadId":THREAD_ID } }),
+                    json!({ "seq":3, "type":"request", "command":"bogus" })];
+
+    let framed:String = requests.iter().map(|request|
+    {
+      let body = request.to_string();
+
+      format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    })
+    .collect();
+
+    for request in read_all(framed.as_bytes())
+    {
+      sender.send(request).unwrap();
+    }
+
+    server.poll(&mut chip8, &mut scheduler, &mut debugger);
+
+    let messages = read_all(&output.0.borrow());
+
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages.iter().map(|message| message["seq"].as_i64()).collect::<Vec<_>>(),
+               vec![Some(1), Some(2), Some(3), Some(4)]);
+
+    assert_eq!(messages[0]["type"], "response");
+    assert_eq!(messages[0]["request_seq"], 1);
+    assert_eq!(messages[0]["success"], true);
+    assert_eq!(messages[0]["body"]["data"], base64_encode(&[0x6A, 0x02, 0x12, 0x02]));
+
+    assert_eq!((&messages[1]["command"], &messages[1]["success"]), (&json!("stepIn"), &json!(true)));
+    assert_eq!((&messages[2]["event"], &messages[2]["body"]["reason"]), (&json!("stopped"), &json!("step")));
+    assert_eq!(chip8.regs().PC, 0x202);
+
+    assert_eq!(messages[3]["request_seq"], 3);
+    assert_eq!(messages[3]["success"], false);
+  }
+}
@@ -0,0 +1,665 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::chip8::Chip8;
+use crate::instruction::Instruction;
+use crate::scheduler::Scheduler;
+use crate::symbols::SymbolMap;
+use crate::watch::{Access, Register, WatchTarget, Watchpoint};
+
+/// Help text for `Debugger::execute`.
+pub const DEBUGGER_HELP:&str = "\
+step [n]            execute n instructions (default 1)
+continue            run until a breakpoint
+reverse-step [n]    undo n instructions (default 1)
+reverse-continue    run backwards to the previous breakpoint or watchpoint hit
+break <addr>        stop before executing addr
+break undefined     stop before undefined opcodes (on by default)
+delete [addr]       remove a breakpoint, or all of them
+delete undefined    don't stop before undefined opcodes
+watch               list watchpoints
+watch <addr> [read|write|exec] [value]
+                    stop when addr is accessed (default write), optionally
+                    only with the given byte (or opcode for exec)
+watch <reg> [value] stop when an instruction changes V0-VF, I, DT or ST
+unwatch [n]         remove watchpoint n, or all of them
+regs                show the registers
+mem <addr> [len]    dump memory
+stack               show the call stack
+disasm [addr] [n]   disassemble n instructions from addr (default PC)
+set <reg> <value>   set V0-VF, PC, I, DT or ST
+Numbers are decimal, or hex with a 0x prefix. With a symbol map loaded,
+addresses can also be labels.";
+
+/// What `Debugger::run_frame` did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOutcome
+{
+  /// The debugger is paused; nothing ran.
+  Paused,
+  /// A whole frame ran.
+  Completed,
+  /// Execution stopped at a breakpoint or on an error, and the debugger
+  /// paused. The message says why.
+  Stopped(String),
+}
+
+/// A command-driven debugger for a `Chip8` run by a `Scheduler`. The
+/// frontend feeds it command lines and calls `run_frame` instead of
+/// `Scheduler::run_frame`; the debugger decides whether anything runs.
+pub struct Debugger
+{
+  breakpoints:        BTreeSet<u16>,
+  break_on_undefined: bool,
+  paused:             bool,
+  skip_hits:          bool, // Resuming: the watch hits were reported
+  skip_pc:            bool, // Resuming: don't stop before the instruction at PC again
+  symbols:            SymbolMap,
+}
+
+// Parse a number, decimal or 0x-prefixed hex.
+pub(crate) fn parse_number(arg:&str) -> Result<u32, String>
+{
+  let parsed = match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X"))
+  {
+    Some(hex) => u32::from_str_radix(hex, 16),
+    None      => arg.parse(),
+  };
+
+  parsed.map_err(|_| format!("invalid number '{}'", arg))
+}
+
+impl Debugger
+{
+  /// A debugger that starts paused, so breakpoints can be set before the
+  /// program runs.
+  pub fn new() -> Self
+  {
+    Debugger { breakpoints:       BTreeSet::new(),
+               break_on_undefined:true,
+               paused:            true,
+               skip_hits:         false,
+               skip_pc:           false,
+               symbols:           SymbolMap::new(),
+             }
+  }
+
+  /// Use the labels of `symbols` in addresses and listings, and stop at
+  /// its breakpoints.
+  pub fn set_symbols(&mut self, symbols:SymbolMap)
+  {
+    self.breakpoints.extend(symbols.breakpoints.values());
+    self.symbols = symbols;
+  }
+
+  pub fn symbols(&self) -> &SymbolMap
+  {
+    &self.symbols
+  }
+
+  // Parse an address, a number or a label.
+  fn parse_addr(&self, arg:Option<&&str>) -> Result<u16, String>
+  {
+    let arg = arg.ok_or("missing address")?;
+
+    if let Some(&addr) = self.symbols.labels.get(*arg)
+    {
+      return Ok(addr);
+    }
+
+    match parse_number(arg)?
+    {
+      addr if addr <= 0xFFFF => Ok(addr as u16),
+      _ => Err(format!("address '{}' out of range", arg)),
+    }
+  }
+
+  pub fn paused(&self) -> bool
+  {
+    self.paused
+  }
+
+  pub fn pause(&mut self)
+  {
+    self.paused = true;
+  }
+
+  /// Let `run_frame` run again, as with the continue command.
+  pub fn resume(&mut self)
+  {
+    self.paused = false;
+  }
+
+  pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_
+  {
+    self.breakpoints.iter().cloned()
+  }
+
+  pub fn add_breakpoint(&mut self, addr:u16)
+  {
+    self.breakpoints.insert(addr);
+  }
+
+  /// Returns false if there was no breakpoint at `addr`.
+  pub fn remove_breakpoint(&mut self, addr:u16) -> bool
+  {
+    self.breakpoints.remove(&addr)
+  }
+
+  pub fn set_break_on_undefined(&mut self, enabled:bool)
+  {
+    self.break_on_undefined = enabled;
+  }
+
+  // Why execution should stop before the instruction at PC, if it should.
+  // The flag is true if it's watchpoints the previous instruction hit,
+  // false if it's about the instruction at PC.
+  fn break_reason(&self, chip8:&Chip8) -> Option<(String, bool)>
+  {
+    let PC = chip8.regs().PC;
+
+    // Read, write and register watchpoints hit by the previous instruction.
+    if !self.skip_hits && !chip8.watch_hits().is_empty()
+    {
+      return Some((Debugger::describe_hits(chip8), true));
+    }
+
+    if self.skip_pc
+    {
+      return None;
+    }
+
+    if let Some(hit) = chip8.execute_watch_hit()
+    {
+      return Some((hit.to_string(), false));
+    }
+
+    if self.breakpoints.contains(&PC)
+    {
+      return Some((format!("Breakpoint at {:#05X}", PC), false));
+    }
+
+    match chip8.instruction_at(PC as usize)
+    {
+      Some(instruction) if self.break_on_undefined && !chip8.is_defined(instruction) =>
+      {
+        Some((format!("Undefined opcode {:#06X} at {:#05X}", instruction.encode(), PC), false))
+      },
+      _ => None,
+    }
+  }
+
+  /// Run (the rest of) one frame unless paused, stopping before any
+  /// breakpoint. Errors pause the debugger instead of ending the session.
+  pub fn run_frame(&mut self, chip8:&mut Chip8, scheduler:&mut Scheduler) -> RunOutcome
+  {
+    if self.paused
+    {
+      return RunOutcome::Paused;
+    }
+
+    let mut reason = None;
+
+    let result = scheduler.run_frame_until(chip8, |chip8|
+    {
+      // Idle cycles execute nothing, so there is nothing to stop before.
+      if chip8.idle()
+      {
+        return false;
+      }
+
+      reason = self.break_reason(chip8);
+
+      // Once an instruction runs, what stopped us last time is history.
+      if reason.is_none()
+      {
+        self.skip_hits = false;
+        self.skip_pc   = false;
+      }
+
+      reason.is_some()
+    });
+
+    self.paused = result != Ok(true);
+
+    match result
+    {
+      Ok(true)  => RunOutcome::Completed,
+      Ok(false) =>
+      {
+        let (reason, from_hits) = reason.unwrap_or_default();
+
+        // Resuming must get past what stopped us.
+        if from_hits { self.skip_hits = true; } else { self.skip_pc = true; }
+
+        RunOutcome::Stopped(reason)
+      },
+      Err(err) => RunOutcome::Stopped(format!("Stopped: {}", err)),
+    }
+  }
+
+  fn describe_hits(chip8:&Chip8) -> String
+  {
+    chip8.watch_hits().iter().map(|hit| hit.to_string()).collect::<Vec<_>>().join("\n")
+  }
+
+  /// Pause and execute a single instruction, finishing the frame (and
+  /// ticking the timers) if it was the frame's last one. Cycles spent
+  /// waiting for the display after a DXYN don't count.
+  pub fn step(&mut self, chip8:&mut Chip8, scheduler:&mut Scheduler) -> Result<(), String>
+  {
+    self.paused = true;
+
+    // Like after a stop, resuming doesn't stop again before the
+    // instruction the step ends on.
+    self.skip_hits = true;
+    self.skip_pc   = true;
+
+    let mut executed = false;
+
+    while !executed
+    {
+      scheduler.run_frame_until(chip8, |chip8|
+      {
+        if executed
+        {
+          return true;
+        }
+
+        executed = !chip8.waiting_for_vblank();
+
+        false
+      })
+      .map_err(|err| format!("Stopped: {}", err))?;
+    }
+
+    Ok(())
+  }
+
+  /// Pause and undo the last instruction, using the machine's undo
+  /// journal (see `Chip8::set_journal_budget`).
+  pub fn reverse_step(&mut self, chip8:&mut Chip8) -> Result<(), String>
+  {
+    self.paused = true;
+
+    chip8.step_back().ok_or("No earlier instruction in the journal")?;
+
+    // Running forward again starts with the instruction that was undone.
+    self.skip_hits = true;
+    self.skip_pc   = true;
+
+    Ok(())
+  }
+
+  /// Pause and undo instructions until one that hit a watchpoint, or
+  /// until PC is at a breakpoint or execute watchpoint. Returns why it
+  /// stopped.
+  pub fn reverse_continue(&mut self, chip8:&mut Chip8) -> String
+  {
+    self.paused    = true;
+    self.skip_hits = true;
+    self.skip_pc   = true;
+
+    let mut undone = false;
+
+    loop
+    {
+      let hits = match chip8.step_back()
+      {
+        Some(hits) => hits,
+        None if undone => return "Reached the start of the journal".to_string(),
+        None => return "No earlier instruction in the journal".to_string(),
+      };
+
+      undone = true;
+
+      if !hits.is_empty()
+      {
+        return hits.iter().map(|hit| hit.to_string()).collect::<Vec<_>>().join("\n");
+      }
+
+      let PC = chip8.regs().PC;
+
+      if let Some(hit) = chip8.execute_watch_hit()
+      {
+        return hit.to_string();
+      }
+
+      if self.breakpoints.contains(&PC)
+      {
+        return format!("Breakpoint at {:#05X}", PC);
+      }
+    }
+  }
+
+  /// Run one command line and return what to print.
+  pub fn execute(&mut self, line:&str, chip8:&mut Chip8, scheduler:&mut Scheduler) -> Result<String, String>
+  {
+    let args:Vec<&str> = line.split_whitespace().collect();
+
+    let mut out = String::new();
+
+    match args.as_slice()
+    {
+      [] => {},
+
+      ["help", ..] | ["h", ..] => out.push_str(DEBUGGER_HELP),
+
+      ["step", rest @ ..] | ["s", rest @ ..] =>
+      {
+        let count = match rest.first() { Some(arg) => parse_number(arg)?, None => 1 };
+
+        for _ in 0..count
+        {
+          self.step(chip8, scheduler)?;
+
+          if !chip8.watch_hits().is_empty()
+          {
+            let _ = writeln!(out, "{}", Debugger::describe_hits(chip8));
+            break;
+          }
+        }
+
+        self.disassemble(&mut out, chip8, chip8.regs().PC, 1);
+      },
+
+      ["continue"] | ["c"] =>
+      {
+        self.paused = false;
+      },
+
+      ["reverse-step", rest @ ..] | ["rs", rest @ ..] =>
+      {
+        let count = match rest.first() { Some(arg) => parse_number(arg)?, None => 1 };
+
+        for _ in 0..count
+        {
+          self.reverse_step(chip8)?;
+        }
+
+        self.disassemble(&mut out, chip8, chip8.regs().PC, 1);
+      },
+
+      ["reverse-continue"] | ["rc"] =>
+      {
+        let _ = writeln!(out, "{}", self.reverse_continue(chip8));
+
+        self.disassemble(&mut out, chip8, chip8.regs().PC, 1);
+      },
+
+      ["break", "undefined"] => self.break_on_undefined = true,
+
+      ["break", addr] | ["b", addr] =>
+      {
+        let addr = self.parse_addr(Some(addr))?;
+
+        self.add_breakpoint(addr);
+
+        let _ = write!(out, "Breakpoint at {:#05X}", addr);
+      },
+
+      ["break"] | ["b"] =>
+      {
+        for addr in self.breakpoints()
+        {
+          let _ = writeln!(out, "{:#05X}", addr);
+        }
+      },
+
+      ["delete", "undefined"] => self.break_on_undefined = false,
+
+      ["delete"] | ["d"] => self.breakpoints.clear(),
+
+      ["delete", addr] | ["d", addr] =>
+      {
+        let addr = self.parse_addr(Some(addr))?;
+
+        if !self.remove_breakpoint(addr)
+        {
+          return Err(format!("No breakpoint at {:#05X}", addr));
+        }
+      },
+
+      ["watch"] =>
+      {
+        for (idx, watchpoint) in chip8.watchpoints().iter().enumerate()
+        {
+          let _ = writeln!(out, "{}: {}", idx, watchpoint);
+        }
+      },
+
+      ["watch", target, rest @ ..] if rest.len() <= 2 =>
+      {
+        let watchpoint = match target.parse::<Register>()
+        {
+          Ok(register) if rest.len() <= 1 => Watchpoint::register(register),
+          Ok(_) => return Err("usage: watch <reg> [value]".to_string()),
+          Err(_) =>
+          {
+            let access = match rest.first().cloned()
+            {
+              Some("read")  | Some("r") => Access::Read,
+              Some("write") | Some("w") | None => Access::Write,
+              Some("exec")  | Some("x") => Access::Execute,
+              Some(other) => return Err(format!("unknown access '{}', use read, write or exec", other)),
+            };
+
+            Watchpoint::memory(self.parse_addr(Some(target))?, access)
+          },
+        };
+
+        let value = match &watchpoint.target
+        {
+          WatchTarget::Register(_)  => rest.first(),
+          WatchTarget::Memory { .. } => rest.get(1),
+        };
+
+        let watchpoint = match value
+        {
+          Some(value) => watchpoint.with_value(parse_number(value)? as u16),
+          None        => watchpoint,
+        };
+
+        chip8.add_watchpoint(watchpoint);
+
+        let _ = write!(out, "{}: {}", chip8.watchpoints().len() - 1, watchpoint);
+      },
+
+      ["unwatch"] => chip8.clear_watchpoints(),
+
+      ["unwatch", idx] =>
+      {
+        let idx = parse_number(idx)? as usize;
+
+        if chip8.remove_watchpoint(idx).is_none()
+        {
+          return Err(format!("No watchpoint {}", idx));
+        }
+      },
+
+      ["regs"] | ["r"] => { let _ = write!(out, "{}", chip8.regs()); },
+
+      ["mem", rest @ ..] | ["m", rest @ ..] =>
+      {
+        let addr  = self.parse_addr(rest.first())? as usize;
+        let len   = match rest.get(1) { Some(arg) => parse_number(arg)? as usize, None => 16 };
+        let end   = (addr + len).min(chip8.memory().size());
+
+        for row in (addr..end).step_by(16)
+        {
+          let _ = write!(out, "{:#06X}:", row);
+
+          for byte in chip8.memory().memory[row..(row + 16).min(end)].iter()
+          {
+            let _ = write!(out, " {:02X}", byte);
+          }
+
+          out.push('\n');
+        }
+      },
+
+      ["stack"] =>
+      {
+        let stack = chip8.stack();
+
+        if stack.sp == 0
+        {
+          out.push_str("Stack is empty");
+        }
+
+        // Innermost call first.
+        for level in (0..stack.sp as usize).rev()
+        {
+          let _ = writeln!(out, "#{} {:#05X}", level, stack.stack[level]);
+        }
+      },
+
+      ["disasm", rest @ ..] =>
+      {
+        let addr  = if rest.is_empty() { chip8.regs().PC } else { self.parse_addr(rest.first())? };
+        let count = match rest.get(1) { Some(arg) => parse_number(arg)? as usize, None => 10 };
+
+        self.disassemble(&mut out, chip8, addr, count);
+      },
+
+      ["set", reg, value] =>
+      {
+        let value = parse_number(value)?;
+        let regs  = chip8.regs_mut();
+
+        match reg.to_uppercase().as_str()
+        {
+          "PC" => regs.PC = value as u16,
+          "I"  => regs.I  = value as u16,
+          "DT" => regs.DELAY_TIMER = value as u8,
+          "ST" => regs.SOUND_TIMER = value as u8,
+          name =>
+          {
+            let idx = name.strip_prefix('V').and_then(|idx| u8::from_str_radix(idx, 16).ok())
+                          .filter(|idx| *idx < 16)
+                          .ok_or_else(|| format!("unknown register '{}'", reg))?;
+
+            regs.V[idx as usize] = value as u8;
+          },
+        }
+      },
+
+      _ => return Err(format!("unknown command '{}', try help", line.trim())),
+    }
+
+    Ok(out)
+  }
+
+  // List `count` instructions from `addr`, marking PC and breakpoints.
+  fn disassemble(&self, out:&mut String, chip8:&Chip8, addr:u16, count:usize)
+  {
+    let mut addr = addr as usize;
+
+    for _ in 0..count
+    {
+      let instruction = match chip8.instruction_at(addr)
+      {
+        Some(instruction) => instruction,
+        None              => break,
+      };
+
+      if let Some((label, 0)) = self.symbols.label_before(addr as u16)
+      {
+        let _ = writeln!(out, "{}:", label);
+      }
+
+      let marker = if addr == chip8.regs().PC as usize { '>' }
+                   else if self.breakpoints.contains(&(addr as u16)) { '*' }
+                   else { ' ' };
+
+      let _ = write!(out, "{} {:#05X}: {:04X}  ", marker, addr, instruction.encode());
+
+      match (instruction, chip8.memory().memory.get(addr + 2..addr + 4))
+      {
+        (Instruction::LoadLongIndex, Some(nnnn)) => { let _ = writeln!(out, "LD I, {:#06X}", (nnnn[0] as u16) << 8 | nnnn[1] as u16); },
+        _ => { let _ = writeln!(out, "{}", instruction); },
+      }
+
+      addr += instruction.size() as usize;
+    }
+  }
+}
+
+impl Default for Debugger
+{
+  fn default() -> Self
+  {
+    Debugger::new()
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::scheduler::Speed;
+
+  // V0 = 1, call 0x208, V1 = 2, loop; 0x208: V0 += 1, return
+  const ROM:[u8;12] = [0x60, 0x01, 0x22, 0x08, 0x61, 0x02, 0x12, 0x06, 0x70, 0x01, 0x00, 0xEE];
+
+  fn start() -> (Debugger, Chip8, Scheduler)
+  {
+    let mut chip8 = Chip8::new();
+    chip8.initialize();
+    chip8.load_rom(&ROM).unwrap();
+
+    (Debugger::new(), chip8, Scheduler::new(Speed::InstructionsPerFrame(10)))
+  }
+
+  #[test]
+  fn breakpoints_stop_before_the_instruction()
+  {
+    let (mut debugger, mut chip8, mut scheduler) = start();
+
+    assert_eq!(debugger.run_frame(&mut chip8, &mut scheduler), RunOutcome::Paused);
+
+    assert_eq!(debugger.execute("break 0x208", &mut chip8, &mut scheduler), Ok("Breakpoint at 0x208".to_string()));
+    debugger.execute("continue", &mut chip8, &mut scheduler).unwrap();
+
+    assert_eq!(debugger.run_frame(&mut chip8, &mut scheduler), RunOutcome::Stopped("Breakpoint at 0x208".to_string()));
+    assert_eq!((chip8.regs().PC, chip8.regs().V[0x0]), (0x208, 0x01));
+    assert!(debugger.paused());
+
+    // Continuing gets past the breakpoint it stopped at.
+    debugger.execute("delete 0x208", &mut chip8, &mut scheduler).unwrap();
+    debugger.execute("continue", &mut chip8, &mut scheduler).unwrap();
+    assert_eq!(debugger.run_frame(&mut chip8, &mut scheduler), RunOutcome::Completed);
+    assert_eq!(chip8.regs().V[0x1], 0x02);
+  }
+
+  #[test]
+  fn step_runs_one_instruction_at_a_time()
+  {
+    let (mut debugger, mut chip8, mut scheduler) = start();
+
+    debugger.execute("step", &mut chip8, &mut scheduler).unwrap();
+    assert_eq!((chip8.regs().PC, chip8.regs().V[0x0]), (0x202, 0x01));
+
+    let out = debugger.execute("step 2", &mut chip8, &mut scheduler).unwrap();
+    assert_eq!((chip8.regs().PC, chip8.regs().V[0x0]), (0x20A, 0x02));
+    assert_eq!(out, "> 0x20A: 00EE  RET\n");
+    assert!(debugger.paused());
+  }
+
+  #[test]
+  fn finish_runs_to_the_return_address()
+  {
+    let (mut debugger, mut chip8, mut scheduler) = start();
+
+    debugger.execute("step 2", &mut chip8, &mut scheduler).unwrap();
+    assert_eq!((chip8.regs().PC, chip8.stack().sp), (0x208, 1));
+
+    // Finish the subroutine: break where the innermost call returns to.
+    let ret = chip8.stack().stack[0].wrapping_add(2);
+    debugger.add_breakpoint(ret);
+    debugger.execute("continue", &mut chip8, &mut scheduler).unwrap();
+
+    assert_eq!(debugger.run_frame(&mut chip8, &mut scheduler), RunOutcome::Stopped("Breakpoint at 0x204".to_string()));
+    assert_eq!((chip8.regs().PC, chip8.stack().sp, chip8.regs().V[0x0]), (0x204, 0, 0x02));
+  }
+}
@@ -0,0 +1,1149 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::analysis::{Analysis, Function, Terminator};
+use crate::disasm::octo_with;
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use crate::memory::PROGRAM_START;
+use crate::quirks::{Platform, Quirks};
+
+/// Language the decompiler writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style
+{
+  /// C-like pseudo-code, e.g. `x += 0x01;` and `while (true)`.
+  C,
+  /// Octo with its structured statements, e.g. `loop .. again`.
+  Octo,
+}
+
+impl FromStr for Style
+{
+  type Err = String;
+
+  fn from_str(s:&str) -> Result<Self, Self::Err>
+  {
+    match s.to_lowercase().as_str()
+    {
+      "c"    => Ok(Style::C),
+      "octo" => Ok(Style::Octo),
+      _ => Err(format!("unknown style '{}'", s)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand
+{
+  Imm(u8),
+  Reg(u8),
+}
+
+// What a skip tests, or its opposite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cond
+{
+  Eq(u8, Operand),
+  Ne(u8, Operand),
+  Key(u8), // The key in the register is down
+  NotKey(u8),
+}
+
+impl Cond
+{
+  // The condition under which `instruction` skips.
+  fn of_skip(instruction:Instruction) -> Option<Cond>
+  {
+    match instruction
+    {
+      Instruction::SkipEqImm { x, nn }     => Some(Cond::Eq(x, Operand::Imm(nn))),
+      Instruction::SkipNeImm { x, nn }     => Some(Cond::Ne(x, Operand::Imm(nn))),
+      Instruction::SkipEqReg { x, y }      => Some(Cond::Eq(x, Operand::Reg(y))),
+      Instruction::SkipNeReg { x, y }      => Some(Cond::Ne(x, Operand::Reg(y))),
+      Instruction::SkipKeyPressed { x }    => Some(Cond::Key(x)),
+      Instruction::SkipKeyNotPressed { x } => Some(Cond::NotKey(x)),
+      _ => None,
+    }
+  }
+
+  fn negate(self) -> Cond
+  {
+    match self
+    {
+      Cond::Eq(x, operand) => Cond::Ne(x, operand),
+      Cond::Ne(x, operand) => Cond::Eq(x, operand),
+      Cond::Key(x)         => Cond::NotKey(x),
+      Cond::NotKey(x)      => Cond::Key(x),
+    }
+  }
+}
+
+// A subroutine's code in address order, with skips and jumps as gotos.
+#[derive(Debug, Clone, Copy)]
+enum Op
+{
+  Do(Instruction),
+  Goto(u16),
+  CondGoto(Cond, u16),
+  Invalid, // Runs into an undefined opcode or the end of the ROM
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Stmt
+{
+  addr:u16,
+  op:  Op,
+}
+
+// A pattern of instructions with a well-known meaning.
+#[derive(Debug, Clone, Copy)]
+enum Idiom
+{
+  Digits { value:u8, last:u8 }, // FX33 and FX65
+  DrawDigit { digit:u8, x:u8, y:u8 }, // FX29 and DXY5
+  PrintDecimal { value:u8 }, // Digits then DrawDigits
+  WaitKey { key:u8, down:bool },
+  WaitDelay { x:u8 },
+  Halt,
+}
+
+// The structured program.
+#[derive(Debug, Clone)]
+enum Node
+{
+  Label(u16),
+  Do(u16, Instruction),
+  Goto(u16),
+  If { cond:Cond, then:Vec<Node>, otherwise:Vec<Node> },
+  // Runs `body` forever, or while `cond` holds at its end.
+  Loop { head:u16, latch:u16, exit:Option<u16>, cond:Option<Cond>, body:Vec<Node> },
+  Break,
+  Continue,
+  Invalid(u16),
+  Idiom(Idiom, Vec<Node>),
+}
+
+// What a register's uses suggest it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Role
+{
+  X,
+  Y,
+  Score,
+  Digit,
+  Input,
+  Timer,
+  Sound,
+  Random,
+  Offset,
+}
+
+impl Role
+{
+  fn name(self) -> &'static str
+  {
+    match self
+    {
+      Role::X      => "x",
+      Role::Y      => "y",
+      Role::Score  => "score",
+      Role::Digit  => "digit",
+      Role::Input  => "input",
+      Role::Timer  => "timer",
+      Role::Sound  => "beep",
+      Role::Random => "rnd",
+      Role::Offset => "offset",
+    }
+  }
+}
+
+fn usage(instruction:Instruction) -> Vec<(u8, Role)>
+{
+  match instruction
+  {
+    Instruction::Draw { x, y, .. }                  => vec![(x, Role::X), (y, Role::Y)],
+    Instruction::Bcd { x }                          => vec![(x, Role::Score)],
+    Instruction::FontChar { x } | Instruction::BigFontChar { x }
+        => vec![(x, Role::Digit)],
+    Instruction::SkipKeyPressed { x } | Instruction::SkipKeyNotPressed { x } | Instruction::WaitKey { x }
+        => vec![(x, Role::Input)],
+    Instruction::GetDelay { x } | Instruction::SetDelay { x }
+        => vec![(x, Role::Timer)],
+    Instruction::SetSound { x }                     => vec![(x, Role::Sound)],
+    Instruction::Random { x, .. }                   => vec![(x, Role::Random)],
+    Instruction::AddIndex { x }                     => vec![(x, Role::Offset)],
+    _ => Vec::new(),
+  }
+}
+
+// Name each register after the role most of its uses suggest, telling
+// registers with the same role apart by number. VF is always the flag.
+fn register_names(analysis:&Analysis) -> Vec<String>
+{
+  let mut votes = vec![BTreeMap::new(); 15];
+
+  for block in analysis.blocks.values()
+  {
+    for &(_, instruction) in block.instructions.iter()
+    {
+      for (x, role) in usage(instruction).into_iter().filter(|&(x, _)| x < 0xF)
+      {
+        *votes[x as usize].entry(role).or_insert(0) += 1;
+      }
+    }
+  }
+
+  let roles:Vec<Option<Role>> = votes.iter()
+                                     .map(|votes| votes.iter().rev().max_by_key(|&(_, count)| *count).map(|(&role, _)| role))
+                                     .collect();
+
+  let mut names:Vec<String> = roles.iter().enumerate().map(|(x, role)| match role
+  {
+    None => format!("v{:x}", x),
+    Some(role) if roles.iter().filter(|&other| other == &Some(*role)).count() > 1
+         => format!("{}_{:x}", role.name(), x),
+    Some(role) => role.name().to_string(),
+  }).collect();
+
+  names.push("flag".to_string());
+  names
+}
+
+// Lay out a subroutine's blocks in address order.
+fn statements(analysis:&Analysis, function:&Function) -> Vec<Stmt>
+{
+  let starts:Vec<u16> = function.blocks.iter().copied().collect();
+  let mut stmts       = Vec::new();
+
+  for (n, start) in starts.iter().enumerate()
+  {
+    let block = &analysis.blocks[start];
+    let mut instructions = block.instructions.iter();
+
+    let last = match block.terminator
+    {
+      Terminator::Jump(_) | Terminator::Branch { .. } => instructions.next_back(),
+      _ => None,
+    };
+
+    stmts.extend(instructions.map(|&(addr, instruction)| Stmt { addr, op:Op::Do(instruction) }));
+
+    match (block.terminator, last)
+    {
+      (Terminator::Jump(target), Some(&(addr, _)))
+          => stmts.push(Stmt { addr, op:Op::Goto(target) }),
+      (Terminator::Branch { skip, .. }, Some(&(addr, instruction)))
+          => stmts.extend(Cond::of_skip(instruction).map(|cond| Stmt { addr, op:Op::CondGoto(cond, skip) })),
+      (Terminator::Invalid, _)
+          => stmts.push(Stmt { addr:block.end, op:Op::Invalid }),
+      _   => {},
+    }
+
+    // Blocks that run on into the next one should find it next in line.
+    let next = match block.terminator
+    {
+      Terminator::Fall(next) | Terminator::Branch { next, .. } | Terminator::Call { next, .. } => next,
+      _ => continue,
+    };
+
+    if starts.get(n + 1) != Some(&next)
+    {
+      stmts.push(Stmt { addr:block.end, op:Op::Goto(next) });
+    }
+  }
+
+  stmts
+}
+
+fn target(stmt:&Stmt) -> Option<u16>
+{
+  match stmt.op
+  {
+    Op::Goto(target) | Op::CondGoto(_, target) => Some(target),
+    _ => None,
+  }
+}
+
+// A skip over a jump is a conditional jump the other way.
+fn fold_skipped_jumps(stmts:&mut Vec<Stmt>)
+{
+  let targets:BTreeSet<u16> = stmts.iter().filter_map(target).collect();
+  let mut i = 0;
+
+  while i + 2 < stmts.len()
+  {
+    if let (Op::CondGoto(cond, skip), Op::Goto(to)) = (stmts[i].op, stmts[i + 1].op)
+    {
+      let jump = stmts[i + 1].addr;
+
+      if skip == jump + 2 && stmts[i + 2].addr == skip && !targets.contains(&jump)
+      {
+        stmts[i].op = Op::CondGoto(cond.negate(), to);
+        stmts.remove(i + 1);
+      }
+    }
+
+    i += 1;
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LoopContext
+{
+  head:    u16,
+  latch:   u16, // The jump back to the head
+  exit:    Option<u16>,
+  infinite:bool,
+}
+
+struct Structurer<'a>
+{
+  stmts:  &'a [Stmt],
+  targets:BTreeSet<u16>,
+}
+
+impl<'a> Structurer<'a>
+{
+  fn addr_of(&self, idx:usize) -> Option<u16>
+  {
+    self.stmts.get(idx).map(|stmt| stmt.addr)
+  }
+
+  // The statement in lo..=hi at `addr`.
+  fn position(&self, lo:usize, hi:usize, addr:u16) -> Option<usize>
+  {
+    (lo..=hi).find(|&idx| self.addr_of(idx) == Some(addr))
+  }
+
+  // A jump that leaves or restarts the innermost loop.
+  fn loop_jump(&self, target:u16, inner:Option<LoopContext>) -> Option<Node>
+  {
+    let context = inner?;
+
+    if Some(target) == context.exit
+    {
+      Some(Node::Break)
+    }
+    else if target == context.latch || (context.infinite && target == context.head)
+    {
+      Some(Node::Continue)
+    }
+    else
+    {
+      None
+    }
+  }
+
+  // Structure the statements lo..hi, which run on into the one at hi. A
+  // loop body starts at its head, which isn't a loop of its own again.
+  fn structure(&self, lo:usize, hi:usize, inner:Option<LoopContext>, body:bool) -> Vec<Node>
+  {
+    let mut nodes = Vec::new();
+    let mut idx   = lo;
+
+    while idx < hi
+    {
+      let addr = self.stmts[idx].addr;
+
+      if !(body && idx == lo)
+      {
+        if self.targets.contains(&addr)
+        {
+          nodes.push(Node::Label(addr));
+        }
+
+        // The last jump back here closes a loop.
+        if let Some(latch) = (idx..hi).rev().find(|&j| target(&self.stmts[j]) == Some(addr))
+        {
+          let cond    = match self.stmts[latch].op { Op::CondGoto(cond, _) => Some(cond), _ => None };
+          let context = LoopContext { head:addr, latch:self.stmts[latch].addr, exit:self.addr_of(latch + 1), infinite:cond.is_none() };
+
+          nodes.push(Node::Loop { head:addr, latch:context.latch, exit:context.exit, cond,
+                                  body:self.structure(idx, latch, Some(context), true) });
+          idx = latch + 1;
+          continue;
+        }
+      }
+
+      match self.stmts[idx].op
+      {
+        Op::Do(instruction) => nodes.push(Node::Do(addr, instruction)),
+        Op::Invalid         => nodes.push(Node::Invalid(addr)),
+        Op::Goto(target) if self.addr_of(idx + 1) == Some(target) => {},
+        Op::Goto(target)    => nodes.push(self.loop_jump(target, inner).unwrap_or(Node::Goto(target))),
+        Op::CondGoto(cond, target) =>
+        {
+          if let Some(end) = self.position(idx + 2, hi, target).filter(|_| target > addr)
+          {
+            // Jumping over code runs it only if the condition fails. If
+            // that code ends jumping further on, what it skips is an else.
+            let split = match self.stmts[end - 1].op
+            {
+              Op::Goto(join) if end > idx + 2 && join > target => self.position(end, hi, join),
+              _ => None,
+            };
+
+            let (then, otherwise, next) = match split
+            {
+              Some(join) => (self.structure(idx + 1, end - 1, inner, false), self.structure(end, join, inner, false), join),
+              None       => (self.structure(idx + 1, end, inner, false), Vec::new(), end),
+            };
+
+            nodes.push(Node::If { cond:cond.negate(), then, otherwise });
+            idx = next;
+            continue;
+          }
+          else if let Some(node) = self.loop_jump(target, inner)
+          {
+            nodes.push(Node::If { cond, then:vec![node], otherwise:Vec::new() });
+          }
+          else if self.addr_of(idx + 1) != Some(target)
+          {
+            nodes.push(Node::If { cond, then:vec![Node::Goto(target)], otherwise:Vec::new() });
+          }
+        },
+      }
+
+      idx += 1;
+    }
+
+    nodes
+  }
+}
+
+// An instruction that only changes the register it returns, and maybe VF.
+fn register_only(instruction:Instruction) -> Option<u8>
+{
+  match instruction
+  {
+    Instruction::LoadImm { x, .. } | Instruction::AddImm { x, .. } | Instruction::Move { x, .. } |
+    Instruction::Or { x, .. } | Instruction::And { x, .. } | Instruction::Xor { x, .. } |
+    Instruction::Add { x, .. } | Instruction::Sub { x, .. } | Instruction::SubReverse { x, .. } |
+    Instruction::ShiftRight { x, .. } | Instruction::ShiftLeft { x, .. } | Instruction::Random { x, .. } |
+    Instruction::GetDelay { x } => Some(x),
+    _ => None,
+  }
+}
+
+// Recognize idioms in `nodes` and the blocks nested in them.
+fn idioms(nodes:Vec<Node>) -> Vec<Node>
+{
+  let nodes:Vec<Node> = nodes.into_iter().map(|node| match node
+  {
+    Node::If { cond, then, otherwise }
+        => Node::If { cond, then:idioms(then), otherwise:idioms(otherwise) },
+    Node::Loop { head, latch, exit, cond, body } =>
+    {
+      let body = idioms(body);
+
+      let idiom = match (cond, body.as_slice())
+      {
+        (None, []) => Some(Idiom::Halt),
+        (Some(Cond::NotKey(key)), body) | (Some(Cond::Key(key)), body)
+            if body.iter().all(|node| matches!(node, Node::Do(_, Instruction::LoadImm { x, .. }) if *x == key))
+            => Some(Idiom::WaitKey { key, down:cond == Some(Cond::NotKey(key)) }),
+        (Some(Cond::Ne(x, Operand::Imm(0))), [Node::Do(_, Instruction::GetDelay { x:read })]) if *read == x
+            => Some(Idiom::WaitDelay { x }),
+        _ => None,
+      };
+
+      let node = Node::Loop { head, latch, exit, cond, body };
+
+      match idiom
+      {
+        Some(idiom) => Node::Idiom(idiom, vec![node]),
+        None        => node,
+      }
+    },
+    node => node,
+  }).collect();
+
+  // Digits and the digits drawn, then the two together.
+  let mut found = Vec::new();
+  let mut idx   = 0;
+
+  while idx < nodes.len()
+  {
+    match (&nodes[idx], nodes.get(idx + 1))
+    {
+      (Node::Do(_, Instruction::Bcd { x:value }), Some(Node::Do(_, Instruction::Load { x:last }))) if *last <= 2 =>
+      {
+        found.push(Node::Idiom(Idiom::Digits { value:*value, last:*last }, nodes[idx..idx + 2].to_vec()));
+        idx += 2;
+        continue;
+      },
+      (Node::Do(_, Instruction::FontChar { x:digit }), _) =>
+      {
+        let between = nodes[idx + 1..].iter()
+                                      .take_while(|node| matches!(node, Node::Do(_, instruction)
+                                                                  if register_only(*instruction).is_some_and(|x| x != *digit && x != 0xF)))
+                                      .count();
+
+        if let Some(&Node::Do(_, Instruction::Draw { x, y, n:5 })) = nodes.get(idx + 1 + between)
+        {
+          // What comes between doesn't touch the digit or I, so the
+          // drawing can follow it.
+          let parts = vec![nodes[idx].clone(), nodes[idx + 1 + between].clone()];
+
+          found.extend(nodes[idx + 1..idx + 1 + between].iter().cloned());
+          found.push(Node::Idiom(Idiom::DrawDigit { digit:*digit, x, y }, parts));
+          idx += between + 2;
+          continue;
+        }
+      },
+      _ => {},
+    }
+
+    found.push(nodes[idx].clone());
+    idx += 1;
+  }
+
+  let mut nodes = Vec::new();
+  let mut idx   = 0;
+
+  while idx < found.len()
+  {
+    if let Node::Idiom(Idiom::Digits { value, .. }, _) = found[idx]
+    {
+      let mut end = idx + 1;
+
+      for (offset, node) in found[idx + 1..].iter().enumerate()
+      {
+        match node
+        {
+          Node::Idiom(Idiom::DrawDigit { .. }, _) => end = idx + 2 + offset,
+          Node::Do(_, instruction) if register_only(*instruction).is_some() => {},
+          _ => break,
+        }
+      }
+
+      if end > idx + 1
+      {
+        nodes.push(Node::Idiom(Idiom::PrintDecimal { value }, found[idx..end].to_vec()));
+        idx = end;
+        continue;
+      }
+    }
+
+    nodes.push(found[idx].clone());
+    idx += 1;
+  }
+
+  nodes
+}
+
+fn subroutine_name(entry:u16) -> String
+{
+  if entry as usize == PROGRAM_START { "main".to_string() } else { format!("sub_{:03X}", entry) }
+}
+
+struct Writer<'a>
+{
+  style:  Style,
+  names:  &'a [String],
+  quirks: Quirks,
+  rom:    &'a [u8],
+  entries:BTreeSet<u16>,
+  addrs:  BTreeSet<u16>, // Of the subroutine being written
+  labels: BTreeSet<u16>, // Labels to write
+  jumps:  BTreeSet<u16>, // Labels jumped to
+  loops:  Vec<(u16, u16, Option<u16>, bool)>, // Head, latch, exit and whether infinite
+  out:    String,
+}
+
+impl<'a> Writer<'a>
+{
+  fn name(&self, x:u8) -> &str
+  {
+    &self.names[x as usize]
+  }
+
+  fn line(&mut self, depth:usize, text:&str)
+  {
+    let _ = writeln!(self.out, "{:width$}{}", "", text, width = depth * 2);
+  }
+
+  fn label(&mut self, addr:u16) -> String
+  {
+    self.jumps.insert(addr);
+    format!("L_{:03X}", addr)
+  }
+
+  fn long(&self, addr:u16) -> u16
+  {
+    let at = addr as usize - PROGRAM_START;
+
+    self.rom.get(at + 2..at + 4).map_or(0, |nnnn| (nnnn[0] as u16) << 8 | nnnn[1] as u16)
+  }
+
+  fn condition(&self, cond:Cond) -> String
+  {
+    let operand = |operand| match operand
+    {
+      Operand::Imm(nn) => format!("{:#04X}", nn),
+      Operand::Reg(y)  => self.name(y).to_string(),
+    };
+
+    match (self.style, cond)
+    {
+      (_, Cond::Eq(x, rhs))          => format!("{} == {}", self.name(x), operand(rhs)),
+      (_, Cond::Ne(x, rhs))          => format!("{} != {}", self.name(x), operand(rhs)),
+      (Style::C, Cond::Key(x))       => format!("key_down({})", self.name(x)),
+      (Style::C, Cond::NotKey(x))    => format!("!key_down({})", self.name(x)),
+      (Style::Octo, Cond::Key(x))    => format!("{} key", self.name(x)),
+      (Style::Octo, Cond::NotKey(x)) => format!("{} -key", self.name(x)),
+    }
+  }
+
+  fn goto(&mut self, target:u16) -> String
+  {
+    let inside = self.addrs.contains(&target);
+
+    match self.style
+    {
+      Style::C if self.entries.contains(&target) => format!("goto {};", subroutine_name(target)),
+      Style::C if inside                         => format!("goto {};", self.label(target)),
+      Style::C                                   => format!("goto *{:#05X};", target),
+      Style::Octo if self.entries.contains(&target) => format!("jump {}", subroutine_name(target)),
+      Style::Octo if inside                         => format!("jump {}", self.label(target)),
+      Style::Octo                                   => format!("jump {:#05X}", target),
+    }
+  }
+
+  // The C statements for an instruction.
+  fn c_statement(&self, addr:u16, instruction:Instruction) -> String
+  {
+    let n      = |x:u8| self.name(x);
+    let shift  = |x:u8, y:u8| if self.quirks.shift_uses_vy { n(y) } else { n(x) };
+    let resets = if self.quirks.logic_resets_vf { "  // flag = 0" } else { "" };
+    let range  = |x:u8| if x == 0 { "v0".to_string() } else { format!("v0..v{:x}", x) };
+    let step   = |x:u8| if self.quirks.load_store_increments_i { format!(" i += {};", x + 1) } else { String::new() };
+
+    match instruction
+    {
+      Instruction::Undefined { opcode } | Instruction::Sys { nnn:opcode }
+          => format!("// {:04X}", opcode),
+      Instruction::Cls                     => "clear();".to_string(),
+      Instruction::Ret                     => "return;".to_string(),
+      Instruction::ScrollDown { n }        => format!("scroll_down({});", n),
+      Instruction::ScrollRight             => "scroll_right();".to_string(),
+      Instruction::ScrollLeft              => "scroll_left();".to_string(),
+      Instruction::Exit                    => "exit();".to_string(),
+      Instruction::LowRes                  => "lores();".to_string(),
+      Instruction::HighRes                 => "hires();".to_string(),
+      Instruction::Jump { nnn }            => format!("goto *{:#05X};", nnn),
+      Instruction::Call { nnn }            => format!("{}();", subroutine_name(nnn)),
+      Instruction::LoadImm { x, nn }       => format!("{} = {:#04X};", n(x), nn),
+      Instruction::AddImm { x, nn }        => format!("{} += {:#04X};", n(x), nn),
+      Instruction::Move { x, y }           => format!("{} = {};", n(x), n(y)),
+      Instruction::Or { x, y }             => format!("{} |= {};{}", n(x), n(y), resets),
+      Instruction::And { x, y }            => format!("{} &= {};{}", n(x), n(y), resets),
+      Instruction::Xor { x, y }            => format!("{} ^= {};{}", n(x), n(y), resets),
+      Instruction::Add { x, y }            => format!("{} += {};  // flag = carry", n(x), n(y)),
+      Instruction::Sub { x, y }            => format!("{} -= {};  // flag = no borrow", n(x), n(y)),
+      Instruction::SubReverse { x, y }     => format!("{} = {} - {};  // flag = no borrow", n(x), n(y), n(x)),
+      Instruction::ShiftRight { x, y }     => format!("{} = {} >> 1;  // flag = bit shifted out", n(x), shift(x, y)),
+      Instruction::ShiftLeft { x, y }      => format!("{} = {} << 1;  // flag = bit shifted out", n(x), shift(x, y)),
+      Instruction::LoadIndex { nnn }       => format!("i = {:#05X};", nnn),
+      Instruction::JumpOffset { nnn } =>
+      {
+        let x = if self.quirks.jump_uses_vx { (nnn >> 8) as u8 } else { 0 };
+
+        format!("goto *({:#05X} + {});", nnn, n(x))
+      },
+      Instruction::Random { x, nn }        => format!("{} = random() & {:#04X};", n(x), nn),
+      Instruction::Draw { x, y, n:rows }   => format!("{} = draw({}, {}, {});", n(0xF), n(x), n(y), rows),
+      Instruction::LoadLongIndex           => format!("i = {:#06X};", self.long(addr)),
+      Instruction::SelectPlanes { n }      => format!("planes({});", n),
+      Instruction::LoadAudio               => "load_audio();".to_string(),
+      Instruction::GetDelay { x }          => format!("{} = delay;", n(x)),
+      Instruction::WaitKey { x }           => format!("{} = wait_key();", n(x)),
+      Instruction::SetDelay { x }          => format!("delay = {};", n(x)),
+      Instruction::SetSound { x }          => format!("sound = {};", n(x)),
+      Instruction::AddIndex { x } if self.quirks.index_overflow_sets_vf
+          => format!("i += {};  // flag = overflow", n(x)),
+      Instruction::AddIndex { x }          => format!("i += {};", n(x)),
+      Instruction::FontChar { x }          => format!("i = font({});", n(x)),
+      Instruction::BigFontChar { x }       => format!("i = big_font({});", n(x)),
+      Instruction::Bcd { x }               => format!("memory[i..i + 2] = decimal_digits({});", n(x)),
+      Instruction::SetPitch { x }          => format!("pitch = {};", n(x)),
+      Instruction::Store { x }             => format!("memory[i..] = {};{}", range(x), step(x)),
+      Instruction::Load { x }              => format!("{} = memory[i..];{}", range(x), step(x)),
+      Instruction::SaveRange { x, y }      => format!("memory[i..] = v{:x}..v{:x};", x, y),
+      Instruction::LoadRange { x, y }      => format!("v{:x}..v{:x} = memory[i..];", x, y),
+      Instruction::SaveFlags { x }         => format!("flags[..] = {};", range(x)),
+      Instruction::LoadFlags { x }         => format!("{} = flags[..];", range(x)),
+      // Skips only end blocks, which come out as conditions.
+      Instruction::SkipEqImm { .. } | Instruction::SkipNeImm { .. } | Instruction::SkipEqReg { .. } |
+      Instruction::SkipNeReg { .. } | Instruction::SkipKeyPressed { .. } | Instruction::SkipKeyNotPressed { .. }
+          => format!("// {}", octo_with(instruction, 0, &|x| self.name(x).to_string())),
+    }
+  }
+
+  fn statement(&self, addr:u16, instruction:Instruction) -> String
+  {
+    match (self.style, instruction)
+    {
+      (Style::C, _)                            => self.c_statement(addr, instruction),
+      (Style::Octo, Instruction::Call { nnn }) => subroutine_name(nnn),
+      (Style::Octo, _)                         => octo_with(instruction, self.long(addr), &|x| self.name(x).to_string()),
+    }
+  }
+
+  // A node that comes out as one line, for `if .. then`.
+  fn single(&mut self, node:&Node) -> Option<String>
+  {
+    match (self.style, node)
+    {
+      (_, Node::Do(addr, instruction))        => Some(self.statement(*addr, *instruction)),
+      (_, Node::Goto(target))                 => Some(self.goto(*target)),
+      (Style::C, Node::Break)                 => Some("break;".to_string()),
+      (Style::C, Node::Continue)              => Some("continue;".to_string()),
+      (Style::C, Node::Idiom(Idiom::Halt, _)) => Some("halt();".to_string()),
+      // Octo has no break or continue, so jump to the label instead.
+      (Style::Octo, Node::Break) =>
+      {
+        let exit = self.loops.last()?.2?;
+
+        Some(format!("jump {}", self.label(exit)))
+      },
+      (Style::Octo, Node::Continue) =>
+      {
+        let (head, latch, _, infinite) = *self.loops.last()?;
+
+        Some(format!("jump {}", self.label(if infinite { head } else { latch })))
+      },
+      _ => None,
+    }
+  }
+
+  fn nodes(&mut self, depth:usize, nodes:&[Node])
+  {
+    for node in nodes
+    {
+      self.node(depth, node);
+    }
+  }
+
+  fn node(&mut self, depth:usize, node:&Node)
+  {
+    match node
+    {
+      Node::Label(addr) if self.labels.contains(addr) =>
+      {
+        let line = match self.style
+        {
+          Style::C    => format!("L_{:03X}:", addr),
+          Style::Octo => format!(": L_{:03X}", addr),
+        };
+
+        self.line(depth, &line);
+      },
+      Node::Label(_) => {},
+      Node::Do(addr, instruction) =>
+      {
+        let statement = self.statement(*addr, *instruction);
+
+        self.line(depth, &statement);
+      },
+      Node::Goto(target) =>
+      {
+        let goto = self.goto(*target);
+
+        self.line(depth, &goto);
+      },
+      Node::Break | Node::Continue =>
+      {
+        let line = self.single(node).unwrap_or_default();
+
+        self.line(depth, &line);
+      },
+      Node::Invalid(addr) =>
+      {
+        let comment = if self.style == Style::C { "//" } else { "#" };
+
+        self.line(depth, &format!("{} runs into {:#05X}, which isn't an instruction", comment, addr));
+      },
+      Node::If { cond, then, otherwise } => self.conditional(depth, *cond, then, otherwise),
+      Node::Loop { head, latch, exit, cond, body } =>
+      {
+        self.loops.push((*head, *latch, *exit, cond.is_none()));
+
+        match self.style
+        {
+          Style::C =>
+          {
+            self.line(depth, if cond.is_some() { "do" } else { "while (true)" });
+            self.line(depth, "{");
+            self.nodes(depth + 1, body);
+            self.line(depth, "}");
+
+            if let Some(cond) = cond
+            {
+              let cond = self.condition(*cond);
+
+              self.line(depth, &format!("while ({});", cond));
+            }
+          },
+          Style::Octo =>
+          {
+            self.line(depth, "loop");
+            self.nodes(depth + 1, body);
+
+            if let Some(cond) = cond
+            {
+              if self.labels.contains(latch)
+              {
+                self.line(depth + 1, &format!(": L_{:03X}", latch));
+              }
+
+              let cond = self.condition(*cond);
+
+              self.line(depth + 1, &format!("while {}", cond));
+            }
+
+            self.line(depth, "again");
+          },
+        }
+
+        self.loops.pop();
+      },
+      Node::Idiom(idiom, parts) => self.idiom(depth, *idiom, parts),
+    }
+  }
+
+  fn conditional(&mut self, depth:usize, cond:Cond, then:&[Node], otherwise:&[Node])
+  {
+    // Octo's while leaves the loop when its condition fails.
+    if let (Style::Octo, [Node::Break], []) = (self.style, then, otherwise)
+    {
+      let cond = self.condition(cond.negate());
+
+      self.line(depth, &format!("while {}", cond));
+      return;
+    }
+
+    // Labels nothing jumps to don't count.
+    let shown:Vec<&Node> = then.iter().filter(|node| !matches!(node, Node::Label(addr) if !self.labels.contains(addr))).collect();
+
+    let simple = match (shown.as_slice(), otherwise)
+    {
+      ([node], []) => self.single(node),
+      _            => None,
+    };
+
+    let cond = self.condition(cond);
+
+    match (self.style, simple)
+    {
+      (Style::C, Some(statement))    => self.line(depth, &format!("if ({}) {}", cond, statement)),
+      (Style::Octo, Some(statement)) => self.line(depth, &format!("if {} then {}", cond, statement)),
+      (Style::C, None) =>
+      {
+        self.line(depth, &format!("if ({})", cond));
+        self.line(depth, "{");
+        self.nodes(depth + 1, then);
+        self.line(depth, "}");
+
+        if !otherwise.is_empty()
+        {
+          self.line(depth, "else");
+          self.line(depth, "{");
+          self.nodes(depth + 1, otherwise);
+          self.line(depth, "}");
+        }
+      },
+      (Style::Octo, None) =>
+      {
+        self.line(depth, &format!("if {} begin", cond));
+        self.nodes(depth + 1, then);
+
+        if !otherwise.is_empty()
+        {
+          self.line(depth, "else");
+          self.nodes(depth + 1, otherwise);
+        }
+
+        self.line(depth, "end");
+      },
+    }
+  }
+
+  fn idiom(&mut self, depth:usize, idiom:Idiom, parts:&[Node])
+  {
+    let comment = match (idiom, self.style)
+    {
+      (Idiom::PrintDecimal { value }, _)      => Some(format!("print {} in decimal", self.name(value))),
+      // The C for the rest says as much.
+      (_, Style::C)                           => None,
+      (Idiom::WaitKey { key, down:true }, _)  => Some(format!("wait for key {} to go down", self.name(key))),
+      (Idiom::WaitKey { key, down:false }, _) => Some(format!("wait for key {} to go up", self.name(key))),
+      (Idiom::WaitDelay { .. }, _)            => Some("wait for the delay timer to run out".to_string()),
+      (Idiom::Halt, _)                        => Some("halt".to_string()),
+      _                                       => None,
+    };
+
+    if let Some(comment) = comment
+    {
+      let marker = if self.style == Style::C { "//" } else { "#" };
+
+      self.line(depth, &format!("{} {}", marker, comment));
+    }
+
+    if self.style == Style::Octo
+    {
+      self.nodes(depth, parts);
+      return;
+    }
+
+    match idiom
+    {
+      Idiom::Digits { value, last } =>
+      {
+        let digits = ["{} / 100", "{} / 10 % 10", "{} % 10"];
+
+        // The digit landing in the value's own register goes last, so the
+        // others still read the value.
+        for x in (0..=last).filter(|&x| x != value).chain(Some(value).filter(|&value| value <= last))
+        {
+          let value = digits[x as usize].replace("{}", self.name(value));
+          let line  = format!("{} = {};", self.name(x), value);
+
+          self.line(depth, &line);
+        }
+      },
+      Idiom::DrawDigit { digit, x, y } =>
+      {
+        let line = format!("{} = draw_digit({}, {}, {});", self.name(0xF), self.name(digit), self.name(x), self.name(y));
+
+        self.line(depth, &line);
+      },
+      Idiom::PrintDecimal { .. } => self.nodes(depth, parts),
+      Idiom::WaitKey { key, down } =>
+      {
+        // Loading the key on every pass is the same as loading it once.
+        if let Some(Node::Loop { body, .. }) = parts.first()
+        {
+          self.nodes(depth, body);
+        }
+
+        let line = format!("wait_key_{}({});", if down { "down" } else { "up" }, self.name(key));
+
+        self.line(depth, &line);
+      },
+      Idiom::WaitDelay { x } =>
+      {
+        let line = format!("{} = wait_delay();", self.name(x));
+
+        self.line(depth, &line);
+      },
+      Idiom::Halt => self.line(depth, "halt();"),
+    }
+  }
+
+  fn function(&mut self, entry:u16, nodes:&[Node])
+  {
+    // Write the body once to find the labels it jumps to.
+    let start = self.out.len();
+
+    self.jumps.clear();
+    self.labels.clear();
+    self.nodes(1, nodes);
+    self.out.truncate(start);
+    self.labels = std::mem::take(&mut self.jumps);
+
+    match self.style
+    {
+      Style::C =>
+      {
+        self.line(0, &format!("void {}()", subroutine_name(entry)));
+        self.line(0, "{");
+        self.nodes(1, nodes);
+        self.line(0, "}");
+      },
+      Style::Octo =>
+      {
+        self.line(0, &format!(": {}", subroutine_name(entry)));
+        self.nodes(1, nodes);
+      },
+    }
+
+    self.line(0, "");
+  }
+}
+
+/// Decompile a ROM loaded at 0x200 for `platform` into structured
+/// pseudo-code, a function per subroutine. Registers are named by how
+/// they're used, and common idioms are spelled out. Fails if the ROM runs
+/// past the 16 bit address space.
+pub fn decompile(rom:&[u8], platform:Platform, style:Style) -> Result<String, Chip8Error>
+{
+  let analysis = Analysis::new(rom, platform)?;
+  let names    = register_names(&analysis);
+
+  let mut writer = Writer { style, names:&names, quirks:Quirks::preset(platform), rom,
+                            entries:analysis.functions.keys().copied().collect(), addrs:BTreeSet::new(),
+                            labels:BTreeSet::new(), jumps:BTreeSet::new(), loops:Vec::new(), out:String::new() };
+
+  let aliases:Vec<(usize, &String)> = names.iter().enumerate().filter(|&(x, name)| *name != format!("v{:x}", x)).collect();
+
+  match style
+  {
+    Style::C =>
+    {
+      let aliases:Vec<String> = aliases.iter().map(|(x, name)| format!("{} = v{:x}", name, x)).collect();
+
+      writer.line(0, &format!("// Registers: {}", aliases.join(", ")));
+    },
+    Style::Octo =>
+    {
+      for (x, name) in aliases.iter()
+      {
+        writer.line(0, &format!(":alias {} v{:x}", name, x));
+      }
+    },
+  }
+
+  writer.line(0, "");
+
+  for function in analysis.functions.values()
+  {
+    let mut stmts = statements(&analysis, function);
+
+    fold_skipped_jumps(&mut stmts);
+
+    let structurer = Structurer { stmts:&stmts, targets:stmts.iter().filter_map(target).collect() };
+    let nodes      = idioms(structurer.structure(0, stmts.len(), None, false));
+
+    writer.addrs = stmts.iter().map(|stmt| stmt.addr).collect();
+    writer.function(function.entry, &nodes);
+  }
+
+  Ok(writer.out)
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  fn c(rom:&[u8]) -> String
+  {
+    decompile(rom, Platform::CosmacVip, Style::C).unwrap()
+  }
+
+  fn octo(rom:&[u8]) -> String
+  {
+    decompile(rom, Platform::CosmacVip, Style::Octo).unwrap()
+  }
+
+  // V1 = 1 if V0 is 5, else 2, then halt.
+  const IF_ELSE:[u8;12] = [0x30, 0x05, 0x12, 0x08, 0x61, 0x01, 0x12, 0x0A, 0x61, 0x02, 0x12, 0x0A];
+
+  // Count V0 up to 10, then halt.
+  const LOOP:[u8;10] = [0x60, 0x00, 0x70, 0x01, 0x30, 0x0A, 0x12, 0x02, 0x12, 0x08];
+
+  // Draw V0 in decimal, wait for the delay timer, then halt.
+  const IDIOMS:[u8;26] = [0xA3, 0x00, 0xF0, 0x33, 0xF2, 0x65, 0xF0, 0x29, 0xD3, 0x45, 0xF1, 0x29, 0xD3, 0x45,
+                          0xF2, 0x29, 0xD3, 0x45, 0xF4, 0x07, 0x34, 0x00, 0x12, 0x12, 0x12, 0x18];
+
+  #[test]
+  fn skips_over_jumps_become_if_else()
+  {
+    assert_eq!(c(&IF_ELSE), "\
+// Registers: flag = vf
+
+void main()
+{
+  if (v0 == 0x05)
+  {
+    v1 = 0x01;
+  }
+  else
+  {
+    v1 = 0x02;
+  }
+  halt();
+}
+
+");
+
+    assert_eq!(octo(&IF_ELSE), "\
+:alias flag vf
+
+: main
+  if v0 == 0x05 begin
+    v1 := 0x01
+  else
+    v1 := 0x02
+  end
+  # halt
+  loop
+  again
+
+");
+  }
+
+  #[test]
+  fn jumps_back_become_loops()
+  {
+    assert_eq!(c(&LOOP), "\
+// Registers: flag = vf
+
+void main()
+{
+  v0 = 0x00;
+  do
+  {
+    v0 += 0x01;
+  }
+  while (v0 != 0x0A);
+  halt();
+}
+
+");
+
+    assert_eq!(octo(&LOOP), "\
+:alias flag vf
+
+: main
+  v0 := 0x00
+  loop
+    v0 += 0x01
+    while v0 != 0x0A
+  again
+  # halt
+  loop
+  again
+
+");
+  }
+
+  #[test]
+  fn idioms_are_spelled_out()
+  {
+    // The digit in the value's own register is written last.
+    assert_eq!(c(&IDIOMS), "\
+// Registers: score = v0, digit_1 = v1, digit_2 = v2, x = v3, y = v4, flag = vf
+
+void main()
+{
+  i = 0x300;
+  // print score in decimal
+  digit_1 = score / 10 % 10;
+  digit_2 = score % 10;
+  score = score / 100;
+  flag = draw_digit(score, x, y);
+  flag = draw_digit(digit_1, x, y);
+  flag = draw_digit(digit_2, x, y);
+  y = wait_delay();
+  halt();
+}
+
+");
+
+    assert!(octo(&IDIOMS).contains("  # wait for the delay timer to run out\n  loop\n    y := delay\n    while y != 0x00\n  again\n"));
+  }
+}
@@ -0,0 +1,375 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::instruction::Instruction;
+use crate::memory::PROGRAM_START;
+use crate::opcode::{OpCode, OpCodeSymbol};
+use crate::quirks::{Platform, Quirks};
+
+/// Assembly syntax of a listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax
+{
+  /// Octo, e.g. `v3 += 0x01`.
+  Octo,
+  /// The classic mnemonics of Cowgod's reference, e.g. `ADD V3, 0x01`.
+  Cowgod,
+}
+
+impl FromStr for Syntax
+{
+  type Err = String;
+
+  fn from_str(s:&str) -> Result<Self, Self::Err>
+  {
+    match s.to_lowercase().as_str()
+    {
+      "octo"                => Ok(Syntax::Octo),
+      "cowgod" | "classic"  => Ok(Syntax::Cowgod),
+      _ => Err(format!("unknown syntax '{}'", s)),
+    }
+  }
+}
+
+/// The instruction in Octo syntax. A `LoadLongIndex` needs the address
+/// that follows it, `long`. Undefined opcodes come out as data bytes.
+pub fn octo(instruction:Instruction, long:u16) -> String
+{
+  octo_with(instruction, long, &|x| format!("v{:x}", x))
+}
+
+/// Like `octo`, with `reg` naming the registers, for instance by their
+/// aliases. Register ranges keep their numbers.
+pub fn octo_with(instruction:Instruction, long:u16, reg:&dyn Fn(u8) -> String) -> String
+{
+  match instruction
+  {
+    Instruction::Undefined { opcode } | Instruction::Sys { nnn:opcode }
+        => format!("{:#04X} {:#04X}", opcode >> 8, opcode & 0xFF),
+    Instruction::Cls                          => "clear".to_string(),
+    Instruction::Ret                          => "return".to_string(),
+    Instruction::ScrollDown { n }             => format!("scroll-down {}", n),
+    Instruction::ScrollRight                  => "scroll-right".to_string(),
+    Instruction::ScrollLeft                   => "scroll-left".to_string(),
+    Instruction::Exit                         => "exit".to_string(),
+    Instruction::LowRes                       => "lores".to_string(),
+    Instruction::HighRes                      => "hires".to_string(),
+    Instruction::Jump { nnn }                 => format!("jump {:#05X}", nnn),
+    Instruction::Call { nnn }                 => format!(":call {:#05X}", nnn),
+    // Octo's conditionals name the case that runs the next instruction,
+    // so they read as the opposite of the skip.
+    Instruction::SkipEqImm { x, nn }          => format!("if {} != {:#04X} then", reg(x), nn),
+    Instruction::SkipNeImm { x, nn }          => format!("if {} == {:#04X} then", reg(x), nn),
+    Instruction::SkipEqReg { x, y }           => format!("if {} != {} then", reg(x), reg(y)),
+    Instruction::SaveRange { x, y }           => format!("save v{:x} - v{:x}", x, y),
+    Instruction::LoadRange { x, y }           => format!("load v{:x} - v{:x}", x, y),
+    Instruction::LoadImm { x, nn }            => format!("{} := {:#04X}", reg(x), nn),
+    Instruction::AddImm { x, nn }             => format!("{} += {:#04X}", reg(x), nn),
+    Instruction::Move { x, y }                => format!("{} := {}", reg(x), reg(y)),
+    Instruction::Or { x, y }                  => format!("{} |= {}", reg(x), reg(y)),
+    Instruction::And { x, y }                 => format!("{} &= {}", reg(x), reg(y)),
+    Instruction::Xor { x, y }                 => format!("{} ^= {}", reg(x), reg(y)),
+    Instruction::Add { x, y }                 => format!("{} += {}", reg(x), reg(y)),
+    Instruction::Sub { x, y }                 => format!("{} -= {}", reg(x), reg(y)),
+    Instruction::ShiftRight { x, y }          => format!("{} >>= {}", reg(x), reg(y)),
+    Instruction::SubReverse { x, y }          => format!("{} =- {}", reg(x), reg(y)),
+    Instruction::ShiftLeft { x, y }           => format!("{} <<= {}", reg(x), reg(y)),
+    Instruction::SkipNeReg { x, y }           => format!("if {} == {} then", reg(x), reg(y)),
+    Instruction::LoadIndex { nnn }            => format!("i := {:#05X}", nnn),
+    Instruction::JumpOffset { nnn }           => format!("jump0 {:#05X}", nnn),
+    Instruction::Random { x, nn }             => format!("{} := random {:#04X}", reg(x), nn),
+    Instruction::Draw { x, y, n }             => format!("sprite {} {} {}", reg(x), reg(y), n),
+    Instruction::SkipKeyPressed { x }         => format!("if {} -key then", reg(x)),
+    Instruction::SkipKeyNotPressed { x }      => format!("if {} key then", reg(x)),
+    Instruction::LoadLongIndex                => format!("i := long {:#06X}", long),
+    Instruction::SelectPlanes { n }           => format!("plane {}", n),
+    Instruction::LoadAudio                    => "audio".to_string(),
+    Instruction::GetDelay { x }               => format!("{} := delay", reg(x)),
+    Instruction::WaitKey { x }                => format!("{} := key", reg(x)),
+    Instruction::SetDelay { x }               => format!("delay := {}", reg(x)),
+    Instruction::SetSound { x }               => format!("buzzer := {}", reg(x)),
+    Instruction::AddIndex { x }               => format!("i += {}", reg(x)),
+    Instruction::FontChar { x }               => format!("i := hex {}", reg(x)),
+    Instruction::BigFontChar { x }            => format!("i := bighex {}", reg(x)),
+    Instruction::Bcd { x }                    => format!("bcd {}", reg(x)),
+    Instruction::SetPitch { x }               => format!("pitch := {}", reg(x)),
+    Instruction::Store { x }                  => format!("save v{:x}", x),
+    Instruction::Load { x }                   => format!("load v{:x}", x),
+    Instruction::SaveFlags { x }              => format!("saveflags v{:x}", x),
+    Instruction::LoadFlags { x }              => format!("loadflags v{:x}", x),
+  }
+}
+
+/// The instruction in classic mnemonics, with the address of a
+/// `LoadLongIndex` filled in.
+pub fn cowgod(instruction:Instruction, long:u16) -> String
+{
+  match instruction
+  {
+    Instruction::LoadLongIndex => format!("LD I, {:#06X}", long),
+    _                          => instruction.to_string(),
+  }
+}
+
+/// What the instruction does, as pseudo-code in the style of
+/// `pong_translation.txt`, e.g. `V[10] = 0x20`. The ambiguous instructions
+/// are described the way `quirks` has them behave.
+pub fn pseudo_code(instruction:Instruction, quirks:&Quirks, long:u16) -> String
+{
+  let flag = |reset:bool| if reset { ", V[15] = 0" } else { "" };
+
+  match instruction
+  {
+    Instruction::Undefined { .. }             => "data".to_string(),
+    Instruction::Sys { nnn }                  => format!("call machine code at {:#05X}", nnn),
+    Instruction::Cls                          => "clear the screen".to_string(),
+    Instruction::Ret                          => "return".to_string(),
+    Instruction::ScrollDown { n }             => format!("scroll down {} rows", n),
+    Instruction::ScrollRight                  => "scroll right 4 pixels".to_string(),
+    Instruction::ScrollLeft                   => "scroll left 4 pixels".to_string(),
+    Instruction::Exit                         => "exit".to_string(),
+    Instruction::LowRes                       => "switch to 64x32".to_string(),
+    Instruction::HighRes                      => "switch to 128x64".to_string(),
+    Instruction::Jump { nnn }                 => format!("PC = {:#05X}", nnn),
+    Instruction::Call { nnn }                 => format!("call {:#05X}", nnn),
+    Instruction::SkipEqImm { x, nn }          => format!("if V[{}] == {:#04X} skip next", x, nn),
+    Instruction::SkipNeImm { x, nn }          => format!("if V[{}] != {:#04X} skip next", x, nn),
+    Instruction::SkipEqReg { x, y }           => format!("if V[{}] == V[{}] skip next", x, y),
+    Instruction::SaveRange { x, y }           => format!("memory[I..] = V[{}]..V[{}]", x, y),
+    Instruction::LoadRange { x, y }           => format!("V[{}]..V[{}] = memory[I..]", x, y),
+    Instruction::LoadImm { x, nn }            => format!("V[{}] = {:#04X}", x, nn),
+    Instruction::AddImm { x, nn }             => format!("V[{}] += {:#04X}", x, nn),
+    Instruction::Move { x, y }                => format!("V[{}] = V[{}]", x, y),
+    Instruction::Or { x, y }                  => format!("V[{}] |= V[{}]{}", x, y, flag(quirks.logic_resets_vf)),
+    Instruction::And { x, y }                 => format!("V[{}] &= V[{}]{}", x, y, flag(quirks.logic_resets_vf)),
+    Instruction::Xor { x, y }                 => format!("V[{}] ^= V[{}]{}", x, y, flag(quirks.logic_resets_vf)),
+    Instruction::Add { x, y }                 => format!("V[{}] += V[{}], V[15] = carry", x, y),
+    Instruction::Sub { x, y }                 => format!("V[{}] -= V[{}], V[15] = no borrow", x, y),
+    Instruction::SubReverse { x, y }          => format!("V[{}] = V[{}] - V[{}], V[15] = no borrow", x, y, x),
+    Instruction::ShiftRight { x, y } =>
+    {
+      let src = if quirks.shift_uses_vy { y } else { x };
+
+      format!("V[{}] = V[{}] >> 1, V[15] = bit shifted out", x, src)
+    },
+    Instruction::ShiftLeft { x, y } =>
+    {
+      let src = if quirks.shift_uses_vy { y } else { x };
+
+      format!("V[{}] = V[{}] << 1, V[15] = bit shifted out", x, src)
+    },
+    Instruction::SkipNeReg { x, y }           => format!("if V[{}] != V[{}] skip next", x, y),
+    Instruction::LoadIndex { nnn }            => format!("I = {:#05X}", nnn),
+    Instruction::JumpOffset { nnn } =>
+    {
+      let x = if quirks.jump_uses_vx { nnn >> 8 } else { 0 };
+
+      format!("PC = {:#05X} + V[{}]", nnn, x)
+    },
+    Instruction::Random { x, nn }             => format!("V[{}] = random & {:#04X}", x, nn),
+    Instruction::Draw { x, y, n:0 }           => format!("draw 16x16 sprite I at V[{}], V[{}], V[15] = collision", x, y),
+    Instruction::Draw { x, y, n }             => format!("draw 8x{} sprite I at V[{}], V[{}], V[15] = collision", n, x, y),
+    Instruction::SkipKeyPressed { x }         => format!("if key V[{}] is down skip next", x),
+    Instruction::SkipKeyNotPressed { x }      => format!("if key V[{}] is up skip next", x),
+    Instruction::LoadLongIndex                => format!("I = {:#06X}", long),
+    Instruction::SelectPlanes { n }           => format!("planes = {}", n),
+    Instruction::LoadAudio                    => "audio pattern = memory[I..I+16]".to_string(),
+    Instruction::GetDelay { x }               => format!("V[{}] = delay timer", x),
+    Instruction::WaitKey { x }                => format!("V[{}] = wait for a key", x),
+    Instruction::SetDelay { x }               => format!("delay timer = V[{}]", x),
+    Instruction::SetSound { x }               => format!("sound timer = V[{}]", x),
+    Instruction::AddIndex { x } =>
+    {
+      let overflow = if quirks.index_overflow_sets_vf { ", V[15] = overflow" } else { "" };
+
+      format!("I += V[{}]{}", x, overflow)
+    },
+    Instruction::FontChar { x }               => format!("I = font digit V[{}]", x),
+    Instruction::BigFontChar { x }            => format!("I = big font digit V[{}]", x),
+    Instruction::Bcd { x }                    => format!("memory[I..I+3] = decimal digits of V[{}]", x),
+    Instruction::SetPitch { x }               => format!("pitch = V[{}]", x),
+    Instruction::Store { x } =>
+    {
+      let step = if quirks.load_store_increments_i { format!(", I += {}", x + 1) } else { String::new() };
+
+      format!("memory[I..] = V[0]..V[{}]{}", x, step)
+    },
+    Instruction::Load { x } =>
+    {
+      let step = if quirks.load_store_increments_i { format!(", I += {}", x + 1) } else { String::new() };
+
+      format!("V[0]..V[{}] = memory[I..]{}", x, step)
+    },
+    Instruction::SaveFlags { x }              => format!("flags = V[0]..V[{}]", x),
+    Instruction::LoadFlags { x }              => format!("V[0]..V[{}] = flags", x),
+  }
+}
+
+/// The instruction at `offset` in `rom`, as the interpreter would execute
+/// it on `platform`, and its size in bytes. `None` past the end of `rom`.
+pub fn decode_at(rom:&[u8], offset:usize, platform:Platform) -> Option<(Instruction, usize)>
+{
+  let bytes = rom.get(offset..offset + 2)?;
+  let mut instruction = OpCode::new((bytes[0] as u16) << 8 | bytes[1] as u16).decode();
+
+  if !platform.supports(instruction.symbol())
+  {
+    instruction = Instruction::Undefined { opcode:instruction.encode() };
+  }
+
+  // Without its address a trailing F000 is just data.
+  if instruction == Instruction::LoadLongIndex && rom.len() < offset + 4
+  {
+    instruction = Instruction::Undefined { opcode:0xF000 };
+  }
+
+  Some((instruction, instruction.size() as usize))
+}
+
+/// Turns ROM images into listings: address, raw bytes, opcode pattern,
+/// mnemonic and a pseudo-code comment per instruction. Decoding goes
+/// through `OpCode`, like the interpreter's, so the two always agree.
+pub struct Disassembler
+{
+  platform:Platform,
+  quirks:  Quirks,
+  syntax:  Syntax,
+}
+
+impl Disassembler
+{
+  /// A disassembler for programs of `platform`, described with that
+  /// platform's quirks.
+  pub fn new(platform:Platform, syntax:Syntax) -> Self
+  {
+    Disassembler { platform, quirks:Quirks::preset(platform), syntax }
+  }
+
+  pub fn set_quirks(&mut self, quirks:Quirks)
+  {
+    self.quirks = quirks;
+  }
+
+  /// The instruction at `offset` in `rom` and its size in bytes.
+  pub fn decode_at(&self, rom:&[u8], offset:usize) -> Option<(Instruction, usize)>
+  {
+    decode_at(rom, offset, self.platform)
+  }
+
+  /// One line per instruction, assuming the ROM is loaded at 0x200 and
+  /// code and data are both 2 byte aligned. An odd trailing byte is
+  /// listed as data.
+  pub fn listing(&self, rom:&[u8]) -> String
+  {
+    let comment = match self.syntax { Syntax::Octo => '#', Syntax::Cowgod => ';' };
+
+    let mut out    = String::new();
+    let mut offset = 0;
+
+    while offset < rom.len()
+    {
+      let addr = PROGRAM_START + offset;
+
+      let (instruction, size) = match self.decode_at(rom, offset)
+      {
+        Some(decoded) => decoded,
+        None =>
+        {
+          let data = match self.syntax
+          {
+            Syntax::Octo   => format!("{:#04X}", rom[offset]),
+            Syntax::Cowgod => format!("DB {:#04X}", rom[offset]),
+          };
+
+          let _ = writeln!(out, "{:#05X}  {:02X}           {:<6}{:<24}{} data", addr, rom[offset], "", data, comment);
+          break;
+        },
+      };
+
+      let bytes = &rom[offset..offset + size];
+      let long  = if size == 4 { (bytes[2] as u16) << 8 | bytes[3] as u16 } else { 0 };
+
+      let raw   = bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ");
+
+      let symbol = match instruction.symbol()
+      {
+        OpCodeSymbol::UNDEF => "DATA".to_string(),
+        symbol              => format!("{:?}", symbol).trim_start_matches('_').to_string(),
+      };
+
+      let text = match self.syntax
+      {
+        Syntax::Octo   => octo(instruction, long),
+        Syntax::Cowgod => cowgod(instruction, long),
+      };
+
+      let note = match instruction
+      {
+        Instruction::Undefined { opcode:0xF000 } if self.platform.supports(OpCodeSymbol::_F000)
+            => "long load without its address".to_string(),
+        // Opcodes of other platforms decode to Undefined; say why.
+        Instruction::Undefined { opcode } if OpCode::new(opcode).find_opcode_symbol() != OpCodeSymbol::UNDEF
+            => format!("not an instruction on {}", self.platform),
+        _   => pseudo_code(instruction, &self.quirks, long),
+      };
+
+      let _ = writeln!(out, "{:#05X}  {:<11}  {:<6}{:<24}{} {}", addr, raw, symbol, text, comment, note);
+
+      offset += size;
+    }
+
+    out
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  // Clear, point I at the sprite, draw it and loop; then the sprite, with
+  // an odd trailing byte.
+  const ROM:[u8;13] = [0x00, 0xE0, 0xA2, 0x08, 0xD0, 0x15, 0x12, 0x06, 0xF0, 0x90, 0x90, 0x90, 0xF0];
+
+  #[test]
+  fn listings_in_both_syntaxes()
+  {
+    assert_eq!(Disassembler::new(Platform::CosmacVip, Syntax::Cowgod).listing(&ROM), "\
+0x200  00 E0        00E0  CLS                     ; clear the screen
+0x202  A2 08        ANNN  LD I, 0x208             ; I = 0x208
+0x204  D0 15        DXYN  DRW V0, V1, 5           ; draw 8x5 sprite I at V[0], V[1], V[15] = collision
+0x206  12 06        1NNN  JP 0x206                ; PC = 0x206
+0x208  F0 90        DATA  DW 0xF090               ; data
+0x20A  90 90        9XY0  SNE V0, V9              ; if V[0] != V[9] skip next
+0x20C  F0                 DB 0xF0                 ; data
+");
+
+    assert_eq!(Disassembler::new(Platform::CosmacVip, Syntax::Octo).listing(&ROM), "\
+0x200  00 E0        00E0  clear                   # clear the screen
+0x202  A2 08        ANNN  i := 0x208              # I = 0x208
+0x204  D0 15        DXYN  sprite v0 v1 5          # draw 8x5 sprite I at V[0], V[1], V[15] = collision
+0x206  12 06        1NNN  jump 0x206              # PC = 0x206
+0x208  F0 90        DATA  0xF0 0x90               # data
+0x20A  90 90        9XY0  if v0 == v9 then        # if V[0] != V[9] skip next
+0x20C  F0                 0xF0                    # data
+");
+  }
+
+  #[test]
+  fn listings_follow_the_platform()
+  {
+    // Long load of I, high resolution
+    let rom = [0xF0, 0x00, 0x12, 0x34, 0x00, 0xFF];
+
+    assert_eq!(Disassembler::new(Platform::XoChip, Syntax::Cowgod).listing(&rom), "\
+0x200  F0 00 12 34  F000  LD I, 0x1234            ; I = 0x1234
+0x204  00 FF        00FF  HIGH                    ; switch to 128x64
+");
+
+    assert_eq!(Disassembler::new(Platform::CosmacVip, Syntax::Cowgod).listing(&rom), "\
+0x200  F0 00        DATA  DW 0xF000               ; not an instruction on vip
+0x202  12 34        1NNN  JP 0x234                ; PC = 0x234
+0x204  00 FF        DATA  DW 0x00FF               ; not an instruction on vip
+");
+  }
+}
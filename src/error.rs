@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Everything that can stop the interpreter in the middle of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error
+{
+  /// `_2NNN` with all 16 stack levels in use.
+  StackOverflow { pc:u16 },
+  /// `_00EE` with an empty stack.
+  StackUnderflow { pc:u16 },
+  /// An opcode that is undefined on the selected platform.
+  InvalidOpcode { pc:u16, opcode:u16 },
+  /// A fetch, sprite read or load/store past the end of memory.
+  MemoryOutOfBounds { addr:usize },
+  /// The ROM does not fit between 0x200 and the end of memory.
+  RomTooLarge { size:usize, max:usize },
+}
+
+impl fmt::Display for Chip8Error
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    match self
+    {
+      Chip8Error::StackOverflow { pc }
+          => write!(f, "stack overflow calling a subroutine at PC:{:#X}", pc),
+      Chip8Error::StackUnderflow { pc }
+          => write!(f, "stack underflow returning from a subroutine at PC:{:#X}", pc),
+      Chip8Error::InvalidOpcode { pc, opcode }
+          => write!(f, "invalid opcode {:#06X} at PC:{:#X}", opcode, pc),
+      Chip8Error::MemoryOutOfBounds { addr }
+          => write!(f, "memory access out of bounds at {:#X}", addr),
+      Chip8Error::RomTooLarge { size, max }
+          => write!(f, "ROM is {} bytes, at most {} fit in memory", size, max),
+    }
+  }
+}
+
+impl Error for Chip8Error {}
+
+/// What `emulate_cycle` does when it meets an undefined opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UndefinedOpcodePolicy
+{
+  /// Return `Chip8Error::InvalidOpcode` and leave PC on the opcode.
+  #[default]
+  Halt,
+  /// Treat the opcode as a 2 byte no-op.
+  Skip,
+  /// Like `Skip`, but keep the opcode for `Chip8::take_skipped_opcodes`.
+  Log,
+}
+
+impl FromStr for UndefinedOpcodePolicy
+{
+  type Err = String;
+
+  fn from_str(s:&str) -> Result<Self, Self::Err>
+  {
+    match s.to_lowercase().as_str()
+    {
+      "halt" => Ok(UndefinedOpcodePolicy::Halt),
+      "skip" => Ok(UndefinedOpcodePolicy::Skip),
+      "log"  => Ok(UndefinedOpcodePolicy::Log),
+      _ => Err(format!("unknown undefined opcode policy '{}'", s)),
+    }
+  }
+}
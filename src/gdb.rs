@@ -0,0 +1,617 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use crate::chip8::Chip8;
+use crate::debugger::Debugger;
+use crate::scheduler::Scheduler;
+
+/// Target description sent to gdb. Registers are numbered in this order:
+/// V0-VF are 0-15, then I, PC, SP, DT and ST.
+pub const TARGET_XML:&str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+const NUM_REGISTERS:usize = 21;
+
+// Stop replies: SIGINT after an interrupt, SIGTRAP otherwise.
+const STOP_INTERRUPTED:&str = "S02";
+const STOP_TRAPPED:&str     = "S05";
+
+fn hex_bytes(bytes:&[u8]) -> String
+{
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex_bytes(hex:&str) -> Option<Vec<u8>>
+{
+  if !hex.len().is_multiple_of(2)
+  {
+    return None;
+  }
+
+  (0..hex.len()).step_by(2).map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok()).collect()
+}
+
+fn parse_hex(hex:&str) -> Option<usize>
+{
+  usize::from_str_radix(hex, 16).ok()
+}
+
+// Register `reg` as little-endian bytes, 1 or 2 of them.
+fn read_register(chip8:&Chip8, reg:usize) -> Option<Vec<u8>>
+{
+  let regs = chip8.regs();
+
+  match reg
+  {
+    0..=15 => Some(vec![regs.V[reg]]),
+    16     => Some(regs.I.to_le_bytes().to_vec()),
+    17     => Some(regs.PC.to_le_bytes().to_vec()),
+    18     => Some(vec![chip8.stack().sp as u8]),
+    19     => Some(vec![regs.DELAY_TIMER]),
+    20     => Some(vec![regs.SOUND_TIMER]),
+    _      => None,
+  }
+}
+
+fn write_register(chip8:&mut Chip8, reg:usize, bytes:&[u8]) -> Option<()>
+{
+  let value = match *bytes
+  {
+    [low]        => low as u16,
+    [low, high]  => u16::from_le_bytes([low, high]),
+    _            => return None,
+  };
+
+  match reg
+  {
+    0..=15 => chip8.regs_mut().V[reg]       = value as u8,
+    16     => chip8.regs_mut().I            = value,
+    17     => chip8.regs_mut().PC           = value,
+    18     => chip8.stack_mut().sp          = value.min(16),
+    19     => chip8.regs_mut().DELAY_TIMER  = value as u8,
+    20     => chip8.regs_mut().SOUND_TIMER  = value as u8,
+    _      => return None,
+  }
+
+  Some(())
+}
+
+/// A GDB remote serial protocol server for one connection. It drives the
+/// machine through a `Debugger`: breakpoints set from gdb are debugger
+/// breakpoints, and `continue` lets the debugger's `run_frame` run until
+/// it stops, at which point the frontend calls `notify_stop`.
+pub struct GdbStub
+{
+  stream:   TcpStream,
+  input:    Vec<u8>, // Bytes received but not yet handled
+  no_ack:   bool, // gdb asked for QStartNoAckMode
+  running:  bool, // A continue is waiting for its stop reply
+  closed:   bool,
+  killed:   bool, // gdb sent k, so the program should end
+}
+
+impl GdbStub
+{
+  /// Wait on localhost:`port` for gdb to connect.
+  pub fn listen(port:u16) -> io::Result<Self>
+  {
+    let listener    = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    let (stream, _) = listener.accept()?;
+
+    // Polled from the frontend's loop, which must never block on gdb.
+    stream.set_nonblocking(true)?;
+    stream.set_nodelay(true)?;
+
+    Ok(GdbStub { stream, input:Vec::new(), no_ack:false, running:false, closed:false, killed:false })
+  }
+
+  /// False once gdb detached, killed the session or went away.
+  pub fn connected(&self) -> bool
+  {
+    !self.closed
+  }
+
+  /// True once gdb killed the program, which the frontend should then
+  /// end.
+  pub fn killed(&self) -> bool
+  {
+    self.killed
+  }
+
+  fn send(&mut self, packet:&str)
+  {
+    let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+
+    if self.stream.write_all(format!("${}#{:02x}", packet, checksum).as_bytes()).is_err()
+    {
+      self.closed = true;
+    }
+  }
+
+  /// Tell gdb the machine stopped, if it is waiting for that.
+  pub fn notify_stop(&mut self)
+  {
+    if self.running
+    {
+      self.running = false;
+      self.send(STOP_TRAPPED);
+    }
+  }
+
+  /// Handle everything gdb sent since the last call.
+  pub fn poll(&mut self, chip8:&mut Chip8, scheduler:&mut Scheduler, debugger:&mut Debugger)
+  {
+    let mut buf = [0u8;4096];
+
+    loop
+    {
+      match self.stream.read(&mut buf)
+      {
+        Ok(0) => { self.closed = true; break; },
+        Ok(len) => self.input.extend_from_slice(&buf[..len]),
+        Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+        Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+        Err(_) => { self.closed = true; break; },
+      }
+    }
+
+    while let Some(packet) = self.next_packet(debugger)
+    {
+      let reply = self.handle(&packet, chip8, scheduler, debugger);
+
+      if let Some(reply) = reply
+      {
+        self.send(&reply);
+      }
+    }
+  }
+
+  // Take the next complete packet out of the input, acknowledging it.
+  // Acks from gdb are skipped and Ctrl-C interrupts a running target.
+  fn next_packet(&mut self, debugger:&mut Debugger) -> Option<String>
+  {
+    loop
+    {
+      match *self.input.first()?
+      {
+        b'$' => break,
+        0x03 =>
+        {
+          debugger.pause();
+
+          if self.running
+          {
+            self.running = false;
+            self.send(STOP_INTERRUPTED);
+          }
+        },
+        _ => {},
+      }
+
+      self.input.remove(0);
+    }
+
+    let end = self.input.iter().position(|&byte| byte == b'#')?;
+
+    // The two checksum digits haven't arrived yet.
+    if self.input.len() < end + 3
+    {
+      return None;
+    }
+
+    let packet:Vec<u8> = self.input.drain(..end + 3).collect();
+
+    if !self.no_ack
+    {
+      let _ = self.stream.write_all(b"+");
+    }
+
+    Some(String::from_utf8_lossy(&packet[1..end]).into_owned())
+  }
+
+  // The reply to `packet`, or None if the reply comes later (continue).
+  fn handle(&mut self, packet:&str, chip8:&mut Chip8, scheduler:&mut Scheduler, debugger:&mut Debugger)
+    -> Option<String>
+  {
+    let error = || Some("E01".to_string());
+
+    let reply = match packet.as_bytes().first()
+    {
+      Some(b'?') => STOP_TRAPPED.to_string(),
+
+      Some(b'q') | Some(b'Q') => return self.handle_query(packet),
+
+      Some(b'H') => "OK".to_string(),
+
+      Some(b'g') =>
+      {
+        (0..NUM_REGISTERS).filter_map(|reg| read_register(chip8, reg)).map(|bytes| hex_bytes(&bytes)).collect()
+      },
+
+      Some(b'G') =>
+      {
+        let bytes = match parse_hex_bytes(&packet[1..]) { Some(bytes) => bytes, None => return error() };
+
+        let mut offset = 0;
+
+        for reg in 0..NUM_REGISTERS
+        {
+          let size = if reg == 16 || reg == 17 { 2 } else { 1 };
+
+          if let Some(value) = bytes.get(offset..offset + size)
+          {
+            write_register(chip8, reg, value);
+          }
+
+          offset += size;
+        }
+
+        "OK".to_string()
+      },
+
+      Some(b'p') =>
+      {
+        match parse_hex(&packet[1..]).and_then(|reg| read_register(chip8, reg))
+        {
+          Some(bytes) => hex_bytes(&bytes),
+          None        => return error(),
+        }
+      },
+
+      Some(b'P') =>
+      {
+        let written = packet[1..].split_once('=').and_then(|(reg, value)|
+        {
+          write_register(chip8, parse_hex(reg)?, &parse_hex_bytes(value)?)
+        });
+
+        if written.is_none()
+        {
+          return error();
+        }
+
+        "OK".to_string()
+      },
+
+      Some(b'm') =>
+      {
+        // The range's end, or None if it is malformed or overflows.
+        let range = packet[1..].split_once(',').and_then(|(addr, len)|
+        {
+          let addr = parse_hex(addr)?;
+
+          Some((addr, addr.checked_add(parse_hex(len)?)?))
+        });
+
+        let memory = &chip8.memory().memory;
+
+        match range
+        {
+          Some((addr, end)) if addr < memory.len() => hex_bytes(&memory[addr..end.min(memory.len())]),
+          _ => return error(),
+        }
+      },
+
+      Some(b'M') =>
+      {
+        let write = packet[1..].split_once(':').and_then(|(range, data)|
+        {
+          let (addr, len) = range.split_once(',')?;
+          let addr        = parse_hex(addr)?;
+
+          Some((addr, addr.checked_add(parse_hex(len)?)?, parse_hex_bytes(data)?))
+        });
+
+        match write
+        {
+          Some((addr, end, data)) if data.len() == end - addr && end <= chip8.memory().size() =>
+          {
+            chip8.memory_mut().memory[addr..end].copy_from_slice(&data);
+
+            "OK".to_string()
+          },
+          _ => return error(),
+        }
+      },
+
+      Some(b'Z') | Some(b'z') =>
+      {
+        // Only software breakpoints (type 0): "Z0,addr,kind".
+        let mut fields = packet[1..].split(',');
+
+        let addr = match (fields.next(), fields.next().and_then(parse_hex))
+        {
+          (Some("0"), Some(addr)) if addr <= 0xFFFF => addr as u16,
+          _ => return Some(String::new()),
+        };
+
+        if packet.starts_with('Z')
+        {
+          debugger.add_breakpoint(addr);
+        }
+        else
+        {
+          debugger.remove_breakpoint(addr);
+        }
+
+        "OK".to_string()
+      },
+
+      Some(b's') | Some(b'c') =>
+      {
+        // An optional address to resume from.
+        if let Some(addr) = parse_hex(&packet[1..])
+        {
+          chip8.regs_mut().PC = addr as u16;
+        }
+
+        if packet.starts_with('c')
+        {
+          debugger.resume();
+          self.running = true;
+
+          return None;
+        }
+
+        // A fault while stepping still stops with SIGTRAP; gdb shows PC.
+        let _ = debugger.step(chip8, scheduler);
+
+        STOP_TRAPPED.to_string()
+      },
+
+      // Reverse execution through the undo journal. Both run to completion
+      // here, so the stop reply follows at once.
+      Some(b'b') if packet == "bs" || packet == "bc" =>
+      {
+        if packet == "bs"
+        {
+          let _ = debugger.reverse_step(chip8);
+        }
+        else
+        {
+          debugger.reverse_continue(chip8);
+        }
+
+        STOP_TRAPPED.to_string()
+      },
+
+      Some(b'D') =>
+      {
+        debugger.resume();
+        self.closed = true;
+
+        "OK".to_string()
+      },
+
+      Some(b'k') =>
+      {
+        self.closed = true;
+        self.killed = true;
+
+        return None;
+      },
+
+      // Unsupported packets get an empty reply.
+      _ => String::new(),
+    };
+
+    Some(reply)
+  }
+
+  fn handle_query(&mut self, packet:&str) -> Option<String>
+  {
+    if packet.starts_with("qSupported")
+    {
+      return Some("PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+;ReverseStep+;ReverseContinue+".to_string());
+    }
+
+    if packet == "QStartNoAckMode"
+    {
+      // This reply is still acknowledged; later ones aren't.
+      self.send("OK");
+      self.no_ack = true;
+
+      return None;
+    }
+
+    if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:")
+    {
+      let chunk = range.split_once(',').and_then(|(offset, len)|
+      {
+        let offset = parse_hex(offset)?;
+
+        Some((offset, offset.checked_add(parse_hex(len)?)?))
+      });
+
+      // gdb waits for a reply even to a malformed request.
+      let (offset, end) = match chunk
+      {
+        Some(chunk) => chunk,
+        None        => return Some("E01".to_string()),
+      };
+
+      let xml   = TARGET_XML.as_bytes();
+      let start = offset.min(xml.len());
+      let end   = end.min(xml.len());
+
+      // 'l' marks the last chunk.
+      let marker = if end == xml.len() { 'l' } else { 'm' };
+
+      return Some(format!("{}{}", marker, String::from_utf8_lossy(&xml[start..end])));
+    }
+
+    let reply = match packet
+    {
+      "qAttached"     => "1",
+      "qC"            => "QC1",
+      "qfThreadInfo"  => "m1",
+      "qsThreadInfo"  => "l",
+      _               => "",
+    };
+
+    Some(reply.to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use std::time::Duration;
+
+  use super::*;
+  use crate::scheduler::Speed;
+
+  // A stub connected to a client socket standing in for gdb.
+  fn connect() -> (GdbStub, TcpStream)
+  {
+    let listener    = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let client      = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+
+    stream.set_nonblocking(true).unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+
+    (GdbStub { stream, input:Vec::new(), no_ack:false, running:false, closed:false, killed:false }, client)
+  }
+
+  fn checksum(body:&str) -> u8
+  {
+    body.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+  }
+
+  // Send `body` as a packet and return the body of the reply, checking
+  // the ack and the reply's checksum.
+  fn exchange(stub:&mut GdbStub, client:&mut TcpStream, chip8:&mut Chip8, debugger:&mut Debugger, body:&str) -> String
+  {
+    let mut scheduler = Scheduler::new(Speed::InstructionsPerFrame(10));
+
+    client.write_all(format!("${}#{:02x}", body, checksum(body)).as_bytes()).unwrap();
+
+    let mut received = Vec::new();
+
+    for _ in 0..100
+    {
+      stub.poll(chip8, &mut scheduler, debugger);
+
+      let mut buf = [0u8;4096];
+
+      if let Ok(len) = client.read(&mut buf)
+      {
+        received.extend_from_slice(&buf[..len]);
+      }
+
+      let text = String::from_utf8_lossy(&received).into_owned();
+
+      if let Some(end) = text.find('#').filter(|&end| text.len() >= end + 3)
+      {
+        let body = text.strip_prefix("+$").expect("ack before the reply")[..end - 2].to_string();
+
+        assert_eq!(&text[end + 1..end + 3], format!("{:02x}", checksum(&body)));
+
+        return body;
+      }
+    }
+
+    panic!("no reply to {}", body);
+  }
+
+  fn start() -> (GdbStub, TcpStream, Chip8, Debugger)
+  {
+    let (stub, client) = connect();
+
+    let mut chip8 = Chip8::new();
+    chip8.initialize();
+    chip8.load_rom(&[0x6A, 0x02, 0x12, 0x02]).unwrap();
+
+    (stub, client, chip8, Debugger::new())
+  }
+
+  #[test]
+  fn memory_reads_and_writes()
+  {
+    let (mut stub, mut client, mut chip8, mut debugger) = start();
+
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "m200,4"), "6a021202");
+
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "M300,2:abcd"), "OK");
+    assert_eq!(&chip8.memory().memory[0x300..0x302], &[0xAB, 0xCD]);
+
+    // Lengths must match the data and the range must fit in memory.
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "M300,3:abcd"), "E01");
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "Mfff,2:abcd"), "E01");
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "m1000,1"), "E01");
+  }
+
+  #[test]
+  fn overflowing_ranges_are_rejected()
+  {
+    let (mut stub, mut client, mut chip8, mut debugger) = start();
+
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "m200,ffffffffffffffff"), "E01");
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "Mffffffffffffffff,2:abcd"), "E01");
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "qXfer:features:read:target.xml:ffffffffffffffff,1"),
+               "E01");
+
+    // A chunk past the end of the description is just the end marker.
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "qXfer:features:read:target.xml:ffffffff,1"), "l");
+  }
+
+  #[test]
+  fn registers_read_in_target_order()
+  {
+    let (mut stub, mut client, mut chip8, mut debugger) = start();
+
+    chip8.regs_mut().V[0xF] = 0x12;
+    chip8.regs_mut().I      = 0x0345;
+
+    let regs = exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "g");
+
+    // 16 V registers, I and PC little-endian, then SP, DT and ST.
+    assert_eq!(regs, format!("{}12{}", "00".repeat(15), "45030002000000"));
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "p11"), "0002");
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "P0=07"), "OK");
+    assert_eq!(chip8.regs().V[0x0], 0x07);
+  }
+
+  #[test]
+  fn software_breakpoints_are_debugger_breakpoints()
+  {
+    let (mut stub, mut client, mut chip8, mut debugger) = start();
+
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "Z0,202,2"), "OK");
+    assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), vec![0x202]);
+
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "z0,202,2"), "OK");
+    assert_eq!(debugger.breakpoints().count(), 0);
+
+    // Hardware breakpoints and out of range addresses aren't supported.
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "Z1,202,2"), "");
+    assert_eq!(exchange(&mut stub, &mut client, &mut chip8, &mut debugger, "Z0,10000,2"), "");
+  }
+}
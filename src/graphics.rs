@@ -0,0 +1,164 @@
+pub const SCREEN_WIDTH_PIXELS:usize     = 64;
+pub const SCREEN_HEIGHT_PIXELS:usize    = 32;
+
+// SUPER-CHIP high resolution mode (00FF).
+pub const HIRES_WIDTH_PIXELS:usize      = 128;
+pub const HIRES_HEIGHT_PIXELS:usize     = 64;
+
+// XO-CHIP bitplanes. Each pixel byte holds one bit per plane, so a pixel
+// has one of four colors.
+pub const PLANE_1:u8     = 0x1;
+pub const PLANE_2:u8     = 0x2;
+pub const ALL_PLANES:u8  = PLANE_1 | PLANE_2;
+
+/// The CHIP-8 framebuffer. One byte per pixel, row major. Bit 0 is the
+/// first bitplane and bit 1 the XO-CHIP second bitplane, so classic
+/// programs only ever see 0x1 (lit) and 0x0 (dark). The buffer is sized
+/// for the 128x64 SUPER-CHIP mode; in low resolution only the first 64x32
+/// bytes are used.
+pub struct Graphics
+{
+  pub gfx:[u8;HIRES_WIDTH_PIXELS*HIRES_HEIGHT_PIXELS],
+  hires:bool,
+}
+
+impl Graphics
+{
+  pub fn new() -> Self
+  {
+    Graphics { gfx:[0x0;HIRES_WIDTH_PIXELS*HIRES_HEIGHT_PIXELS], hires:false  }
+  }
+
+  pub fn clear(&mut self)
+  {
+    for pixel_idx in 0..self.gfx.len()
+    {
+      self.gfx[pixel_idx] = 0x0;
+    }
+  }
+
+  /// Switch between 64x32 and 128x64. Switching clears the screen.
+  pub fn set_hires(&mut self, hires:bool)
+  {
+    self.hires = hires;
+
+    self.clear();
+  }
+
+  pub fn hires(&self) -> bool
+  {
+    self.hires
+  }
+
+  /// Width in pixels of the active resolution.
+  pub fn width(&self) -> usize
+  {
+    if self.hires { HIRES_WIDTH_PIXELS } else { SCREEN_WIDTH_PIXELS }
+  }
+
+  /// Height in pixels of the active resolution.
+  pub fn height(&self) -> usize
+  {
+    if self.hires { HIRES_HEIGHT_PIXELS } else { SCREEN_HEIGHT_PIXELS }
+  }
+
+  /// The pixels of the active resolution, `width()*height()` bytes.
+  pub fn pixels(&self) -> &[u8]
+  {
+    &self.gfx[..self.width()*self.height()]
+  }
+
+  /// Returns true if the pixel at (x,y) is lit in any plane.
+  pub fn pixel(&self, x:usize, y:usize) -> bool
+  {
+    self.gfx[y*self.width() + x] != 0x0
+  }
+
+  /// The plane bits of the pixel at (x,y), 0..=3.
+  pub fn pixel_value(&self, x:usize, y:usize) -> u8
+  {
+    self.gfx[y*self.width() + x]
+  }
+
+  /// 00E0 on XO-CHIP: clear only the selected planes.
+  pub fn clear_planes(&mut self, planes:u8)
+  {
+    for pixel in self.gfx.iter_mut()
+    {
+      *pixel &= !planes;
+    }
+  }
+
+  // Move the bits of `planes` from pixel `from` (None for off screen)
+  // into pixel `to`, leaving the other planes untouched.
+  fn shift_pixel(&mut self, to:usize, from:Option<usize>, planes:u8)
+  {
+    let bits = match from
+    {
+      Some(from) => self.gfx[from] & planes,
+      None       => 0x0,
+    };
+
+    self.gfx[to] = (self.gfx[to] & !planes) | bits;
+  }
+
+  /// 00CN: move every row of `planes` down by `rows`, filling the top with
+  /// dark pixels.
+  pub fn scroll_down(&mut self, rows:usize, planes:u8)
+  {
+    let width   = self.width();
+    let height  = self.height();
+
+    for y in (0..height).rev()
+    {
+      for x in 0..width
+      {
+        let from = if y >= rows { Some((y - rows)*width + x) } else { None };
+
+        self.shift_pixel(y*width + x, from, planes);
+      }
+    }
+  }
+
+  /// 00FC: move every column of `planes` left by `cols`.
+  pub fn scroll_left(&mut self, cols:usize, planes:u8)
+  {
+    let width   = self.width();
+    let height  = self.height();
+
+    for y in 0..height
+    {
+      for x in 0..width
+      {
+        let from = if x + cols < width { Some(y*width + x + cols) } else { None };
+
+        self.shift_pixel(y*width + x, from, planes);
+      }
+    }
+  }
+
+  /// 00FB: move every column of `planes` right by `cols`.
+  pub fn scroll_right(&mut self, cols:usize, planes:u8)
+  {
+    let width   = self.width();
+    let height  = self.height();
+
+    for y in 0..height
+    {
+      for x in (0..width).rev()
+      {
+        let from = if x >= cols { Some(y*width + x - cols) } else { None };
+
+        self.shift_pixel(y*width + x, from, planes);
+      }
+    }
+  }
+}
+
+impl Default for Graphics
+{
+  fn default() -> Self
+  {
+    Graphics::new()
+  }
+}
@@ -0,0 +1,501 @@
+use std::fmt;
+
+use crate::opcode::OpCodeSymbol;
+
+/// A decoded instruction with its operands. `x` and `y` are register
+/// numbers (0x0-0xF), `n` a 4 bit, `nn` an 8 bit and `nnn` a 12 bit
+/// constant, named after the opcode patterns in `OpCodeSymbol`.
+///
+/// `decode` and `Instruction::encode` round-trip every 16 bit value:
+/// anything that is not a known instruction decodes to `Undefined` with
+/// the raw opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction
+{
+  Undefined { opcode:u16 },
+  Sys { nnn:u16 },                  // 0NNN
+  Cls,                              // 00E0
+  Ret,                              // 00EE
+  ScrollDown { n:u8 },              // 00CN
+  ScrollRight,                      // 00FB
+  ScrollLeft,                       // 00FC
+  Exit,                             // 00FD
+  LowRes,                           // 00FE
+  HighRes,                          // 00FF
+  Jump { nnn:u16 },                 // 1NNN
+  Call { nnn:u16 },                 // 2NNN
+  SkipEqImm { x:u8, nn:u8 },        // 3XNN
+  SkipNeImm { x:u8, nn:u8 },        // 4XNN
+  SkipEqReg { x:u8, y:u8 },         // 5XY0
+  SaveRange { x:u8, y:u8 },         // 5XY2
+  LoadRange { x:u8, y:u8 },         // 5XY3
+  LoadImm { x:u8, nn:u8 },          // 6XNN
+  AddImm { x:u8, nn:u8 },           // 7XNN
+  Move { x:u8, y:u8 },              // 8XY0
+  Or { x:u8, y:u8 },                // 8XY1
+  And { x:u8, y:u8 },               // 8XY2
+  Xor { x:u8, y:u8 },               // 8XY3
+  Add { x:u8, y:u8 },               // 8XY4
+  Sub { x:u8, y:u8 },               // 8XY5
+  ShiftRight { x:u8, y:u8 },        // 8XY6
+  SubReverse { x:u8, y:u8 },        // 8XY7
+  ShiftLeft { x:u8, y:u8 },         // 8XYE
+  SkipNeReg { x:u8, y:u8 },         // 9XY0
+  LoadIndex { nnn:u16 },            // ANNN
+  JumpOffset { nnn:u16 },           // BNNN
+  Random { x:u8, nn:u8 },           // CXNN
+  Draw { x:u8, y:u8, n:u8 },        // DXYN, DXY0 when n == 0
+  SkipKeyPressed { x:u8 },          // EX9E
+  SkipKeyNotPressed { x:u8 },       // EXA1
+  LoadLongIndex,                    // F000 NNNN
+  SelectPlanes { n:u8 },            // FN01
+  LoadAudio,                        // F002
+  GetDelay { x:u8 },                // FX07
+  WaitKey { x:u8 },                 // FX0A
+  SetDelay { x:u8 },                // FX15
+  SetSound { x:u8 },                // FX18
+  AddIndex { x:u8 },                // FX1E
+  FontChar { x:u8 },                // FX29
+  BigFontChar { x:u8 },             // FX30
+  Bcd { x:u8 },                     // FX33
+  SetPitch { x:u8 },                // FX3A
+  Store { x:u8 },                   // FX55
+  Load { x:u8 },                    // FX65
+  SaveFlags { x:u8 },               // FX75
+  LoadFlags { x:u8 },               // FX85
+}
+
+/// Decode a 16 bit opcode.
+pub fn decode(val:u16) -> Instruction
+{
+  let x   = ((val & 0x0F00) >> 8) as u8;
+  let y   = ((val & 0x00F0) >> 4) as u8;
+  let n   = (val & 0x000F) as u8;
+  let nn  = (val & 0x00FF) as u8;
+  let nnn = val & 0x0FFF;
+
+  match val >> 12
+  {
+    0x0 => match val
+    {
+      0x00E0 => Instruction::Cls,
+      0x00EE => Instruction::Ret,
+      0x00FB => Instruction::ScrollRight,
+      0x00FC => Instruction::ScrollLeft,
+      0x00FD => Instruction::Exit,
+      0x00FE => Instruction::LowRes,
+      0x00FF => Instruction::HighRes,
+      _ if val & 0xFFF0 == 0x00C0 => Instruction::ScrollDown { n },
+      _ if x != 0                 => Instruction::Sys { nnn },
+      _ => Instruction::Undefined { opcode:val },
+    },
+    0x1 => Instruction::Jump { nnn },
+    0x2 => Instruction::Call { nnn },
+    0x3 => Instruction::SkipEqImm { x, nn },
+    0x4 => Instruction::SkipNeImm { x, nn },
+    0x5 => match n
+    {
+      0x0 => Instruction::SkipEqReg { x, y },
+      0x2 => Instruction::SaveRange { x, y },
+      0x3 => Instruction::LoadRange { x, y },
+      _   => Instruction::Undefined { opcode:val },
+    },
+    0x6 => Instruction::LoadImm { x, nn },
+    0x7 => Instruction::AddImm { x, nn },
+    0x8 => match n
+    {
+      0x0 => Instruction::Move { x, y },
+      0x1 => Instruction::Or { x, y },
+      0x2 => Instruction::And { x, y },
+      0x3 => Instruction::Xor { x, y },
+      0x4 => Instruction::Add { x, y },
+      0x5 => Instruction::Sub { x, y },
+      0x6 => Instruction::ShiftRight { x, y },
+      0x7 => Instruction::SubReverse { x, y },
+      0xE => Instruction::ShiftLeft { x, y },
+      _   => Instruction::Undefined { opcode:val },
+    },
+    0x9 if n == 0 => Instruction::SkipNeReg { x, y },
+    0xA => Instruction::LoadIndex { nnn },
+    0xB => Instruction::JumpOffset { nnn },
+    0xC => Instruction::Random { x, nn },
+    0xD => Instruction::Draw { x, y, n },
+    0xE => match nn
+    {
+      0x9E => Instruction::SkipKeyPressed { x },
+      0xA1 => Instruction::SkipKeyNotPressed { x },
+      _    => Instruction::Undefined { opcode:val },
+    },
+    0xF => match nn
+    {
+      0x00 if x == 0 => Instruction::LoadLongIndex,
+      0x01 => Instruction::SelectPlanes { n:x },
+      0x02 if x == 0 => Instruction::LoadAudio,
+      0x07 => Instruction::GetDelay { x },
+      0x0A => Instruction::WaitKey { x },
+      0x15 => Instruction::SetDelay { x },
+      0x18 => Instruction::SetSound { x },
+      0x1E => Instruction::AddIndex { x },
+      0x29 => Instruction::FontChar { x },
+      0x30 => Instruction::BigFontChar { x },
+      0x33 => Instruction::Bcd { x },
+      0x3A => Instruction::SetPitch { x },
+      0x55 => Instruction::Store { x },
+      0x65 => Instruction::Load { x },
+      0x75 => Instruction::SaveFlags { x },
+      0x85 => Instruction::LoadFlags { x },
+      _    => Instruction::Undefined { opcode:val },
+    },
+    _ => Instruction::Undefined { opcode:val },
+  }
+}
+
+// Assemble an opcode from its nibbles / operands.
+fn xy(high:u16, x:u8, y:u8, n:u16) -> u16
+{
+  (high << 12) | ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | (n & 0xF)
+}
+
+fn xnn(high:u16, x:u8, nn:u8) -> u16
+{
+  (high << 12) | ((x as u16 & 0xF) << 8) | nn as u16
+}
+
+fn fx(x:u8, nn:u8) -> u16
+{
+  xnn(0xF, x, nn)
+}
+
+impl Instruction
+{
+  /// The opcode for this instruction. For `LoadLongIndex` this is only the
+  /// leading 0xF000; the address is the following word.
+  pub fn encode(&self) -> u16
+  {
+    match *self
+    {
+      Instruction::Undefined { opcode }         => opcode,
+      Instruction::Sys { nnn }                  => nnn & 0x0FFF,
+      Instruction::Cls                          => 0x00E0,
+      Instruction::Ret                          => 0x00EE,
+      Instruction::ScrollDown { n }             => 0x00C0 | (n as u16 & 0xF),
+      Instruction::ScrollRight                  => 0x00FB,
+      Instruction::ScrollLeft                   => 0x00FC,
+      Instruction::Exit                         => 0x00FD,
+      Instruction::LowRes                       => 0x00FE,
+      Instruction::HighRes                      => 0x00FF,
+      Instruction::Jump { nnn }                 => 0x1000 | (nnn & 0x0FFF),
+      Instruction::Call { nnn }                 => 0x2000 | (nnn & 0x0FFF),
+      Instruction::SkipEqImm { x, nn }          => xnn(0x3, x, nn),
+      Instruction::SkipNeImm { x, nn }          => xnn(0x4, x, nn),
+      Instruction::SkipEqReg { x, y }           => xy(0x5, x, y, 0x0),
+      Instruction::SaveRange { x, y }           => xy(0x5, x, y, 0x2),
+      Instruction::LoadRange { x, y }           => xy(0x5, x, y, 0x3),
+      Instruction::LoadImm { x, nn }            => xnn(0x6, x, nn),
+      Instruction::AddImm { x, nn }             => xnn(0x7, x, nn),
+      Instruction::Move { x, y }                => xy(0x8, x, y, 0x0),
+      Instruction::Or { x, y }                  => xy(0x8, x, y, 0x1),
+      Instruction::And { x, y }                 => xy(0x8, x, y, 0x2),
+      Instruction::Xor { x, y }                 => xy(0x8, x, y, 0x3),
+      Instruction::Add { x, y }                 => xy(0x8, x, y, 0x4),
+      Instruction::Sub { x, y }                 => xy(0x8, x, y, 0x5),
+      Instruction::ShiftRight { x, y }          => xy(0x8, x, y, 0x6),
+      Instruction::SubReverse { x, y }          => xy(0x8, x, y, 0x7),
+      Instruction::ShiftLeft { x, y }           => xy(0x8, x, y, 0xE),
+      Instruction::SkipNeReg { x, y }           => xy(0x9, x, y, 0x0),
+      Instruction::LoadIndex { nnn }            => 0xA000 | (nnn & 0x0FFF),
+      Instruction::JumpOffset { nnn }           => 0xB000 | (nnn & 0x0FFF),
+      Instruction::Random { x, nn }             => xnn(0xC, x, nn),
+      Instruction::Draw { x, y, n }             => xy(0xD, x, y, n as u16),
+      Instruction::SkipKeyPressed { x }         => xnn(0xE, x, 0x9E),
+      Instruction::SkipKeyNotPressed { x }      => xnn(0xE, x, 0xA1),
+      Instruction::LoadLongIndex                => 0xF000,
+      Instruction::SelectPlanes { n }           => fx(n, 0x01),
+      Instruction::LoadAudio                    => 0xF002,
+      Instruction::GetDelay { x }               => fx(x, 0x07),
+      Instruction::WaitKey { x }                => fx(x, 0x0A),
+      Instruction::SetDelay { x }               => fx(x, 0x15),
+      Instruction::SetSound { x }               => fx(x, 0x18),
+      Instruction::AddIndex { x }               => fx(x, 0x1E),
+      Instruction::FontChar { x }               => fx(x, 0x29),
+      Instruction::BigFontChar { x }            => fx(x, 0x30),
+      Instruction::Bcd { x }                    => fx(x, 0x33),
+      Instruction::SetPitch { x }               => fx(x, 0x3A),
+      Instruction::Store { x }                  => fx(x, 0x55),
+      Instruction::Load { x }                   => fx(x, 0x65),
+      Instruction::SaveFlags { x }              => fx(x, 0x75),
+      Instruction::LoadFlags { x }              => fx(x, 0x85),
+    }
+  }
+
+  /// The opcode pattern this instruction was decoded from.
+  pub fn symbol(&self) -> OpCodeSymbol
+  {
+    match *self
+    {
+      Instruction::Undefined { .. }             => OpCodeSymbol::UNDEF,
+      Instruction::Sys { .. }                   => OpCodeSymbol::_0NNN,
+      Instruction::Cls                          => OpCodeSymbol::_00E0,
+      Instruction::Ret                          => OpCodeSymbol::_00EE,
+      Instruction::ScrollDown { .. }            => OpCodeSymbol::_00CN,
+      Instruction::ScrollRight                  => OpCodeSymbol::_00FB,
+      Instruction::ScrollLeft                   => OpCodeSymbol::_00FC,
+      Instruction::Exit                         => OpCodeSymbol::_00FD,
+      Instruction::LowRes                       => OpCodeSymbol::_00FE,
+      Instruction::HighRes                      => OpCodeSymbol::_00FF,
+      Instruction::Jump { .. }                  => OpCodeSymbol::_1NNN,
+      Instruction::Call { .. }                  => OpCodeSymbol::_2NNN,
+      Instruction::SkipEqImm { .. }             => OpCodeSymbol::_3XNN,
+      Instruction::SkipNeImm { .. }             => OpCodeSymbol::_4XNN,
+      Instruction::SkipEqReg { .. }             => OpCodeSymbol::_5XY0,
+      Instruction::SaveRange { .. }             => OpCodeSymbol::_5XY2,
+      Instruction::LoadRange { .. }             => OpCodeSymbol::_5XY3,
+      Instruction::LoadImm { .. }               => OpCodeSymbol::_6XNN,
+      Instruction::AddImm { .. }                => OpCodeSymbol::_7XNN,
+      Instruction::Move { .. }                  => OpCodeSymbol::_8XY0,
+      Instruction::Or { .. }                    => OpCodeSymbol::_8XY1,
+      Instruction::And { .. }                   => OpCodeSymbol::_8XY2,
+      Instruction::Xor { .. }                   => OpCodeSymbol::_8XY3,
+      Instruction::Add { .. }                   => OpCodeSymbol::_8XY4,
+      Instruction::Sub { .. }                   => OpCodeSymbol::_8XY5,
+      Instruction::ShiftRight { .. }            => OpCodeSymbol::_8XY6,
+      Instruction::SubReverse { .. }            => OpCodeSymbol::_8XY7,
+      Instruction::ShiftLeft { .. }             => OpCodeSymbol::_8XYE,
+      Instruction::SkipNeReg { .. }             => OpCodeSymbol::_9XY0,
+      Instruction::LoadIndex { .. }             => OpCodeSymbol::_ANNN,
+      Instruction::JumpOffset { .. }            => OpCodeSymbol::_BNNN,
+      Instruction::Random { .. }                => OpCodeSymbol::_CXNN,
+      Instruction::Draw { n:0, .. }             => OpCodeSymbol::_DXY0,
+      Instruction::Draw { .. }                  => OpCodeSymbol::_DXYN,
+      Instruction::SkipKeyPressed { .. }        => OpCodeSymbol::_EX9E,
+      Instruction::SkipKeyNotPressed { .. }     => OpCodeSymbol::_EXA1,
+      Instruction::LoadLongIndex                => OpCodeSymbol::_F000,
+      Instruction::SelectPlanes { .. }          => OpCodeSymbol::_FN01,
+      Instruction::LoadAudio                    => OpCodeSymbol::_F002,
+      Instruction::GetDelay { .. }              => OpCodeSymbol::_FX07,
+      Instruction::WaitKey { .. }               => OpCodeSymbol::_FX0A,
+      Instruction::SetDelay { .. }              => OpCodeSymbol::_FX15,
+      Instruction::SetSound { .. }              => OpCodeSymbol::_FX18,
+      Instruction::AddIndex { .. }              => OpCodeSymbol::_FX1E,
+      Instruction::FontChar { .. }              => OpCodeSymbol::_FX29,
+      Instruction::BigFontChar { .. }           => OpCodeSymbol::_FX30,
+      Instruction::Bcd { .. }                   => OpCodeSymbol::_FX33,
+      Instruction::SetPitch { .. }              => OpCodeSymbol::_FX3A,
+      Instruction::Store { .. }                 => OpCodeSymbol::_FX55,
+      Instruction::Load { .. }                  => OpCodeSymbol::_FX65,
+      Instruction::SaveFlags { .. }             => OpCodeSymbol::_FX75,
+      Instruction::LoadFlags { .. }             => OpCodeSymbol::_FX85,
+    }
+  }
+
+  /// Size in bytes, 4 for the XO-CHIP `F000 NNNN` and 2 for the rest.
+  pub fn size(&self) -> u16
+  {
+    if *self == Instruction::LoadLongIndex { 4 } else { 2 }
+  }
+}
+
+// Classic (Cowgod) mnemonics.
+impl fmt::Display for Instruction
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    match *self
+    {
+      Instruction::Undefined { opcode }         => write!(f, "DW {:#06X}", opcode),
+      Instruction::Sys { nnn }                  => write!(f, "SYS {:#05X}", nnn),
+      Instruction::Cls                          => write!(f, "CLS"),
+      Instruction::Ret                          => write!(f, "RET"),
+      Instruction::ScrollDown { n }             => write!(f, "SCD {}", n),
+      Instruction::ScrollRight                  => write!(f, "SCR"),
+      Instruction::ScrollLeft                   => write!(f, "SCL"),
+      Instruction::Exit                         => write!(f, "EXIT"),
+      Instruction::LowRes                       => write!(f, "LOW"),
+      Instruction::HighRes                      => write!(f, "HIGH"),
+      Instruction::Jump { nnn }                 => write!(f, "JP {:#05X}", nnn),
+      Instruction::Call { nnn }                 => write!(f, "CALL {:#05X}", nnn),
+      Instruction::SkipEqImm { x, nn }          => write!(f, "SE V{:X}, {:#04X}", x, nn),
+      Instruction::SkipNeImm { x, nn }          => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+      Instruction::SkipEqReg { x, y }           => write!(f, "SE V{:X}, V{:X}", x, y),
+      Instruction::SaveRange { x, y }           => write!(f, "SAVE V{:X} - V{:X}", x, y),
+      Instruction::LoadRange { x, y }           => write!(f, "LOAD V{:X} - V{:X}", x, y),
+      Instruction::LoadImm { x, nn }            => write!(f, "LD V{:X}, {:#04X}", x, nn),
+      Instruction::AddImm { x, nn }             => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+      Instruction::Move { x, y }                => write!(f, "LD V{:X}, V{:X}", x, y),
+      Instruction::Or { x, y }                  => write!(f, "OR V{:X}, V{:X}", x, y),
+      Instruction::And { x, y }                 => write!(f, "AND V{:X}, V{:X}", x, y),
+      Instruction::Xor { x, y }                 => write!(f, "XOR V{:X}, V{:X}", x, y),
+      Instruction::Add { x, y }                 => write!(f, "ADD V{:X}, V{:X}", x, y),
+      Instruction::Sub { x, y }                 => write!(f, "SUB V{:X}, V{:X}", x, y),
+      Instruction::ShiftRight { x, y }          => write!(f, "SHR V{:X}, V{:X}", x, y),
+      Instruction::SubReverse { x, y }          => write!(f, "SUBN V{:X}, V{:X}", x, y),
+      Instruction::ShiftLeft { x, y }           => write!(f, "SHL V{:X}, V{:X}", x, y),
+      Instruction::SkipNeReg { x, y }           => write!(f, "SNE V{:X}, V{:X}", x, y),
+      Instruction::LoadIndex { nnn }            => write!(f, "LD I, {:#05X}", nnn),
+      Instruction::JumpOffset { nnn }           => write!(f, "JP V0, {:#05X}", nnn),
+      Instruction::Random { x, nn }             => write!(f, "RND V{:X}, {:#04X}", x, nn),
+      Instruction::Draw { x, y, n }             => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+      Instruction::SkipKeyPressed { x }         => write!(f, "SKP V{:X}", x),
+      Instruction::SkipKeyNotPressed { x }      => write!(f, "SKNP V{:X}", x),
+      Instruction::LoadLongIndex                => write!(f, "LD I, LONG"),
+      Instruction::SelectPlanes { n }           => write!(f, "PLANE {}", n),
+      Instruction::LoadAudio                    => write!(f, "AUDIO"),
+      Instruction::GetDelay { x }               => write!(f, "LD V{:X}, DT", x),
+      Instruction::WaitKey { x }                => write!(f, "LD V{:X}, K", x),
+      Instruction::SetDelay { x }               => write!(f, "LD DT, V{:X}", x),
+      Instruction::SetSound { x }               => write!(f, "LD ST, V{:X}", x),
+      Instruction::AddIndex { x }               => write!(f, "ADD I, V{:X}", x),
+      Instruction::FontChar { x }               => write!(f, "LD F, V{:X}", x),
+      Instruction::BigFontChar { x }            => write!(f, "LD HF, V{:X}", x),
+      Instruction::Bcd { x }                    => write!(f, "LD B, V{:X}", x),
+      Instruction::SetPitch { x }               => write!(f, "PITCH V{:X}", x),
+      Instruction::Store { x }                  => write!(f, "LD [I], V{:X}", x),
+      Instruction::Load { x }                   => write!(f, "LD V{:X}, [I]", x),
+      Instruction::SaveFlags { x }              => write!(f, "LD R, V{:X}", x),
+      Instruction::LoadFlags { x }              => write!(f, "LD V{:X}, R", x),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::chip8::Chip8;
+  use crate::quirks::Platform;
+
+  const PLATFORMS:[Platform;5] = [Platform::CosmacVip, Platform::Chip48, Platform::SuperChipLegacy,
+                                  Platform::SuperChipModern, Platform::XoChip];
+
+  #[test]
+  fn every_opcode_round_trips()
+  {
+    for val in 0..=0xFFFF
+    {
+      let instruction = decode(val);
+
+      assert_eq!(instruction.encode(), val, "{:#06X} decoded to {:?}", val, instruction);
+      assert_eq!(decode(instruction.encode()), instruction);
+    }
+  }
+
+  #[test]
+  fn operands_are_decoded()
+  {
+    let cases =
+    [
+      (0x0123, Instruction::Sys { nnn:0x123 }),
+      (0x00E0, Instruction::Cls),
+      (0x00EE, Instruction::Ret),
+      (0x00C7, Instruction::ScrollDown { n:7 }),
+      (0x00FB, Instruction::ScrollRight),
+      (0x00FC, Instruction::ScrollLeft),
+      (0x00FD, Instruction::Exit),
+      (0x00FE, Instruction::LowRes),
+      (0x00FF, Instruction::HighRes),
+      (0x1ABC, Instruction::Jump { nnn:0xABC }),
+      (0x2ABC, Instruction::Call { nnn:0xABC }),
+      (0x3A12, Instruction::SkipEqImm { x:0xA, nn:0x12 }),
+      (0x4A12, Instruction::SkipNeImm { x:0xA, nn:0x12 }),
+      (0x5AB0, Instruction::SkipEqReg { x:0xA, y:0xB }),
+      (0x5AB2, Instruction::SaveRange { x:0xA, y:0xB }),
+      (0x5AB3, Instruction::LoadRange { x:0xA, y:0xB }),
+      (0x6A12, Instruction::LoadImm { x:0xA, nn:0x12 }),
+      (0x7A12, Instruction::AddImm { x:0xA, nn:0x12 }),
+      (0x8AB0, Instruction::Move { x:0xA, y:0xB }),
+      (0x8AB1, Instruction::Or { x:0xA, y:0xB }),
+      (0x8AB2, Instruction::And { x:0xA, y:0xB }),
+      (0x8AB3, Instruction::Xor { x:0xA, y:0xB }),
+      (0x8AB4, Instruction::Add { x:0xA, y:0xB }),
+      (0x8AB5, Instruction::Sub { x:0xA, y:0xB }),
+      (0x8AB6, Instruction::ShiftRight { x:0xA, y:0xB }),
+      (0x8AB7, Instruction::SubReverse { x:0xA, y:0xB }),
+      (0x8ABE, Instruction::ShiftLeft { x:0xA, y:0xB }),
+      (0x9AB0, Instruction::SkipNeReg { x:0xA, y:0xB }),
+      (0xAABC, Instruction::LoadIndex { nnn:0xABC }),
+      (0xBABC, Instruction::JumpOffset { nnn:0xABC }),
+      (0xCA12, Instruction::Random { x:0xA, nn:0x12 }),
+      (0xDAB5, Instruction::Draw { x:0xA, y:0xB, n:5 }),
+      (0xDAB0, Instruction::Draw { x:0xA, y:0xB, n:0 }),
+      (0xEA9E, Instruction::SkipKeyPressed { x:0xA }),
+      (0xEAA1, Instruction::SkipKeyNotPressed { x:0xA }),
+      (0xF000, Instruction::LoadLongIndex),
+      (0xF301, Instruction::SelectPlanes { n:3 }),
+      (0xF002, Instruction::LoadAudio),
+      (0xFA07, Instruction::GetDelay { x:0xA }),
+      (0xFA0A, Instruction::WaitKey { x:0xA }),
+      (0xFA15, Instruction::SetDelay { x:0xA }),
+      (0xFA18, Instruction::SetSound { x:0xA }),
+      (0xFA1E, Instruction::AddIndex { x:0xA }),
+      (0xFA29, Instruction::FontChar { x:0xA }),
+      (0xFA30, Instruction::BigFontChar { x:0xA }),
+      (0xFA33, Instruction::Bcd { x:0xA }),
+      (0xFA3A, Instruction::SetPitch { x:0xA }),
+      (0xFA55, Instruction::Store { x:0xA }),
+      (0xFA65, Instruction::Load { x:0xA }),
+      (0xFA75, Instruction::SaveFlags { x:0xA }),
+      (0xFA85, Instruction::LoadFlags { x:0xA }),
+    ];
+
+    for (val, instruction) in cases.iter()
+    {
+      assert_eq!(decode(*val), *instruction, "{:#06X}", val);
+      assert_eq!(instruction.encode(), *val, "{:?}", instruction);
+    }
+  }
+
+  #[test]
+  fn unknown_opcodes_are_undefined()
+  {
+    for &val in [0x0000, 0x00E1, 0x00BF, 0x5AB1, 0x8AB8, 0x9AB1, 0xEA9F, 0xF0FF, 0xF100, 0xF102].iter()
+    {
+      assert_eq!(decode(val), Instruction::Undefined { opcode:val }, "{:#06X}", val);
+    }
+  }
+
+  #[test]
+  fn long_index_is_the_only_four_byte_instruction()
+  {
+    for val in 0..=0xFFFF
+    {
+      let instruction = decode(val);
+      let expected    = if val == 0xF000 { 4 } else { 2 };
+
+      assert_eq!(instruction.size(), expected, "{:?}", instruction);
+    }
+  }
+
+  #[test]
+  fn definedness_depends_on_platform()
+  {
+    let superchip = [Platform::SuperChipLegacy, Platform::SuperChipModern, Platform::XoChip];
+
+    let cases:[(u16, &[Platform]);14] =
+    [
+      (0x6A12, &PLATFORMS),
+      (0xDAB5, &PLATFORMS),
+      (0xDAB0, &PLATFORMS), // Draws nothing before SUPER-CHIP
+      (0x0123, &[]), // Machine code routines never run
+      (0x0000, &[]),
+      (0x00C7, &superchip),
+      (0x00FB, &superchip),
+      (0x00FF, &superchip),
+      (0xFA30, &superchip),
+      (0xFA75, &superchip),
+      (0xF000, &[Platform::XoChip]),
+      (0xF301, &[Platform::XoChip]),
+      (0x5AB2, &[Platform::XoChip]),
+      (0xFA3A, &[Platform::XoChip]),
+    ];
+
+    for (val, defined_on) in cases.iter()
+    {
+      for &platform in PLATFORMS.iter()
+      {
+        let chip8 = Chip8::with_platform(platform);
+
+        assert_eq!(chip8.is_defined(decode(*val)), defined_on.contains(&platform), "{:#06X} on {}", val, platform);
+      }
+    }
+  }
+}
@@ -0,0 +1,195 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use crate::chip8::KeyWait;
+use crate::registers::Registers;
+use crate::stack::Stack;
+use crate::watch::WatchHit;
+
+/// Memory the undo journal may use unless told otherwise.
+pub const DEFAULT_JOURNAL_BUDGET:usize = 16*1024*1024;
+
+// What an instruction changed, enough to put the machine back the way it
+// was before it ran. Registers and the stack are small enough to copy
+// whole; memory and the screen only keep the bytes that were overwritten.
+pub(crate) struct UndoEntry
+{
+  pub regs:         Registers,
+  pub stack:        Stack,
+  pub curr_opcode:  u16,
+  pub draw_flag:    bool,
+  pub wait_vblank:  bool,
+  pub halted:       bool,
+  pub key_wait:     Option<KeyWait>,
+  pub hires:        bool,
+  pub planes:       u8,
+  pub pitch:        u8,
+  pub audio_pattern:[u8;16],
+  pub rpl:          [u8;16],
+  pub rng_state:    Option<u64>,
+  pub memory:       Vec<(usize, u8)>, // (address, old byte), in write order
+  pub pixels:       Vec<(usize, u8)>, // (index, old value) of pixels a sprite flipped
+  pub screen:       Option<Vec<u8>>, // The whole framebuffer, before a clear, scroll or mode switch
+  pub hits:         Vec<WatchHit>, // Watchpoints the instruction hit
+}
+
+impl UndoEntry
+{
+  // Approximate bytes the entry takes, counting what it owns on the heap.
+  fn size(&self) -> usize
+  {
+    mem::size_of::<UndoEntry>()
+      + self.memory.len()*mem::size_of::<(usize, u8)>()
+      + self.pixels.len()*mem::size_of::<(usize, u8)>()
+      + self.screen.as_ref().map_or(0, Vec::len)
+      + self.hits.len()*mem::size_of::<WatchHit>()
+  }
+}
+
+/// A history of undo entries, one per executed instruction, oldest first.
+/// The oldest entries are dropped once the journal uses more than its
+/// memory budget.
+pub struct Journal
+{
+  budget: usize, // Bytes the journal may use
+  used:   usize, // Bytes used by `entries`
+  entries:VecDeque<UndoEntry>,
+}
+
+impl Journal
+{
+  /// A journal using at most `budget` bytes; 0 disables it.
+  pub fn new(budget:usize) -> Self
+  {
+    Journal { budget, used:0, entries:VecDeque::new() }
+  }
+
+  pub fn budget(&self) -> usize
+  {
+    self.budget
+  }
+
+  pub fn set_budget(&mut self, budget:usize)
+  {
+    self.budget = budget;
+    self.trim();
+  }
+
+  /// Approximate bytes in use.
+  pub fn used(&self) -> usize
+  {
+    self.used
+  }
+
+  /// Number of instructions that can be undone.
+  pub fn len(&self) -> usize
+  {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool
+  {
+    self.entries.is_empty()
+  }
+
+  pub fn clear(&mut self)
+  {
+    self.entries.clear();
+    self.used = 0;
+  }
+
+  pub(crate) fn push(&mut self, entry:UndoEntry)
+  {
+    if self.budget == 0
+    {
+      return;
+    }
+
+    self.used += entry.size();
+    self.entries.push_back(entry);
+    self.trim();
+  }
+
+  pub(crate) fn pop(&mut self) -> Option<UndoEntry>
+  {
+    let entry = self.entries.pop_back()?;
+
+    self.used -= entry.size();
+
+    Some(entry)
+  }
+
+  // Drop the oldest entries until the journal fits its budget.
+  fn trim(&mut self)
+  {
+    while self.used > self.budget
+    {
+      match self.entries.pop_front()
+      {
+        Some(oldest) => self.used -= oldest.size(),
+        None         => break,
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::chip8::Chip8;
+  use crate::quirks::Platform;
+
+  // Draw a digit, call 0x20E, which stores registers, scrolls, switches
+  // to high resolution, clears and draws again, then return and loop.
+  const ROM:[u8;28] = [0xF0, 0x29, 0xD1, 0x15, 0x22, 0x0E, 0x12, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                       0xA3, 0x00, 0xF3, 0x55, 0x00, 0xC2, 0x00, 0xFF, 0x00, 0xE0, 0xD1, 0x15, 0x00, 0xEE];
+
+  #[test]
+  fn stepping_back_restores_every_earlier_state()
+  {
+    let mut chip8 = Chip8::with_platform(Platform::SuperChipModern);
+    chip8.initialize();
+    chip8.load_rom(&ROM).unwrap();
+    chip8.set_journal_budget(DEFAULT_JOURNAL_BUDGET);
+
+    let mut states = vec![chip8.save_state()];
+
+    for _ in 0..11
+    {
+      chip8.emulate_cycle().unwrap();
+      states.push(chip8.save_state());
+    }
+
+    assert_eq!(chip8.journal().len(), 11);
+
+    for state in states.iter().rev().skip(1)
+    {
+      assert!(chip8.step_back().is_some());
+      assert_eq!(&chip8.save_state(), state);
+    }
+
+    assert!(chip8.step_back().is_none());
+    assert_eq!(chip8.journal().used(), 0);
+  }
+
+  #[test]
+  fn oldest_entries_are_dropped_past_the_budget()
+  {
+    let mut chip8 = Chip8::new();
+    chip8.initialize();
+    chip8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    chip8.set_journal_budget(10*mem::size_of::<UndoEntry>());
+
+    for _ in 0..100
+    {
+      chip8.emulate_cycle().unwrap();
+    }
+
+    assert_eq!(chip8.journal().len(), 10);
+    assert!(chip8.journal().used() <= chip8.journal().budget());
+
+    chip8.set_journal_budget(0);
+    assert!(chip8.journal().is_empty());
+  }
+}
@@ -0,0 +1,66 @@
+//! A headless CHIP-8 interpreter.
+//!
+//! The crate holds the machine itself (`Chip8` and its `Registers`,
+//! `Memory`, `Stack` and `Graphics`) with no dependency on any windowing
+//! library. The `chip8` binary in this package is a thin piston frontend
+//! on top of it, built with the default `gui` feature; other tools can
+//! embed the interpreter the same way and depend on the crate with
+//! `default-features = false` to leave piston out.
+
+#![allow(non_snake_case)]
+
+mod analysis;
+mod asm;
+mod chip8;
+pub mod cli;
+mod dap;
+mod debugger;
+mod decompile;
+mod disasm;
+mod error;
+mod gdb;
+mod graphics;
+mod instruction;
+mod journal;
+mod lint;
+mod memory;
+mod movie;
+mod opcode;
+mod quirks;
+mod registers;
+mod rewind;
+mod rng;
+mod savestate;
+mod scheduler;
+mod stack;
+mod symbols;
+mod trace;
+mod watch;
+
+pub use crate::analysis::{Analysis, Block, ByteKind, Function, Terminator};
+pub use crate::asm::{assemble, AsmError, Assembly};
+pub use crate::chip8::{Chip8, KeyWait, BIG_FONTSET_START, NUM_KEYS};
+pub use crate::dap::{DapServer, LaunchArgs};
+pub use crate::debugger::{Debugger, RunOutcome, DEBUGGER_HELP};
+pub use crate::decompile::{decompile, Style};
+pub use crate::disasm::{cowgod, decode_at, octo, octo_with, pseudo_code, Disassembler, Syntax};
+pub use crate::error::{Chip8Error, UndefinedOpcodePolicy};
+pub use crate::gdb::{GdbStub, TARGET_XML};
+pub use crate::graphics::{Graphics, ALL_PLANES, HIRES_HEIGHT_PIXELS, HIRES_WIDTH_PIXELS, PLANE_1, PLANE_2,
+                          SCREEN_HEIGHT_PIXELS, SCREEN_WIDTH_PIXELS};
+pub use crate::instruction::{decode, Instruction};
+pub use crate::journal::{Journal, DEFAULT_JOURNAL_BUDGET};
+pub use crate::lint::{lint, Lint};
+pub use crate::memory::{Memory, MEMORY_SIZE, PROGRAM_START, XO_MEMORY_SIZE};
+pub use crate::movie::{rom_hash, Movie, MovieError, MovieMode, MovieSession, MOVIE_FORMAT, MOVIE_VERSION};
+pub use crate::opcode::{OpCode, OpCodeSymbol};
+pub use crate::quirks::{Platform, Quirks};
+pub use crate::registers::Registers;
+pub use crate::rewind::{Rewind, DEFAULT_REWIND_BUDGET};
+pub use crate::rng::{RandomSource, XorShiftRng};
+pub use crate::savestate::{SaveState, SaveStateError, SAVE_STATE_FORMAT, SAVE_STATE_VERSION};
+pub use crate::scheduler::{Scheduler, Speed, TIMER_HZ};
+pub use crate::stack::{Stack, STACK_LEVELS};
+pub use crate::symbols::{SourceLine, SymbolMap, SymbolsError, SYMBOLS_FORMAT, SYMBOLS_VERSION};
+pub use crate::trace::{field_width, Trace, TraceLine};
+pub use crate::watch::{Access, Register, WatchHit, WatchTarget, Watchpoint};
@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::analysis::{Analysis, Terminator};
+use crate::disasm::decode_at;
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use crate::memory::PROGRAM_START;
+use crate::opcode::{OpCode, OpCodeSymbol};
+use crate::quirks::Platform;
+use crate::stack::STACK_LEVELS;
+
+/// A likely bug found in a ROM without running it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lint
+{
+  /// Execution can reach an opcode no platform defines.
+  UndefinedOpcode { addr:u16, opcode:u16 },
+  /// Execution can reach an opcode of a later platform.
+  UnsupportedOpcode { addr:u16, opcode:u16, platform:Platform },
+  /// Execution can run past the last byte of the ROM.
+  RunsOffEnd { addr:u16 },
+  /// A jump or call into the interpreter's memory below 0x200, or past
+  /// the end of the ROM.
+  TargetOutOfRange { addr:u16, target:u16 },
+  /// A jump or call to an odd address, usually a miscounted label.
+  OddTarget { addr:u16, target:u16 },
+  /// A 00EE reached from the entry point with no call to return from.
+  UnmatchedReturn { addr:u16 },
+  /// Subroutines that call each other in a cycle, which overflows the
+  /// stack unless something ends it.
+  Recursion { chain:Vec<u16> },
+  /// Calls nested deeper than the stack has levels.
+  StackOverflow { chain:Vec<u16> },
+  /// A sprite drawn from an address set just before whose bytes run past
+  /// the end of memory.
+  SpritePastMemory { addr:u16, index:u16, len:u16 },
+}
+
+impl Lint
+{
+  /// Where the problem is.
+  pub fn addr(&self) -> u16
+  {
+    match self
+    {
+      Lint::UndefinedOpcode { addr, .. } | Lint::UnsupportedOpcode { addr, .. } | Lint::RunsOffEnd { addr } |
+      Lint::TargetOutOfRange { addr, .. } | Lint::OddTarget { addr, .. } | Lint::UnmatchedReturn { addr } |
+      Lint::SpritePastMemory { addr, .. } => *addr,
+      Lint::Recursion { chain } | Lint::StackOverflow { chain } => chain[0],
+    }
+  }
+}
+
+fn describe_chain(chain:&[u16]) -> String
+{
+  chain.iter().map(|addr| format!("{:#05X}", addr)).collect::<Vec<_>>().join(" -> ")
+}
+
+impl fmt::Display for Lint
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    write!(f, "{:#05X}: ", self.addr())?;
+
+    match self
+    {
+      Lint::UndefinedOpcode { opcode, .. }
+          => write!(f, "undefined opcode {:04X}", opcode),
+      Lint::UnsupportedOpcode { opcode, platform, .. }
+          =>
+          {
+            let family = if OpCode::new(*opcode).find_opcode_symbol().is_xochip() { "XO-CHIP" } else { "SUPER-CHIP" };
+
+            write!(f, "{} opcode {:04X} isn't supported on {}", family, opcode, platform)
+          },
+      Lint::RunsOffEnd { .. }
+          => write!(f, "execution runs past the end of the ROM"),
+      Lint::TargetOutOfRange { target, .. } if (*target as usize) < PROGRAM_START
+          => write!(f, "jumps to {:#05X}, below the program at 0x200", target),
+      Lint::TargetOutOfRange { target, .. }
+          => write!(f, "jumps to {:#05X}, past the end of the ROM", target),
+      Lint::OddTarget { target, .. }
+          => write!(f, "jumps to odd address {:#05X}", target),
+      Lint::UnmatchedReturn { .. }
+          => write!(f, "returns without a matching call"),
+      Lint::Recursion { chain }
+          => write!(f, "recursive calls {} can overflow the {} level stack", describe_chain(chain), STACK_LEVELS),
+      Lint::StackOverflow { chain }
+          => write!(f, "calls nest {} deep, past the {} level stack: {}", chain.len() - 1, STACK_LEVELS, describe_chain(chain)),
+      Lint::SpritePastMemory { index, len, .. }
+          => write!(f, "draws {} sprite bytes from {:#05X}, past the end of memory", len, index),
+    }
+  }
+}
+
+// Follow the call graph from `entry`, reporting cycles and nesting deeper
+// than the stack. Returns how deep calls from `entry` nest.
+fn check_calls(analysis:&Analysis, entry:u16, path:&mut Vec<u16>, depths:&mut BTreeMap<u16, Vec<u16>>,
+               cycles:&mut BTreeSet<Vec<u16>>) -> Vec<u16>
+{
+  if let Some(deepest) = depths.get(&entry)
+  {
+    return deepest.clone();
+  }
+
+  path.push(entry);
+
+  // The longest chain of calls starting here.
+  let mut deepest = vec![entry];
+
+  for &callee in analysis.functions[&entry].calls.iter()
+  {
+    if let Some(start) = path.iter().position(|&addr| addr == callee)
+    {
+      let mut chain = path[start..].to_vec();
+
+      chain.push(callee);
+      cycles.insert(chain);
+      continue;
+    }
+
+    let below = check_calls(analysis, callee, path, depths, cycles);
+
+    if below.len() + 1 > deepest.len()
+    {
+      deepest = std::iter::once(entry).chain(below).collect();
+    }
+  }
+
+  path.pop();
+  depths.insert(entry, deepest.clone());
+
+  deepest
+}
+
+/// Check a ROM loaded at 0x200 for code reachable from the entry point
+/// that would misbehave on `platform`. Lints come in address order. Fails
+/// if the ROM runs past the 16 bit address space.
+pub fn lint(rom:&[u8], platform:Platform) -> Result<Vec<Lint>, Chip8Error>
+{
+  let analysis = Analysis::new(rom, platform)?;
+  let rom_end  = PROGRAM_START + rom.len();
+
+  let mut lints   = Vec::new();
+  let mut targets = BTreeSet::new();
+
+  for block in analysis.blocks.values()
+  {
+    let addr = block.instructions.last().map_or(block.start, |&(addr, _)| addr);
+
+    let target = match block.terminator
+    {
+      Terminator::Jump(target) | Terminator::Call { target, .. } => target,
+      _ => continue,
+    };
+
+    targets.insert(target);
+
+    if (target as usize) < PROGRAM_START || target as usize >= rom_end
+    {
+      lints.push(Lint::TargetOutOfRange { addr, target });
+    }
+    else if target % 2 == 1
+    {
+      lints.push(Lint::OddTarget { addr, target });
+    }
+  }
+
+  for &addr in analysis.invalid.iter()
+  {
+    let decoded = (addr as usize).checked_sub(PROGRAM_START).and_then(|offset| decode_at(rom, offset, platform));
+
+    match decoded
+    {
+      Some((Instruction::Undefined { opcode }, _)) if OpCode::new(opcode).find_opcode_symbol() != OpCodeSymbol::UNDEF
+          => lints.push(Lint::UnsupportedOpcode { addr, opcode, platform }),
+      Some((instruction, _))
+          => lints.push(Lint::UndefinedOpcode { addr, opcode:instruction.encode() }),
+      // Already reported as a bad jump.
+      None if targets.contains(&addr) => {},
+      None
+          => lints.push(Lint::RunsOffEnd { addr }),
+    }
+  }
+
+  // The entry point wasn't called, so it has nothing to return to.
+  let entry = &analysis.functions[&(PROGRAM_START as u16)];
+
+  for start in entry.blocks.iter()
+  {
+    let block = &analysis.blocks[start];
+
+    if let (Terminator::Return, Some(&(addr, _))) = (block.terminator, block.instructions.last())
+    {
+      lints.push(Lint::UnmatchedReturn { addr });
+    }
+  }
+
+  let mut cycles = BTreeSet::new();
+  let deepest    = check_calls(&analysis, PROGRAM_START as u16, &mut Vec::new(), &mut BTreeMap::new(), &mut cycles);
+
+  // The entry point's own level isn't on the stack.
+  if deepest.len() - 1 > STACK_LEVELS
+  {
+    lints.push(Lint::StackOverflow { chain:deepest });
+  }
+
+  lints.extend(cycles.into_iter().map(|chain| Lint::Recursion { chain }));
+
+  // Sprites drawn right after loading I, within a block.
+  for block in analysis.blocks.values()
+  {
+    let mut index = None;
+
+    for &(addr, instruction) in block.instructions.iter()
+    {
+      match instruction
+      {
+        Instruction::LoadIndex { nnn } => index = Some(nnn),
+        Instruction::LoadLongIndex =>
+        {
+          let at = addr as usize - PROGRAM_START;
+
+          index = Some((rom[at + 2] as u16) << 8 | rom[at + 3] as u16);
+        },
+        Instruction::AddIndex { .. } | Instruction::FontChar { .. } | Instruction::BigFontChar { .. } |
+        Instruction::Store { .. } | Instruction::Load { .. } => index = None,
+        Instruction::Draw { n, .. } =>
+        {
+          // DXY0 draws a 16x16 sprite where it does anything at all.
+          let len = match n
+          {
+            0 if platform.supports_superchip() => 32,
+            _                                  => n as u16,
+          };
+
+          match index
+          {
+            Some(index) if index as usize + len as usize > platform.memory_size()
+                => lints.push(Lint::SpritePastMemory { addr, index, len }),
+            _   => {},
+          }
+        },
+        _ => {},
+      }
+    }
+  }
+
+  lints.sort_by_key(Lint::addr);
+  Ok(lints)
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  fn vip(rom:&[u8]) -> Vec<Lint>
+  {
+    lint(rom, Platform::CosmacVip).unwrap()
+  }
+
+  #[test]
+  fn clean_roms_have_no_lints()
+  {
+    // Call a subroutine that returns, then loop.
+    assert_eq!(vip(&[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE]), vec![]);
+  }
+
+  #[test]
+  fn opcodes_and_the_end_of_the_rom()
+  {
+    assert_eq!(vip(&[0xFF, 0xFF]), vec![Lint::UndefinedOpcode { addr:0x200, opcode:0xFFFF }]);
+    assert_eq!(vip(&[0x00, 0xFF]), vec![Lint::UnsupportedOpcode { addr:0x200, opcode:0x00FF, platform:Platform::CosmacVip }]);
+    assert_eq!(lint(&[0x00, 0xFF, 0x12, 0x00], Platform::SuperChipModern).unwrap(), vec![]);
+    assert_eq!(vip(&[0x60, 0x00]), vec![Lint::RunsOffEnd { addr:0x202 }]);
+  }
+
+  #[test]
+  fn jump_targets()
+  {
+    assert_eq!(vip(&[0x11, 0x00]), vec![Lint::TargetOutOfRange { addr:0x200, target:0x100 }]);
+    assert_eq!(vip(&[0x12, 0x10]), vec![Lint::TargetOutOfRange { addr:0x200, target:0x210 }]);
+    assert_eq!(vip(&[0x12, 0x03, 0x00, 0x12, 0x00]), vec![Lint::OddTarget { addr:0x200, target:0x203 }]);
+
+    assert_eq!(Lint::TargetOutOfRange { addr:0x200, target:0x100 }.to_string(),
+               "0x200: jumps to 0x100, below the program at 0x200");
+  }
+
+  #[test]
+  fn calls_and_returns()
+  {
+    assert_eq!(vip(&[0x00, 0xEE]), vec![Lint::UnmatchedReturn { addr:0x200 }]);
+
+    // 0x204 calls itself.
+    assert_eq!(vip(&[0x22, 0x04, 0x12, 0x02, 0x22, 0x04, 0x00, 0xEE]), vec![Lint::Recursion { chain:vec![0x204, 0x204] }]);
+
+    // 17 subroutines, each calling the next.
+    let mut rom = vec![0x22, 0x04, 0x12, 0x02];
+
+    for sub in 0..17
+    {
+      let next:u16 = 0x204 + 4*(sub + 1);
+      let body     = if sub < 16 { [0x20 | (next >> 8) as u8, next as u8, 0x00, 0xEE] } else { [0x00, 0xEE, 0x00, 0x00] };
+
+      rom.extend_from_slice(&body);
+    }
+
+    let chain:Vec<u16> = std::iter::once(0x200).chain((0..17).map(|sub| 0x204 + 4*sub)).collect();
+
+    assert_eq!(vip(&rom), vec![Lint::StackOverflow { chain }]);
+  }
+
+  #[test]
+  fn sprites_past_memory()
+  {
+    // I = 0xFFC, draw 5 rows, loop
+    let rom = [0xAF, 0xFC, 0xD0, 0x15, 0x12, 0x04];
+
+    assert_eq!(vip(&rom), vec![Lint::SpritePastMemory { addr:0x202, index:0xFFC, len:5 }]);
+    assert_eq!(lint(&rom, Platform::XoChip).unwrap(), vec![]);
+  }
+}
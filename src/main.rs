@@ -1,1316 +1,926 @@
 use piston_window::*;
-use std::{thread, time};
-use std::fs::File;
+use std::env;
 use std::fs;
-use std::io::Read;
-use std::io::prelude::*;
-use std::fmt;
-use rand::Rng;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use chip8::cli::option_value;
+use chip8::{Chip8, DapServer, Debugger, GdbStub, Movie, MovieMode, MovieSession, Platform, Rewind, RunOutcome,
+            SaveState, Scheduler, Speed, SymbolMap, Trace, UndefinedOpcodePolicy, DEFAULT_JOURNAL_BUDGET,
+            DEFAULT_REWIND_BUDGET, SCREEN_HEIGHT_PIXELS, SCREEN_WIDTH_PIXELS, TIMER_HZ};
 
 //Colors
 
 const COLOR_BLACK:[f32;4] = [0.0, 0.0, 0.0, 1.0];
 const COLOR_RED:  [f32;4] = [1.0, 0.0, 0.0, 1.0];
 const COLOR_GREEN:[f32;4] = [0.0, 1.0, 0.0, 1.0];
+const COLOR_YELLOW:[f32;4] = [1.0, 1.0, 0.0, 1.0];
+
+// Pixel colors indexed by the XO-CHIP plane bits: dark, first plane only,
+// second plane only, both planes. Classic programs only use the first two.
+const PALETTE:[[f32;4];4] = [COLOR_BLACK, COLOR_RED, COLOR_GREEN, COLOR_YELLOW];
 
-const SCREEN_WIDTH_PIXELS:usize     = 64;
-const SCREEN_HEIGHT_PIXELS:usize    = 32;
+// Size of a low resolution pixel. High resolution pixels are scaled so the
+// 128x64 screen fills the same window.
 const PIXEL_SIZE:usize              = 10;
 
-enum OpCodeSymbol
+// Save state slots 0-9, stored next to the ROM as <rom>.state<N>.
+const NUM_SAVE_SLOTS:u8             = 10;
+
+// Set while stdout carries the debug adapter protocol, which status
+// messages mustn't end up in.
+static STDOUT_TAKEN:AtomicBool = AtomicBool::new(false);
+
+// println! for status messages, which go to stderr while stdout is taken.
+macro_rules! status
 {
-  UNDEF,
-  _0NNN,
-  _00E0,
-  _00EE,
-  _1NNN,
-  _2NNN,
-  _3XNN,
-  _4XNN,
-  _5XY0,
-  _6XNN,
-  _7XNN,
-  _8XY0,
-  _8XY1,
-  _8XY2,
-  _8XY3,
-  _8XY4,
-  _8XY5,
-  _8XY6,
-  _8XY7,
-  _8XYE,
-  _9XY0,
-  _ANNN,
-  _BNNN,
-  _CXNN,
-  _DXYN,
-  _EX9E,
-  _EXA1,
-  _FX07,
-  _FX0A,
-  _FX15,
-  _FX18,
-  _FX1E,
-  _FX29,
-  _FX33,
-  _FX55,
-  _FX65,
+  ($($arg:tt)*) =>
+  {
+    if STDOUT_TAKEN.load(Ordering::Relaxed) { eprintln!($($arg)*); } else { println!($($arg)*); }
+  };
 }
 
-struct OpCode
+// Command line settings.
+struct Options
 {
-  val:u16, // 2 bytes opcode
+  game_name:    String,
+  platform:     Option<Platform>, // None keeps the legacy quirks
+  on_undefined: UndefinedOpcodePolicy,
+  speed:        Speed,
+  seed:         Option<u64>,
+  load_state:   Option<String>, // Slot number or save state file
+  rewind_budget:usize, // Bytes, 0 disables rewinding
+  record:       Option<String>, // Movie file to record into
+  play:         Option<String>, // Movie file to play back
+  debug:        bool,
+  gdb:          Option<u16>, // Port to serve the GDB remote protocol on
+  dap:          bool, // Serve the debug adapter protocol on stdio
+  symbols:      Option<String>, // Symbol map for --debug and --gdb
+  trace:        Option<String>, // File to log executed instructions to
 }
 
-impl OpCode
+struct Emulator
 {
-  fn new(val:u16) -> Self
+  window:    PistonWindow,
+  chip8:     Chip8,
+  scheduler: Scheduler,
+  running:   bool, // Cleared when the interpreter stops on an error
+  beeping:   bool,
+  game_name: String,
+  slot:      u8, // Save state slot used by the hotkeys
+  rewind:    Rewind,
+  rewinding: bool, // Set while the rewind key is held
+  keypad:    u16, // Keys held on the keyboard, latched once per frame
+  movie:     Option<MovieSession>,
+  movie_path:Option<String>, // Where a recording is written on exit
+  debugger:  Option<Debugger>,
+  commands:  Option<Receiver<String>>, // Debugger command lines read from stdin
+  quit:      bool, // Set by the debugger's quit command
+  gdb:       Option<GdbStub>,
+  dap:       Option<DapServer>,
+}
+
+impl Emulator
+{
+  fn new(options:&Options) -> Self
   {
-    OpCode { val }
+    let mut emulator = Emulator{ window:WindowSettings::new("CHIP-8 Emulator",
+                [(SCREEN_WIDTH_PIXELS*PIXEL_SIZE) as u32,
+                 (SCREEN_HEIGHT_PIXELS*PIXEL_SIZE) as u32])
+                .exit_on_esc(true).build().unwrap(),
+                chip8: options.platform.map_or_else(Chip8::new, Chip8::with_platform),
+                scheduler: Scheduler::new(options.speed),
+                running: true,
+                beeping: false,
+                game_name: options.game_name.clone(),
+                slot: 0,
+                rewind: Rewind::new(options.rewind_budget),
+                rewinding: false,
+                keypad: 0x0,
+                movie: None,
+                movie_path: None,
+                debugger: None,
+                commands: None,
+                quit: false,
+                gdb: None,
+                dap: None };
+
+    emulator.chip8.set_undefined_opcode_policy(options.on_undefined);
+
+    if let Some(seed) = options.seed
+    {
+      emulator.chip8.reseed(seed);
+    }
+
+    // Updates arrive at the timer rate with a fixed dt, which is the
+    // emulated time the scheduler runs on. Frames are presented at the
+    // same rate.
+    emulator.window.set_ups(TIMER_HZ as u64);
+    emulator.window.set_max_fps(TIMER_HZ as u64);
+
+    emulator
   }
 
-  fn find_opcode_symbol(&self) -> OpCodeSymbol
+  fn load_game(&mut self, file_name:&str) -> Vec<u8>
   {
-    if ( self.val & 0xF000 == 0x0 ) // The first 4 bits are 0000 (0x0)
-    {
-      if ( self.val & 0x0F00 == 0x0 ) // The second 4 bits are 0000 (0x0)
-      {
-        if ( self.val & 0x00F0 == 0x00E0 ) // The third 4 bits are 1110 (0xE)
-        {
-          if ( self.val & 0x000F == 0x0 )
-          {
-            return OpCodeSymbol::_00E0;
-          }
-          else if ( self.val & 0x000F == 0x000E )
-          {
-            return OpCodeSymbol::_00EE;
-          }
-          else
-          {
-            return OpCodeSymbol::UNDEF;
-          }
-        }
-        else
-        {
-          return OpCodeSymbol::UNDEF; // Error
-        }
-      }
-      else
-      {
-        return OpCodeSymbol::_0NNN;
-      }
-    }
-    else if ( self.val & 0xF000 == 0x1000 )
-    {
-      return OpCodeSymbol::_1NNN;
-    }
-    else if ( self.val & 0xF000 == 0x2000 )
-    {
-      return OpCodeSymbol::_2NNN;
-    }
-    else if ( self.val & 0xF000 == 0x3000 )
-    {
-      return OpCodeSymbol::_3XNN;
-    }
-    else if ( self.val & 0xF000 == 0x4000 )
+    let rom = fs::read(file_name).unwrap_or_else(|err|
     {
-      return OpCodeSymbol::_4XNN;
-    }
-    else if ( self.val & 0xF000 == 0x5000 )
+      eprintln!("Can't read {}: {}", file_name, err);
+      process::exit(1);
+    });
+
+    status!("File size:{}", rom.len());
+
+    if let Err(err) = self.chip8.load_rom(&rom)
     {
-      return OpCodeSymbol::_5XY0;
+      eprintln!("Can't load {}: {}", file_name, err);
+      process::exit(1);
     }
-    else if ( self.val & 0xF000 == 0x6000 )
-    {
-      return OpCodeSymbol::_6XNN;
+
+    status!("Game loaded successfully...");
+
+    rom
+  }
+
+  fn setup(&mut self, game_name:&str) -> Vec<u8>
+  {
+    self.chip8.initialize();
+
+    self.load_game( game_name )
+  }
+
+  // Map a keyboard key to a CHIP-8 hex key, row by row from 1 to V.
+  fn keypad_key(button:Button) -> Option<u8>
+  {
+    match button
+    {
+      Button::Keyboard(Key::D1) => Some(0x0),
+      Button::Keyboard(Key::D2) => Some(0x1),
+      Button::Keyboard(Key::D3) => Some(0x2),
+      Button::Keyboard(Key::D4) => Some(0x3),
+      Button::Keyboard(Key::Q)  => Some(0x4),
+      Button::Keyboard(Key::W)  => Some(0x5),
+      Button::Keyboard(Key::E)  => Some(0x6),
+      Button::Keyboard(Key::R)  => Some(0x7),
+      Button::Keyboard(Key::A)  => Some(0x8),
+      Button::Keyboard(Key::S)  => Some(0x9),
+      Button::Keyboard(Key::D)  => Some(0xA),
+      Button::Keyboard(Key::F)  => Some(0xB),
+      Button::Keyboard(Key::Z)  => Some(0xC),
+      Button::Keyboard(Key::X)  => Some(0xD),
+      Button::Keyboard(Key::C)  => Some(0xE),
+      Button::Keyboard(Key::V)  => Some(0xF),
+      _ => None,
     }
-    else if ( self.val & 0xF000 == 0x7000 )
+  }
+
+  fn slot_path(&self, slot:u8) -> String
+  {
+    format!("{}.state{}", self.game_name, slot)
+  }
+
+  fn save_state(&mut self, path:&str)
+  {
+    match self.chip8.save_state().save(path)
     {
-      return OpCodeSymbol::_7XNN;
+      Ok(())   => status!("Saved state to {}", path),
+      Err(err) => eprintln!("Can't save state to {}: {}", path, err),
     }
-    else if ( self.val & 0xF000 == 0x8000 )
+  }
+
+  fn load_state(&mut self, path:&str) -> bool
+  {
+    let loaded = SaveState::load(path).and_then(|state| self.chip8.load_state(&state));
+
+    match loaded
     {
-      match self.val & 0x000F
+      Ok(()) =>
       {
-        0x0 => { return OpCodeSymbol::_8XY0;},
-        0x1 => { return OpCodeSymbol::_8XY1;},
-        0x2 => { return OpCodeSymbol::_8XY2;},
-        0x3 => { return OpCodeSymbol::_8XY3;},
-        0x4 => { return OpCodeSymbol::_8XY4;},
-        0x5 => { return OpCodeSymbol::_8XY5;},
-        0x6 => { return OpCodeSymbol::_8XY6;},
-        0x7 => { return OpCodeSymbol::_8XY7;},
-        0xE => { return OpCodeSymbol::_8XYE;},
-        _   => { return OpCodeSymbol::UNDEF;},
-      }
-    }
-    else if ( self.val & 0xF000 == 0x9000 )
-    {
-      return OpCodeSymbol::_9XY0;
-    }
-    else if ( self.val & 0xF000 == 0xA000 )
-    {
-      return OpCodeSymbol::_ANNN;
-    }
-    else if ( self.val & 0xF000 == 0xB000 )
-    {
-      return OpCodeSymbol::_BNNN;
-    }
-    else if ( self.val & 0xF000 == 0xC000 )
-    {
-      return OpCodeSymbol::_CXNN;
+        status!("Loaded state from {}", path);
+
+        // A state saved after a crash resumes from the same point.
+        self.running = true;
+
+        true
+      },
+      Err(err) =>
+      {
+        eprintln!("Can't load state from {}: {}", path, err);
+
+        false
+      },
     }
-    else if ( self.val & 0xF000 == 0xD000 )
+  }
+
+  // Save state hotkeys: F5 saves to the current slot, F9 loads it, and
+  // F6/F7 select the previous/next slot. Holding Backspace runs the game
+  // backwards. F8 takes over a movie being played back and records from
+  // the current frame.
+  fn handle_hotkeys(&mut self, event:&Event)
+  {
+    if event.release_args() == Some(Button::Keyboard(Key::Backspace))
     {
-      return OpCodeSymbol::_DXYN;
+      self.rewinding = false;
     }
-    else if ( self.val & 0xF000 == 0xE000 )
-    {
-      match self.val & 0x000F
-      {
-        0xE => { return OpCodeSymbol::_EX9E; },
-        0x1 => { return OpCodeSymbol::_EXA1; },
-        _   => { return OpCodeSymbol::UNDEF; },
-      }
-    }
-    else if ( self.val & 0xF000 == 0xF000 )
+
+    match event.press_args()
     {
-      if ( self.val & 0x000F == 0x7 )
-      {
-        return OpCodeSymbol::_FX07;
-      }
-      else if ( self.val & 0x000F == 0xA )
-      {
-        return OpCodeSymbol::_FX0A;
-      }
-      else if ( self.val & 0x000F == 0x9 )
+      Some(Button::Keyboard(Key::Backspace)) =>
       {
-        return OpCodeSymbol::_FX29;
-      }
-      else if ( self.val & 0x000F == 0x3 )
+        self.rewinding = self.rewind.budget() > 0;
+      },
+      Some(Button::Keyboard(Key::F5)) =>
       {
-        return OpCodeSymbol::_FX33;
-      }
-      else if ( self.val & 0x00F0 == 0x10 )
+        let path = self.slot_path(self.slot);
+
+        self.save_state(&path);
+      },
+      Some(Button::Keyboard(Key::F9)) =>
       {
-        match self.val & 0x000F
+        // Jumping to another point in time would desync the movie.
+        if self.movie.is_some()
         {
-          0x5 => { return OpCodeSymbol::_FX15; },
-          0x8 => { return OpCodeSymbol::_FX18; },
-          0xE => { return OpCodeSymbol::_FX1E; },
-          _   => { return OpCodeSymbol::UNDEF; },
+          eprintln!("Can't load a state while a movie is running");
+          return;
         }
-      }
-      else if ( self.val & 0x00F0 == 0x0050 )
-      {
-        return OpCodeSymbol::_FX55;
-      }
-      else if ( self.val & 0x00F0 == 0x0060 )
-      {
-        return OpCodeSymbol::_FX65;
-      }
-      else
-      {
-        return OpCodeSymbol::UNDEF;
-      }
-    }
-    else
-    {
-      return OpCodeSymbol::UNDEF;
-    }
-  }
-}
 
-impl fmt::Display for OpCode
-{
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
-  {
-    match self.find_opcode_symbol()
-    {
-      OpCodeSymbol::UNDEF
-          => {write!(f, "UNDEF");},
-      OpCodeSymbol::_0NNN 
-          => {write!(f,"_0NNN");},
-      OpCodeSymbol::_00E0
-          => {write!(f, "_00E0");},
-      OpCodeSymbol::_00EE
-          => {write!(f, "_00EE");},
-      OpCodeSymbol::_1NNN
-          => {write!(f, "_1NNN");},
-      OpCodeSymbol::_2NNN
-          => {write!(f, "_2NNN");},
-      OpCodeSymbol::_3XNN
-          => {write!(f, "_3XNN");},
-      OpCodeSymbol::_4XNN
-          => {write!(f, "_4XNN");},
-      OpCodeSymbol::_5XY0
-          => {write!(f, "_5XY0");},
-      OpCodeSymbol::_6XNN
-          => {write!(f, "_6XNN");},
-      OpCodeSymbol::_7XNN
-          => {write!(f, "_7XNN");},
-      OpCodeSymbol::_8XY0
-          => {write!(f, "_8XY0");},
-      OpCodeSymbol::_8XY1
-          => {write!(f, "_8XY1");},
-      OpCodeSymbol::_8XY2
-          => {write!(f, "_8XY2");},
-      OpCodeSymbol::_8XY3
-          => {write!(f, "_8XY3");},
-      OpCodeSymbol::_8XY4
-          => {write!(f, "_8XY4");},
-      OpCodeSymbol::_8XY5
-          => {write!(f, "_8XY5");},
-      OpCodeSymbol::_8XY6
-          => {write!(f, "_8XY6");},
-      OpCodeSymbol::_8XY7
-          => {write!(f, "_8XY7");},
-      OpCodeSymbol::_8XYE
-          => {write!(f, "_8XYE");},
-      OpCodeSymbol::_9XY0
-          => {write!(f, "_9XY0");},
-      OpCodeSymbol::_ANNN // Set I to the address NNN
-          => {write!(f, "_ANNN");},
-      OpCodeSymbol::_BNNN
-          => {write!(f, "_BNNN");},
-      OpCodeSymbol::_CXNN
-          => {write!(f, "_CXNN");},
-      OpCodeSymbol::_DXYN
-          => {write!(f, "_DXYN");},
-      OpCodeSymbol::_EX9E
-          => {write!(f, "_EX9E");},
-      OpCodeSymbol::_EXA1
-          => {write!(f, "_EXA1");},
-      OpCodeSymbol::_FX07
-          => {write!(f, "_FX07");},
-      OpCodeSymbol::_FX0A
-          => {write!(f, "_FX0A");},
-      OpCodeSymbol::_FX15
-          => {write!(f, "_FX15");},
-      OpCodeSymbol::_FX18
-          => {write!(f, "_FX18");},
-      OpCodeSymbol::_FX1E
-          => {write!(f, "_FX1E");},
-      OpCodeSymbol::_FX29
-          => {write!(f, "_FX29");},
-      OpCodeSymbol::_FX33
-          => {write!(f, "_FX33");},
-      OpCodeSymbol::_FX55
-          => {write!(f, "_FX55");},
-      OpCodeSymbol::_FX65
-          => {write!(f, "_FX65");},
-    }
+        let path = self.slot_path(self.slot);
 
-    write!(f," :Value={:#X}", self.val); 
+        self.load_state(&path);
+      },
+      Some(Button::Keyboard(Key::F8)) =>
+      {
+        if let Some(movie) = self.movie.as_mut().filter(|movie| movie.mode() == MovieMode::Playing)
+        {
+          movie.rerecord();
 
-    write!(f,"")
-  } 
-}
+          status!("Re-recording from frame {}", movie.frame());
+        }
+      },
+      Some(Button::Keyboard(Key::F6)) =>
+      {
+        self.slot = (self.slot + NUM_SAVE_SLOTS - 1) % NUM_SAVE_SLOTS;
 
-//  0x000-0x1FF  - Chip 8 interpreter (contains font set in emu)
-//  0x050-0x0A0  - Used for the built in 4x5 pixel font set (0-F)
-//  0x200-0xFFF  - Program ROM and work RAM
-struct Memory
-{
-  memory:[u8;4096], // 4K of memory
-}
+        status!("Save slot {}", self.slot);
+      },
+      Some(Button::Keyboard(Key::F7)) =>
+      {
+        self.slot = (self.slot + 1) % NUM_SAVE_SLOTS;
 
-impl Memory
-{
-  fn new() -> Self
-  {
-    Memory { memory:[0x0;4096] }
+        status!("Save slot {}", self.slot);
+      },
+      _ => {},
+    }
   }
 
-  fn clear(&mut self)
+  fn set_keys(&mut self, event:&Event)
   {
-    for idx in 0..4096
+    if let Some(key) = event.press_args().and_then(Emulator::keypad_key)
     {
-      self.memory[idx] = 0x0;
+      self.keypad |= 0x1 << key;
     }
-  }
-}
-
-struct Registers
-{
-  V:[u8;16], // V0..VE - 15 8-bit general purpose registers. 16th register carry flag 
-  PC:u16, // 0x000-0xFFF
-  I:u16,
-  DELAY_TIMER:u8,
-  SOUND_TIMER:u8,
-}
 
-impl Registers
-{
-  fn new() -> Self
-  {
-    Registers
+    if let Some(key) = event.release_args().and_then(Emulator::keypad_key)
     {
-      V:[0x0;16],
-      PC:0x0,
-      I:0x0,
-      DELAY_TIMER:0,
-      SOUND_TIMER:0
+      self.keypad &= !(0x1 << key);
     }
   }
 
-  fn clear(&mut self)
+  fn draw_graphics(&mut self, event:&Event)
   {
-    for idx in 0..16
-    {
-      self.V[idx] = 0x0;
-    }
+    // gfx is in chip8, but the piston graphics window
+    // is in the emulator.
+    // draw_graphics() needs to inquire the chip8 gfx matrix
+    // and draw it into the piston window.
+    let _chip8 = &self.chip8;
 
-    self.PC = 0x200; // PC starts at address 0x200
+    let width       = _chip8.graphics().width();
+    let height      = _chip8.graphics().height();
+    let pixel_size  = (SCREEN_WIDTH_PIXELS*PIXEL_SIZE) / width;
 
-    self.I = 0x0;
+    self.window.draw_2d(event,
+                        |context, graphics|
+                        {
+                          for x_idx in 0..width
+                          {
+                            for y_idx in 0..height
+                            {
+                              let color = PALETTE[_chip8.graphics().pixel_value(x_idx, y_idx) as usize];
 
-    self.DELAY_TIMER = 0x0;
+                              rectangle( color,
+                                         [1.0*((x_idx*pixel_size) as f64),
+                                          1.0*((y_idx*pixel_size) as f64),
+                                          1.0*(pixel_size as f64),
+                                          1.0*(pixel_size as f64)],
+                                         context.transform,
+                                         graphics );
+                            }
+                          }
+                        });
 
-    self.SOUND_TIMER = 0x0;
+    self.chip8.clear_draw_flag();
   }
-}
 
-impl fmt::Display for Registers 
-{
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  // Go back one frame per update, as long as there is history left.
+  fn rewind(&mut self)
   {
-    for idx in 0..16
+    if let Some(state) = self.rewind.rewind()
     {
-      if idx % 4 == 0
+      if let Err(err) = self.chip8.load_state(&state)
       {
-        write!(f,"\n");
+        eprintln!("Can't rewind: {}", err);
+        return;
       }
 
-      write!(f, "V[{}]={:#X} ", idx, self.V[idx]);
-    }
-
-    write!(f, "\n");
+      if let Some(movie) = self.movie.as_mut()
+      {
+        movie.step_back();
+      }
 
-    write!(f, "PC:{:#X}, I:{:#X}, DELAY_TIMER:{:#X}, SOUND_TIMER:{:#X}", self.PC, self.I, self.DELAY_TIMER, self.SOUND_TIMER); 
-    
-    write!(f, "")
+      self.running = true;
+    }
   }
-}
 
-struct Graphics
-{
-  // Add piston window
-  gfx:[u8;64*32],
-}
-
-impl Graphics
-{
-  fn new() -> Self
+  // The keypad for the next frame: the keyboard, or the movie being
+  // played back.
+  fn frame_keypad(&mut self) -> u16
   {
-    Graphics { gfx:[0x0;64*32]  }
-  }
+    let movie = match self.movie.as_mut()
+    {
+      Some(movie) => movie,
+      None        => return self.keypad,
+    };
 
-  fn clear(&mut self)
-  {
-    for pixel_idx in 0..64*32 // 2048
+    match movie.next_frame(self.keypad)
     {
-      self.gfx[pixel_idx] = 0x0;
-    }
-  }
-}
+      Some(keypad) => keypad,
+      None =>
+      {
+        status!("Movie finished after {} frames", movie.frame());
 
-// The Chip 8 instruction set has opcodes that allow the program to jump to a certain
-// address or call a subroutine. While the specification dont mention a stack, you
-// will need to implement one as part of the interpreter yourself. The stack is used
-// to remember the current location before a jump is performed. So anytime you
-// perform a jump or call a subroutine, store the program counter in the stack before
-// proceeding. The system has 16 levels of stack and in order to remember which level
-// of the stack is used, you need to implement a stack pointer (sp).
-struct Stack
-{
-  stack:[u16;16],
-  sp:u16,
-}
+        self.movie = None;
 
-impl Stack
-{
-  fn new() -> Self
-  {
-    Stack { stack:[0x0;16], sp:0x0 }
+        self.keypad
+      },
+    }
   }
 
-  fn clear(&mut self)
+  // Run one frame, under the debugger if there is one. Returns false if
+  // the frame didn't complete.
+  fn run_frame(&mut self) -> bool
   {
-    for idx in 0..16
+    let debugger = match self.debugger.as_mut()
     {
-      self.stack[idx] = 0x0;
-    }
+      Some(debugger) => debugger,
+      None =>
+      {
+        // Keep the window open on the last frame if the program crashes.
+        if let Err(err) = self.scheduler.run_frame(&mut self.chip8)
+        {
+          eprintln!("Emulation stopped: {}", err);
+          self.running = false;
+          return false;
+        }
 
-    self.sp = 0x0;
-  }
-}
+        return true;
+      },
+    };
 
-struct Chip8
-{
-  draw_flag:    bool,
-  regs:         Registers,
-  memory:       Memory,
-  stack:        Stack,
-  curr_opcode:  OpCode,
-  graphics:     Graphics,
-  key:          [u8;16], // HEX based 0x0-0xF
-}
+    match debugger.run_frame(&mut self.chip8, &mut self.scheduler)
+    {
+      RunOutcome::Completed => true,
+      RunOutcome::Paused    => false,
+      RunOutcome::Stopped(reason) =>
+      {
+        if let Some(gdb) = self.gdb.as_mut()
+        {
+          gdb.notify_stop();
+        }
 
-impl Chip8
-{
-  fn new() -> Self
-  {
-    Chip8 { draw_flag:false,
-            regs:Registers::new(),
-            memory:Memory::new(),
-            stack:Stack::new(),
-            curr_opcode:OpCode::new(0x0),
-            graphics:Graphics::new(),
-            key:[0x0;16],
-          }
+        if let Some(dap) = self.dap.as_mut()
+        {
+          dap.notify_stop(&self.chip8, debugger, &reason);
+        }
+
+        if self.commands.is_some()
+        {
+          println!("{}", reason);
+          Emulator::prompt();
+        }
+
+        false
+      },
+    }
   }
 
-  fn init_fontset(&mut self)
+  // Put the machine under a (paused) debugger, unless it already is, and
+  // start journaling instructions so they can be stepped backwards.
+  fn attach_debugger(&mut self)
   {
-    let chip8_fontset:[u8;80] =
-    [ 
-      0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-      0x20, 0x60, 0x20, 0x20, 0x70, // 1
-      0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-      0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-      0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-      0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-      0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-      0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-      0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-      0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-      0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-      0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-      0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-      0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-      0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-      0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-    ];
-    
-    for i in 0..80
+    if self.debugger.is_none()
     {
-      self.memory.memory[i] = chip8_fontset[i];
+      self.debugger = Some(Debugger::new());
+
+      self.chip8.set_journal_budget(DEFAULT_JOURNAL_BUDGET);
     }
   }
 
-  fn initialize(&mut self)
+  fn prompt()
   {
-    // Initialize registers and memory once.
-    self.curr_opcode = OpCode::new(0x0); // Reset current opcode. 
-
-    // Clear stack
-    self.stack.clear(); // Reset stack and stack pointer.
-    
-    // Clear registers V0-VF, PC, I, timers(delay, sound).
-    self.regs.clear();
-    
-    // Clear memory
-    self.memory.clear();
- 
-    // Clear display
-    self.graphics.clear();
-
-    // Load fontset
-    self.init_fontset();
-
-    // Clear the screen
-    self.draw_flag = true;
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
   }
 
-  fn load_game(&mut self, file_name:&str)
+  // Read debugger commands on a separate thread, so the window keeps
+  // rendering while waiting for input.
+  fn start_debugger(&mut self)
   {
-    let     file_size  = fs::metadata(file_name).unwrap().len() as usize;
-    let mut file       = File::open(file_name).unwrap();
-    let mut buffer     = [0u8;0xDFF];
-
-    println!("File size:{}", file_size);
-    
-    file.read(&mut buffer).unwrap();
+    let (sender, receiver) = mpsc::channel();
 
-    for idx in 0..file_size/*0xDFF*/
+    thread::spawn(move ||
     {
-      self.memory.memory[0x200 + idx] = buffer[idx];      
-
-      if idx % 2 == 1
+      for line in io::stdin().lock().lines().map_while(Result::ok)
       {
-        let first_half:u8   = self.memory.memory[0x200 + idx - 1];
-        let second_half:u8  = self.memory.memory[0x200 + idx];
-
-        let val:u16 = ((first_half as u16 ) << 8) | (second_half as u16);
-        
-        if val != 0
+        if sender.send(line).is_err()
         {
-          /*println!( "Opcode: {:#X}", val );
-          println!( "({},{}):", first_half, second_half );*/
-
-          let opcode = OpCode::new(val);
-
-          //println!("{}", opcode);
-          //println!("{}", opcode.find_opcode_symbol() as u32 );
+          break;
         }
       }
-    }
+    });
 
-    println!("Game loaded successfully...");
-  }
-
-  // Every cycle, the method emulateCycle is called which emulates
-  // one cycle of the Chip 8 CPU. During this cycle, 
-  // the emulator will Fetch, Decode and Execute one opcode.
-  
-  // Fetch opcode:
-  // =============
-  // During this step, the system will fetch one opcode from the
-  // memory at the location specified by the program counter (pc).
-  // In our Chip 8 emulator, data is stored in an array in which
-  // each address contains one byte. As one opcode is 2 bytes long,
-  // we will need to fetch two successive bytes and merge them to
-  // get the actual opcode.
-  fn fetch_opcode(&mut self)
-  {
-    let first_half:u8   = self.memory.memory[self.regs.PC as usize];
-    let second_half:u8  = self.memory.memory[(self.regs.PC as usize + 1)];
-
-    let val:u16 = ((first_half as u16 ) << 8) | (second_half as u16);
+    self.attach_debugger();
+    self.commands = Some(receiver);
 
-    self.curr_opcode = OpCode::new(val);
-
-    /*println!("{}", self.regs);
-    println!("opcode:{}", self.curr_opcode);*/
+    println!("Debugger paused at {:#05X}. Type help for commands.", self.chip8.regs().PC);
+    Emulator::prompt();
   }
 
-  fn execute_opcode(&mut self)
+  fn poll_debugger(&mut self)
   {
-    let  opcode_symbol = self.curr_opcode.find_opcode_symbol();
+    let (debugger, commands) = match (self.debugger.as_mut(), self.commands.as_ref())
+    {
+      (Some(debugger), Some(commands)) => (debugger, commands),
+      _ => return,
+    };
 
-    match opcode_symbol
+    while let Ok(line) = commands.try_recv()
     {
-/*      OpCodeSymbol::_0NNN =>
-      {
-      }, */
-      
-      OpCodeSymbol::_00E0 =>
+      if matches!(line.trim(), "quit" | "q")
       {
-        // clear the graphics screen.
-        self.graphics.clear();
-        
-        self.regs.PC += 2;
-        
-        self.draw_flag = true;
-      },
-      
-      OpCodeSymbol::_00EE =>
-      {
-        // Return from a function call. This is my implementation. 
-        self.stack.sp -= 1;
-        self.regs.PC = self.stack.stack[self.stack.sp as usize];
-        self.regs.PC += 2;
-      },
-      
-      OpCodeSymbol::_1NNN =>
-      {
-        self.regs.PC = self.curr_opcode.val & 0x0FFF;
-      },
-      
-      OpCodeSymbol::_2NNN =>
-      {
-        self.stack.stack[self.stack.sp as usize] = self.regs.PC;
-        self.stack.sp += 1;
-        self.regs.PC = self.curr_opcode.val & 0x0FFF;
-      },
-      
-      OpCodeSymbol::_3XNN =>
-      {
-        let X   = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let NN  = (self.curr_opcode.val & 0x00FF); // NN is an 8 bit constant (see Wikipedia for chip8).
-        
-        if self.regs.V[X] == (NN as u8) 
-        {
-          self.regs.PC  += 4;
-        }
-        else
-        {
-          self.regs.PC  += 2;
-        }
+        self.quit = true;
+        return;
       }
-      
-      OpCodeSymbol::_4XNN =>
+
+      match debugger.execute(&line, &mut self.chip8, &mut self.scheduler)
       {
-        let X   = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let NN  = (self.curr_opcode.val & 0x00FF); // NN is an 8 bit constant (see Wikipedia for chip8).
-        
-        if self.regs.V[X] != (NN as u8) 
-        {
-          self.regs.PC  += 4;
-        }
-        else
-        {
-          self.regs.PC  += 2;
-        }
+        Ok(out)  => if !out.is_empty() { println!("{}", out.trim_end()); },
+        Err(err) => println!("{}", err),
       }
-      
-      OpCodeSymbol::_5XY0 =>
+
+      // Commands may change what's on screen, e.g. by stepping a DXYN.
+      self.running = true;
+
+      if debugger.paused()
       {
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let Y = ((self.curr_opcode.val & 0x00F0) >> 4) as usize;
-        
-        if self.regs.V[X] == self.regs.V[Y] 
-        {
-          self.regs.PC  += 4;
-        }
-        else
-        {
-          self.regs.PC  += 2;
-        }
+        Emulator::prompt();
       }
-      
-      OpCodeSymbol::_6XNN => /* My implementation */
-      {
-        // Extract X register nums (for VX) out of the opcode.  
-        let X    = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let NN   = (self.curr_opcode.val & 0x00FF); // NN is an 8 bit constant (see Wikipedia for chip8).
-        
-        // V[X] = NN
-        self.regs.V[X] = NN as u8; 
-        
-        // PC += 2
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_7XNN => /* My implementation */
-      {
-        // Extract X register nums (for VX) out of the opcode.  
-        let X    = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let NN   = (self.curr_opcode.val & 0x00FF); // NN is an 8 bit constant (see Wikipedia for chip8).
-        
-        // V[X] += NN
-        //self.regs.V[X] += (NN as u8); 
-        self.regs.V[X] = self.regs.V[X].wrapping_add(NN as u8); 
-        
-        // PC += 2
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_8XY0 => /* My implementation */
-      {
-        // Extract X and Y register nums (for VX and VY) out of the opcode.  
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let Y = ((self.curr_opcode.val & 0x00F0) >> 4) as usize;
-        
-        // V[X] = V[Y]
-        self.regs.V[X] = self.regs.V[Y]; 
-        
-        // PC += 2
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_8XY1 => /* My implementation */
-      {
-        // Extract X and Y register nums (for VX and VY) out of the opcode.  
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let Y = ((self.curr_opcode.val & 0x00F0) >> 4) as usize;
-        
-        // V[X] = V[X] | V[Y] /* Bitwise Or */
-        self.regs.V[X] =  self.regs.V[X] | 
-                                         self.regs.V[Y]; 
-        
-        // PC += 2
-        self.regs.PC  += 2;
-      },
+    }
+  }
+
+  // Wait for gdb to attach on `port`. The machine stays paused until gdb
+  // continues it.
+  fn start_gdb(&mut self, port:u16)
+  {
+    println!("Waiting for gdb on localhost:{}...", port);
 
-      OpCodeSymbol::_8XY2 => /* My implementation */
+    match GdbStub::listen(port)
+    {
+      Ok(gdb) =>
       {
-        // Extract X and Y register nums (for VX and VY) out of the opcode.  
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let Y = ((self.curr_opcode.val & 0x00F0) >> 4) as usize;
-        
-        // V[X] = V[X] & V[Y] /* Bitwise And */
-        self.regs.V[X] =  self.regs.V[X] & 
-                                         self.regs.V[Y]; 
-        
-        // PC += 2
-        self.regs.PC  += 2;
+        println!("gdb connected");
+
+        self.gdb = Some(gdb);
       },
-      
-      OpCodeSymbol::_8XY3 => /* My implementation */
+      Err(err) =>
       {
-        // Extract X and Y register nums (for VX and VY) out of the opcode.  
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let Y = ((self.curr_opcode.val & 0x00F0) >> 4) as usize;
-        
-        // V[X] = V[X] ^ V[Y] /* Bitwise Xor */
-        self.regs.V[X] =  self.regs.V[X] ^ 
-                                         self.regs.V[Y]; 
-        
-        // PC += 2
-        self.regs.PC  += 2;
+        eprintln!("Can't serve gdb on port {}: {}", port, err);
+        process::exit(1);
       },
-      
-      OpCodeSymbol::_8XY4 =>
-      {
-        // Extract X and Y register nums (for VX and VY) out of the opcode.  
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let Y = ((self.curr_opcode.val & 0x00F0) >> 4) as usize;
-        
-        // Set the carry flag (VF) 
-        if (self.regs.V[Y] as u16) + (self.regs.V[X] as u16)>  0xFF
-        {
-          self.regs.V[0xF] = 1;
-        }
-        else
-        {
-          self.regs.V[0xF] = 0;
-        }
-
-        // VX += VY
-        //self.regs.V[X] += self.regs.V[Y];
-        self.regs.V[X] = self.regs.V[X].wrapping_add(self.regs.V[Y]);
+    }
 
-        // PC += 2
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_8XY5 =>
-      {
-        // Extract X and Y register nums (for VX and VY) out of the opcode.  
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let Y = ((self.curr_opcode.val & 0x00F0) >> 4) as usize;
+    self.attach_debugger();
+  }
 
-        // Set the carry flag (VF) if there is a borrow 
-        if self.regs.V[X] < self.regs.V[Y]
-        {
-          self.regs.V[0xF] = 0;
-        }
-        else
-        {
-          self.regs.V[0xF] = 1;
-        }
+  fn poll_gdb(&mut self)
+  {
+    let (gdb, debugger) = match (self.gdb.as_mut(), self.debugger.as_mut())
+    {
+      (Some(gdb), Some(debugger)) => (gdb, debugger),
+      _ => return,
+    };
 
-        // VX -= VY
-        // self.regs.V[X] -= self.regs.V[Y];
-        self.regs.V[X] = self.regs.V[X].wrapping_sub(self.regs.V[Y]);
+    gdb.poll(&mut self.chip8, &mut self.scheduler, debugger);
 
-        // PC += 2
-        self.regs.PC  += 2;
+    // Registers or memory may have changed, e.g. by stepping a DXYN.
+    self.running = true;
 
-      },
-      
-      OpCodeSymbol::_8XY6 =>
-      {
-        // VX >> 1
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
+    // gdb killed the program.
+    if gdb.killed()
+    {
+      self.quit = true;
+    }
 
-        //self.regs.V[0xF] = self.regs.V[X] & 0x80; // 0x80 the first bit 0b10000000
-        // shift right
-        self.regs.V[0xF] = self.regs.V[X] & 0x1; // 0x1 the first bit 0b10000000
+    if !gdb.connected()
+    {
+      println!("gdb disconnected");
 
-        self.regs.V[X] >>= 1;
+      self.gdb = None;
 
-        // PC += 2
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_8XY7 =>
+      // Without the REPL there is nobody left to resume the machine.
+      if self.commands.is_none()
       {
-        // Extract X and Y register nums (for VX and VY) out of the opcode.  
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let Y = ((self.curr_opcode.val & 0x00F0) >> 4) as usize;
+        debugger.resume();
+      }
+    }
+  }
 
-        // Set the carry flag (VF) if there is a borrow 
-        if self.regs.V[X] > self.regs.V[Y]
-        {
-          self.regs.V[0xF] = 0;
-        }
-        else
-        {
-          self.regs.V[0xF] = 1;
-        }
+  fn poll_dap(&mut self)
+  {
+    let (dap, debugger) = match (self.dap.as_mut(), self.debugger.as_mut())
+    {
+      (Some(dap), Some(debugger)) => (dap, debugger),
+      _ => return,
+    };
 
-        // VX -= VY
-        self.regs.V[X] = self.regs.V[Y] - self.regs.V[X];
+    dap.poll(&mut self.chip8, &mut self.scheduler, debugger);
 
-        // PC += 2
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_8XYE =>
-      {
-        // VX <<= 1
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
+    self.running = true;
 
-        //self.regs.V[0xF] = self.regs.V[X] & 0x01; // 0x80 the lsb 0b00000001
-        self.regs.V[0xF] = self.regs.V[X] >> 7;
-        self.regs.V[X] <<= 1;
+    // The editor stopped the session.
+    if dap.finished()
+    {
+      self.quit = true;
+    }
+  }
 
-        // PC += 2
-        self.regs.PC  += 2;
-      },
-      
+  fn update(&mut self, dt:f64)
+  {
+    if self.rewinding
+    {
+      self.rewind();
+      return;
+    }
+
+    if !self.running
+    {
+      return;
+    }
 
-      OpCodeSymbol::_9XY0 =>
+    for _ in 0..self.scheduler.frames_due(dt)
+    {
+      if self.debugger.as_ref().is_some_and(|debugger| debugger.paused())
       {
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let Y = ((self.curr_opcode.val & 0x00F0) >> 4) as usize;
-        
-        if self.regs.V[X] != self.regs.V[Y] 
-        {
-          self.regs.PC  += 4;
-        }
-        else
-        {
-          self.regs.PC  += 2;
-        }
+        break;
       }
-      
-      OpCodeSymbol::_ANNN =>
-      {
-        self.regs.I = self.curr_opcode.val & 0x0FFF;
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_BNNN => /* My implementation */
-      {
-        let NNN = self.curr_opcode.val & 0x0FFF;
-        self.regs.PC = NNN + (self.regs.V[0] as u16); // NNN + V0
-      },
-      
-      // Asher - Up to here all the opcodes seem to be implemented correctly
-      // ( as in the C++ implementation).
-      //
-      // TODO: Continue from here
-      OpCodeSymbol::_CXNN =>
-      {
-        let      NN       = self.curr_opcode.val & 0x00FF;
-        let      X        = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let mut  rng      = rand::thread_rng();
-        let      rnd:u16  = rng.gen(); 
-
-        self.regs.V[X] = ((rnd % 0xFF) & NN) as u8;
-        
-        // PC += 2
-        self.regs.PC  += 2;
-      },
 
-      // Display pixel at position(X,Y)
-      // Asher: TODO: check this opcode thouhroughly
-      OpCodeSymbol::_DXYN =>
+      // Input only changes between frames, so a movie can replay it. A
+      // frame the debugger stopped in the middle of keeps its input.
+      if !self.scheduler.in_frame()
       {
-        let X       = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        let Y       = ((self.curr_opcode.val & 0x00F0) >> 4) as usize;
-        let height  = (self.curr_opcode.val & 0x000F) as u8; 
-        let x       = self.regs.V[X];
-        let y       = self.regs.V[Y];
-
-        // (x,y) holds the position. height as height
-
-        let mut pixel;
+        let keypad = self.frame_keypad();
 
-        self.regs.V[0xF] = 0;
+        self.chip8.set_keypad(keypad);
+      }
 
-        for yline in 0..height
-        {
-          pixel = self.memory.memory[(self.regs.I + (yline as u16)) as usize];
-
-          for xline in 0..8
-          {
-            if pixel & (0x80 >> xline) != 0
-            {
-              let gfx_idx = (((x + xline) as usize) + (( (y + yline) as usize ) * 64)); 
-
-              if self.graphics.gfx[ gfx_idx ] == 1
-              {
-                self.regs.V[0xF] = 1;
-              }
-              
-              // XOR
-              self.graphics.gfx[ gfx_idx ] ^= 1;
-            }
-          }
-        }
+      let completed = self.run_frame();
 
-        self.draw_flag = true;
-        
-        // PC += 2
-        self.regs.PC  += 2;
-      }
-  
-      OpCodeSymbol::_EX9E =>
+      for skipped in self.chip8.take_skipped_opcodes()
       {
-
-        let X  = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-        
-        if self.key[(self.regs.V[X]) as usize] != 0
-        {
-          self.regs.PC  += 4;
-        }
-        else
-        {
-          self.regs.PC  += 2;
-        }
+        eprintln!("Skipped {}", skipped);
       }
-  
-      OpCodeSymbol::_EXA1 =>
-      {
-        let X  = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-
-        // loop {};
 
-        if self.key[(self.regs.V[X]) as usize ] == 0
-        {
-          self.regs.PC  += 4;
-        }
-        else
-        {
-          self.regs.PC  += 2;
-        }
+      if !completed
+      {
+        break;
       }
 
-      OpCodeSymbol::_FX07 =>
+      if self.rewind.budget() > 0
       {
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
+        self.rewind.push(&self.chip8.save_state());
+      }
+    }
 
-        self.regs.V[X] = self.regs.DELAY_TIMER; 
-        
-        self.regs.PC  += 2;
-      },
+    if self.chip8.sound_active() && !self.beeping
+    {
+      status!("BEEP!");
+    }
 
-      OpCodeSymbol::_FX0A =>
-      {
-        // get_key()
-        let mut key_press = false;
+    self.beeping = self.chip8.sound_active();
+  }
 
-        println!("_FX0A");
-        loop {};
+  fn main_loop(&mut self)
+  {
+    while let Some(event) = self.window.next()
+    {
+      self.set_keys(&event);
 
-        for idx in 0..16
-        {
-          if self.key[idx] != 0
-          {
-            let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
+      self.handle_hotkeys(&event);
 
-            self.regs.V[X] = idx as u8;
+      self.poll_debugger();
 
-            println!("key idx:{} was pushed", idx);
+      self.poll_gdb();
 
-            key_press = true;
-          }
-        }
+      self.poll_dap();
 
-        if key_press
-        {
-          self.regs.PC  += 2;
-        }
-      },
-      
-      OpCodeSymbol::_FX15 =>
+      if let Some(args) = event.update_args()
       {
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
+        self.update(args.dt);
+      }
 
-        self.regs.DELAY_TIMER = self.regs.V[X]; 
-        
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_FX18 =>
+      // Catch Window CloseEvent, a SUPER-CHIP 00FD exit or the debugger's
+      // quit command
+      if event.close_args().is_some() || self.chip8.halted() || self.quit
       {
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
+        break;
+      }
 
-        self.regs.SOUND_TIMER = self.regs.V[X]; 
-        
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_FX1E =>
+      if event.render_args().is_some()
       {
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-
-        if self.regs.I + (self.regs.V[X] as u16) > 0xFFF
-        {
-          self.regs.V[0xF] = 1;
-        }
-        else
-        {
-          self.regs.V[0xF] = 0;
-        }
-
-        self.regs.I += (self.regs.V[X] as u16);
+        self.draw_graphics(&event);
+      }
+    }
+  }
 
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_FX29 =>
+  // Play back `playback` if given, or start recording if asked to.
+  fn setup_movie(&mut self, options:&Options, rom:&[u8], playback:Option<Movie>)
+  {
+    if let Some(movie) = playback
+    {
+      if let Err(err) = movie.check_rom(rom)
       {
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
-
-        self.regs.I = (self.regs.V[X] * 0x5) as u16;
+        eprintln!("Can't play movie: {}", err);
+        process::exit(1);
+      }
 
-        self.regs.PC  += 2;
-      },
-      
-      OpCodeSymbol::_FX33 =>
-      {
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8) as usize;
+      self.chip8.set_quirks(movie.quirks);
 
-        self.memory.memory[self.regs.I as usize] = self.regs.V[X] / 100;
-        self.memory.memory[(self.regs.I+1) as usize] = (self.regs.V[X]/10) % 10;
-        self.memory.memory[(self.regs.I+2) as usize] = (self.regs.V[X] % 100) % 10;
+      self.movie = Some(MovieSession::play(movie));
+    }
+    else if options.record.is_some()
+    {
+      let seed = options.seed.unwrap_or_else(rand::random);
 
-        self.regs.PC  += 2;
-      },
+      self.chip8.reseed(seed);
 
-      OpCodeSymbol::_FX55 =>
-      {
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8);
+      let movie = Movie::new(rom, self.chip8.platform(), *self.chip8.quirks(), self.scheduler.speed(), seed);
 
-        for idx in 0..X
-        {
-          self.memory.memory[(self.regs.I + idx) as usize] = self.regs.V[idx as usize];
-        }
-      
-        self.regs.I += (X + 1); 
-        
-        self.regs.PC  += 2;
-      }
+      self.movie = Some(MovieSession::record(movie));
+    }
 
-      OpCodeSymbol::_FX65 =>
-      {
-        let X = ((self.curr_opcode.val & 0x0F00) >> 8);
+    // Re-recording a playback goes to --record if given, else back into
+    // the played file.
+    self.movie_path = options.record.clone().or_else(|| options.play.clone());
+  }
 
-        for idx in 0..X
-        {
-          self.regs.V[idx as usize] = self.memory.memory[(self.regs.I + idx) as usize];
-        }
-      
-        self.regs.I += (X + 1); 
-        
-        self.regs.PC  += 2;
-      },
+  // Write out the movie if anything was recorded.
+  fn finish_movie(&mut self)
+  {
+    let (movie, path) = match (self.movie.take(), self.movie_path.as_ref())
+    {
+      (Some(movie), Some(path)) if movie.mode() == MovieMode::Recording => (movie.into_movie(), path),
+      _ => return,
+    };
 
-      _ =>
-      {
-        println!("Error parsing opcode !!!!!");
-      }
+    match movie.save(path)
+    {
+      Ok(())   => status!("Saved {} frame movie to {}", movie.len(), path),
+      Err(err) => eprintln!("Can't save movie to {}: {}", path, err),
     }
   }
 
-  fn emulate_cycle(&mut self)
+  // Flush the trace, if there is one.
+  fn finish_trace(&mut self, options:&Options)
   {
-    self.fetch_opcode();
-
-    self.execute_opcode();
+    let (trace, path) = match (self.chip8.take_trace(), options.trace.as_ref())
+    {
+      (Some(trace), Some(path)) => (trace, path),
+      _ => return,
+    };
 
-    // Update timers
-    if self.regs.DELAY_TIMER > 0
+    match trace.finish()
     {
-      self.regs.DELAY_TIMER -= 1;
+      Ok(lines) => status!("Traced {} instructions to {}", lines, path),
+      Err(err)  => eprintln!("Can't write trace {}: {}", path, err),
     }
+  }
 
-    if self.regs.SOUND_TIMER > 0
+  fn start(&mut self, options:&Options, playback:Option<Movie>)
+  {
+    let rom = self.setup( &options.game_name );
+
+    self.setup_movie(options, &rom, playback);
+
+    if let Some(state) = options.load_state.as_deref()
     {
-      if self.regs.SOUND_TIMER == 1
+      // A bare number names one of the ROM's slots.
+      let path = match state.parse::<u8>()
+      {
+        Ok(slot) if slot < NUM_SAVE_SLOTS => { self.slot = slot; self.slot_path(slot) },
+        _ => state.to_string(),
+      };
+
+      if !self.load_state(&path)
       {
-        println!("BEEP!");
+        process::exit(1);
       }
+    }
 
-      self.regs.SOUND_TIMER -= 1;
+    if options.debug
+    {
+      self.start_debugger();
     }
-  }
-  
-  fn set_keys(&mut self, event:&Event)
-  {
-    // TODO: Catch Button Events
-    if let Some(button) = event.press_args()
+
+    if let Some(port) = options.gdb
     {
-      let mut valid_key = true;
-      let key;
+      self.start_gdb(port);
+    }
 
-      match button
+    if let Some(path) = options.symbols.as_ref()
+    {
+      let symbols = SymbolMap::load(path).unwrap_or_else(|err|
       {
-        Button::Keyboard(Key::D1) => {self.key[0] = 1; key = 0;},
-        Button::Keyboard(Key::D2) => {self.key[1] = 1; key = 1;},
-        Button::Keyboard(Key::D3) => {self.key[2] = 1; key = 2;},
-        Button::Keyboard(Key::D4) => {self.key[3] = 1; key = 3;},
-        Button::Keyboard(Key::Q)  => {self.key[4] = 1; key = 4;},
-        Button::Keyboard(Key::W)  => {self.key[5] = 1; key = 5;},
-        Button::Keyboard(Key::E)  => {self.key[6] = 1; key = 6;},
-        Button::Keyboard(Key::R)  => {self.key[7] = 1; key = 7;},
-        Button::Keyboard(Key::A)  => {self.key[8] = 1; key = 8;},
-        Button::Keyboard(Key::S)  => {self.key[9] = 1; key = 9;},
-        Button::Keyboard(Key::D)  => {self.key[10] = 1; key = 10;},
-        Button::Keyboard(Key::F)  => {self.key[11] = 1; key = 11;},
-        Button::Keyboard(Key::Z)  => {self.key[12] = 1; key = 12;},
-        Button::Keyboard(Key::X)  => {self.key[13] = 1; key = 13;},
-        Button::Keyboard(Key::C)  => {self.key[14] = 1; key = 14;},
-        Button::Keyboard(Key::V)  => {self.key[15] = 1; key = 15;},
-         _ => { valid_key = false; key = 99; },
-      }
+        eprintln!("Can't load symbols {}: {}", path, err);
+        process::exit(1);
+      });
 
-      if valid_key
+      if let Some(debugger) = self.debugger.as_mut()
       {
-        for idx in 0..16
-        {
-          if idx != key
-          {
-            self.key[idx] = 0;
-          }
-        }
+        debugger.set_symbols(symbols);
       }
+    }
 
-      println!("Key:{}", key);
+    if let Some(path) = options.trace.as_ref()
+    {
+      let trace = Trace::create(path).unwrap_or_else(|err|
+      {
+        eprintln!("Can't create trace {}: {}", path, err);
+        process::exit(1);
+      });
+
+      self.chip8.set_trace(trace);
+    }
+
+    // The debugger starts paused; the editor resumes it once it has set
+    // its breakpoints.
+    if self.dap.is_some()
+    {
+      self.attach_debugger();
+    }
+
+    self.main_loop();
+
+    self.finish_movie();
+
+    self.finish_trace(options);
+
+    if let Some(dap) = self.dap.as_mut()
+    {
+      dap.terminate();
     }
   }
 }
 
-struct Emulator
-{
-  window: PistonWindow,
-  chip8:  Chip8
-}
+const USAGE:&str = "usage: chip8 [--platform vip|chip48|schip-legacy|schip-modern|xochip]
+             [--on-undefined halt|skip|log] [--speed <n>ips|<n>ipf] [--seed <n>]
+             [--load-state <slot>|<file>] [--rewind-mb <n>]
+             [--record <movie>] [--play <movie>] [--debug] [--gdb <port>] [--dap]
+             [--symbols <file>] [--trace <file>] [rom]";
 
-impl Emulator
+fn parse_options() -> Options
 {
-  fn new() -> Self
+  let args:Vec<String> = env::args().skip(1).collect();
+
+  let mut options = Options { game_name:    String::from("pong.rom"),
+                              platform:     None,
+                              on_undefined: UndefinedOpcodePolicy::default(),
+                              speed:        Speed::default(),
+                              seed:         None,
+                              load_state:   None,
+                              rewind_budget:DEFAULT_REWIND_BUDGET,
+                              record:       None,
+                              play:         None,
+                              debug:        false,
+                              gdb:          None,
+                              dap:          false,
+                              symbols:      None,
+                              trace:        None,
+                            };
+
+  let mut idx = 0;
+
+  while idx < args.len()
   {
-    Emulator{ window:WindowSettings::new("CHIP-8 Emulator",
-                [(SCREEN_WIDTH_PIXELS*PIXEL_SIZE) as u32,
-                 (SCREEN_HEIGHT_PIXELS*PIXEL_SIZE) as u32])
-                .exit_on_esc(true).build().unwrap(),
-                chip8: Chip8::new() }
+    match args[idx].as_str()
+    {
+      "--platform"     => { options.platform     = Some(option_value(&args, &mut idx, USAGE)); },
+      "--on-undefined" => { options.on_undefined = option_value(&args, &mut idx, USAGE); },
+      "--speed"        => { options.speed        = option_value(&args, &mut idx, USAGE); },
+      "--seed"         => { options.seed         = Some(option_value(&args, &mut idx, USAGE)); },
+      "--load-state"   => { options.load_state   = Some(option_value(&args, &mut idx, USAGE)); },
+      "--rewind-mb"    => { options.rewind_budget= option_value::<usize>(&args, &mut idx, USAGE)*1024*1024; },
+      "--record"       => { options.record       = Some(option_value(&args, &mut idx, USAGE)); },
+      "--play"         => { options.play         = Some(option_value(&args, &mut idx, USAGE)); },
+      "--debug"        => { options.debug        = true; },
+      "--gdb"          => { options.gdb          = Some(option_value(&args, &mut idx, USAGE)); },
+      "--dap"          => { options.dap          = true; },
+      "--symbols"      => { options.symbols      = Some(option_value(&args, &mut idx, USAGE)); },
+      "--trace"        => { options.trace        = Some(option_value(&args, &mut idx, USAGE)); },
+
+      "-h" | "--help" =>
+      {
+        println!("{}", USAGE);
+        process::exit(0);
+      },
+
+      rom => { options.game_name = rom.to_string(); },
+    }
+
+    idx += 1;
   }
 
-  fn setup(&mut self, game_name:&str)
+  // A movie starts from power-on.
+  if options.load_state.is_some() && (options.record.is_some() || options.play.is_some())
   {
-    self.chip8.initialize();
-    
-    self.chip8.load_game( game_name );
+    eprintln!("--load-state can't be combined with --record or --play");
+    process::exit(1);
   }
-  
-  fn display(&mut self, context:&Context, graphics:&mut G2d)
+
+  // The REPL would read the same stdin, and gdb would fight over the
+  // debugger.
+  if options.dap && (options.debug || options.gdb.is_some())
   {
-    for x_idx in 0..64
-    {
-      for y_idx in 0..32
-      {
-        let color:[f32;4];
-          
-        if self.chip8.graphics.gfx[y_idx*64 + x_idx] == 0x1
-        {
-          color = COLOR_RED;
-        }
-        else
-        {
-          color = COLOR_BLACK;
-        }
-          
-        rectangle( color,
-                   [1.0*((x_idx*PIXEL_SIZE) as f64),
-                    1.0*((y_idx*PIXEL_SIZE) as f64),
-                    1.0*(((x_idx*PIXEL_SIZE) + PIXEL_SIZE) as f64),
-                    1.0*(((y_idx*PIXEL_SIZE) + PIXEL_SIZE) as f64)],
-                    context.transform,
-                    graphics );
-      }
-    }
+    eprintln!("--dap can't be combined with --debug or --gdb");
+    process::exit(1);
   }
 
-  fn draw_graphics(&mut self, event:&Event)
+  // The debug adapter gets its symbols from the launch request.
+  if options.symbols.is_some() && !(options.debug || options.gdb.is_some())
   {
-    // gfx is in chip8, but the piston graphics window
-    // is in the emulator.
-    // draw_graphics() needs to inquire the chip8 gfx matrix
-    // and draw it into the piston window.
-    let ref mut _chip8 = &mut self.chip8;
-    
-    self.window.draw_2d(event,
-                        |context, graphics|
-                        {  
-                          for x_idx in 0..64
-                          {
-                            for y_idx in 0..32
-                            {
-                              let color:[f32;4];
-          
-                              if _chip8.graphics.gfx[y_idx*64 + x_idx] == 0x1
-                              {
-                                color = COLOR_RED;
-                              }
-                              else
-                              {
-                                color = COLOR_BLACK;
-                              }
-          
-                              rectangle( color,
-                                         [1.0*((x_idx*PIXEL_SIZE) as f64),
-                                          1.0*((y_idx*PIXEL_SIZE) as f64),
-                                          1.0*(((x_idx*PIXEL_SIZE) + PIXEL_SIZE) as f64),
-                                          1.0*(((y_idx*PIXEL_SIZE) + PIXEL_SIZE) as f64)],
-                                         context.transform,
-                                         graphics );
-                            }
-                          }
-                        });
-
-    self.chip8.draw_flag = false;
+    eprintln!("--symbols needs --debug or --gdb");
+    process::exit(1);
   }
 
-  fn main_loop(&mut self, cycle_limit:u64)
+  options
+}
+
+fn main()
+{
+  let mut options = parse_options();
+
+  // An editor launching the debug adapter names the ROM in its launch
+  // request rather than on the command line.
+  let dap = if options.dap
   {
-    let mut num_cycle:u64 = 0;
+    STDOUT_TAKEN.store(true, Ordering::Relaxed);
 
-    while let Some(event) = self.window.next()
-    {
-      self.chip8.set_keys(&event);
+    let mut dap = DapServer::start();
 
-      num_cycle += 1;
+    let launch = match dap.wait_for_launch()
+    {
+      Some(launch) => launch,
+      None         => process::exit(0),
+    };
 
-      if cycle_limit < 1000 && num_cycle > cycle_limit
-      {
-      }
-      else
-      {
-        self.chip8.emulate_cycle();
-      }
-      
-      // Catch Window CloseEvent 
-      if let Some(_) = event.close_args()
-      {
-        break;
-      }
-      
-      /*self.window.draw_2d( &event,
-                           |context, graphics|
-                           { 
-                             clear([1.0; 4], graphics);
-                           }); */
-
-      if self.chip8.draw_flag
-      {
-        self.draw_graphics(&event);
-      }
+    options.game_name = launch.program;
+    options.platform  = launch.platform.or(options.platform);
 
-    }
+    Some(dap)
   }
+  else
+  {
+    None
+  };
 
-  fn start(&mut self, game_name:&str)
+  // A movie brings the settings it was recorded with.
+  let playback = options.play.as_ref().map(|path| Movie::load(path).unwrap_or_else(|err|
   {
-    self.setup( game_name );
+    eprintln!("Can't load movie {}: {}", path, err);
+    process::exit(1);
+  }));
 
-    self.main_loop(1000);
+  if let Some(movie) = playback.as_ref()
+  {
+    options.platform  = Some(movie.platform);
+    options.speed     = movie.speed;
+    options.seed      = Some(movie.seed);
   }
-}
 
-fn main()
-{ 
-  Emulator::new().start("pong.rom");
-  // Emulator::new().start("guess.rom");
-  //Emulator::new().start("ttto.rom");
-  //Emulator::new().start("flipc.rom");
+  let mut emulator = Emulator::new(&options);
+
+  emulator.dap = dap;
 
-  // thread::sleep(time::Duration::from_millis(3000));
+  emulator.start(&options, playback);
 }
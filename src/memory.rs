@@ -0,0 +1,53 @@
+/// Size of the classic CHIP-8 address space in bytes.
+pub const MEMORY_SIZE:usize = 4096;
+
+/// Size of the XO-CHIP address space in bytes.
+pub const XO_MEMORY_SIZE:usize = 0x10000;
+
+/// Address at which programs are loaded and execution starts.
+pub const PROGRAM_START:usize = 0x200;
+
+//  0x000-0x1FF   - Chip 8 interpreter (contains the font sets in emu)
+//  0x000-0x04F   - Built in 4x5 pixel font set (0-F)
+//  0x050-0x0EF   - SUPER-CHIP 8x10 pixel font set (0-F), at BIG_FONTSET_START
+//  0x200-0xFFF   - Program ROM and work RAM
+//  0x1000-0xFFFF - XO-CHIP only, extended RAM
+/// The CHIP-8 RAM: 4K, or 64K on XO-CHIP.
+pub struct Memory
+{
+  pub memory:Vec<u8>, // 4K of memory (64K for XO-CHIP)
+}
+
+impl Memory
+{
+  pub fn new() -> Self
+  {
+    Memory::with_size(MEMORY_SIZE)
+  }
+
+  pub fn with_size(size:usize) -> Self
+  {
+    Memory { memory:vec![0x0;size] }
+  }
+
+  pub fn size(&self) -> usize
+  {
+    self.memory.len()
+  }
+
+  pub fn clear(&mut self)
+  {
+    for byte in self.memory.iter_mut()
+    {
+      *byte = 0x0;
+    }
+  }
+}
+
+impl Default for Memory
+{
+  fn default() -> Self
+  {
+    Memory::new()
+  }
+}
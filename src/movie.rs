@@ -0,0 +1,445 @@
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::quirks::{Platform, Quirks};
+use crate::scheduler::Speed;
+
+/// Identifies a JSON file as an input movie of this emulator.
+pub const MOVIE_FORMAT:&str = "chip8-movie";
+
+/// Layout version written by this build.
+pub const MOVIE_VERSION:u32 = 1;
+
+// Binary movies start with this, followed by the version, the JSON header
+// and the frames as little-endian u16 keypad masks.
+const BINARY_MAGIC:&[u8;4] = b"C8MV";
+
+/// Why a movie couldn't be read, written or combined.
+#[derive(Debug)]
+pub enum MovieError
+{
+  Io(io::Error),
+  /// The file isn't a valid JSON or binary movie.
+  Format(String),
+  /// Written by a newer build than this one.
+  UnsupportedVersion { version:u32 },
+  /// The movie was recorded with a different ROM.
+  RomMismatch { expected:u64, found:u64 },
+  /// Two movies that can't be appended, since they were recorded with
+  /// different settings.
+  Incompatible,
+}
+
+impl fmt::Display for MovieError
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    match self
+    {
+      MovieError::Io(err)
+          => write!(f, "{}", err),
+      MovieError::Format(reason)
+          => write!(f, "malformed movie: {}", reason),
+      MovieError::UnsupportedVersion { version }
+          => write!(f, "movie version {} is newer than the supported version {}", version, MOVIE_VERSION),
+      MovieError::RomMismatch { expected, found }
+          => write!(f, "movie was recorded with ROM {:016x}, this ROM is {:016x}", expected, found),
+      MovieError::Incompatible
+          => write!(f, "movies were recorded with different ROMs or settings"),
+    }
+  }
+}
+
+impl Error for MovieError {}
+
+impl From<io::Error> for MovieError
+{
+  fn from(err:io::Error) -> Self
+  {
+    MovieError::Io(err)
+  }
+}
+
+impl From<serde_json::Error> for MovieError
+{
+  fn from(err:serde_json::Error) -> Self
+  {
+    MovieError::Format(err.to_string())
+  }
+}
+
+/// 64-bit FNV-1a hash identifying a ROM image.
+pub fn rom_hash(rom:&[u8]) -> u64
+{
+  rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// A recorded play session. Starting from power-on with the same ROM,
+/// platform, quirks, speed and RNG seed, feeding `frames[n]` to
+/// `Chip8::set_keypad` before frame n reproduces the session exactly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Movie
+{
+  pub format:   String,
+  pub version:  u32,
+
+  pub rom_hash: u64,
+  pub platform: Platform,
+  pub quirks:   Quirks,
+  pub speed:    Speed,
+  pub seed:     u64,
+
+  pub frames:   Vec<u16>, // Keypad mask for each frame
+}
+
+impl Movie
+{
+  /// An empty movie for a session with these settings.
+  pub fn new(rom:&[u8], platform:Platform, quirks:Quirks, speed:Speed, seed:u64) -> Self
+  {
+    Movie { format:   MOVIE_FORMAT.to_string(),
+            version:  MOVIE_VERSION,
+            rom_hash: rom_hash(rom),
+            platform,
+            quirks,
+            speed,
+            seed,
+            frames:   Vec::new(),
+          }
+  }
+
+  pub fn len(&self) -> usize
+  {
+    self.frames.len()
+  }
+
+  pub fn is_empty(&self) -> bool
+  {
+    self.frames.is_empty()
+  }
+
+  /// Fail unless the movie was recorded with `rom`.
+  pub fn check_rom(&self, rom:&[u8]) -> Result<(), MovieError>
+  {
+    let found = rom_hash(rom);
+
+    if found != self.rom_hash
+    {
+      return Err(MovieError::RomMismatch { expected:self.rom_hash, found });
+    }
+
+    Ok(())
+  }
+
+  /// Keep only the first `frames` frames.
+  pub fn truncate(&mut self, frames:usize)
+  {
+    self.frames.truncate(frames);
+  }
+
+  /// Add the frames of `other`, which must have been recorded with the
+  /// same ROM and settings.
+  pub fn append(&mut self, other:&Movie) -> Result<(), MovieError>
+  {
+    let same_session = self.rom_hash == other.rom_hash && self.platform == other.platform &&
+                       self.quirks   == other.quirks   && self.speed    == other.speed    &&
+                       self.seed     == other.seed;
+
+    if !same_session
+    {
+      return Err(MovieError::Incompatible);
+    }
+
+    self.frames.extend_from_slice(&other.frames);
+
+    Ok(())
+  }
+
+  pub fn to_json(&self) -> String
+  {
+    serde_json::to_string(self).expect("movie serializes")
+  }
+
+  pub fn from_json(json:&str) -> Result<Self, MovieError>
+  {
+    let movie:Movie = serde_json::from_str(json)?;
+
+    if movie.format != MOVIE_FORMAT
+    {
+      return Err(MovieError::Format(format!("unknown format '{}'", movie.format)));
+    }
+
+    if movie.version > MOVIE_VERSION
+    {
+      return Err(MovieError::UnsupportedVersion { version:movie.version });
+    }
+
+    Ok(movie)
+  }
+
+  /// The compact binary form: 2 bytes per frame plus a small header.
+  pub fn to_binary(&self) -> Vec<u8>
+  {
+    let header = Movie { frames:Vec::new(), ..self.clone() }.to_json();
+
+    let mut bytes = Vec::with_capacity(16 + header.len() + 2*self.frames.len());
+
+    bytes.extend_from_slice(BINARY_MAGIC);
+    bytes.extend_from_slice(&self.version.to_le_bytes());
+    bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+
+    for mask in self.frames.iter()
+    {
+      bytes.extend_from_slice(&mask.to_le_bytes());
+    }
+
+    bytes
+  }
+
+  pub fn from_binary(bytes:&[u8]) -> Result<Self, MovieError>
+  {
+    let truncated = || MovieError::Format("truncated binary movie".to_string());
+
+    let read_u32 = |offset:usize| -> Result<u32, MovieError>
+    {
+      let word = bytes.get(offset..offset + 4).ok_or_else(truncated)?;
+
+      Ok(u32::from_le_bytes(word.try_into().unwrap()))
+    };
+
+    if !bytes.starts_with(BINARY_MAGIC)
+    {
+      return Err(MovieError::Format("not a binary movie".to_string()));
+    }
+
+    let version = read_u32(4)?;
+
+    if version > MOVIE_VERSION
+    {
+      return Err(MovieError::UnsupportedVersion { version });
+    }
+
+    let header_len    = read_u32(8)? as usize;
+    let header        = bytes.get(12..12 + header_len).ok_or_else(truncated)?;
+
+    let mut movie     = Movie::from_json(&String::from_utf8_lossy(header))?;
+
+    let frames_start  = 12 + header_len + 4;
+    let num_frames    = read_u32(12 + header_len)? as usize;
+    let frames        = bytes.get(frames_start..frames_start + 2*num_frames).ok_or_else(truncated)?;
+
+    movie.frames = frames.chunks(2).map(|mask| u16::from_le_bytes([mask[0], mask[1]])).collect();
+
+    Ok(movie)
+  }
+
+  /// Write the movie, as JSON if `path` ends in `.json` and in the binary
+  /// form otherwise.
+  pub fn save<P:AsRef<Path>>(&self, path:P) -> Result<(), MovieError>
+  {
+    let is_json = path.as_ref().extension().is_some_and(|ext| ext == "json");
+
+    if is_json
+    {
+      fs::write(path, self.to_json())?;
+    }
+    else
+    {
+      fs::write(path, self.to_binary())?;
+    }
+
+    Ok(())
+  }
+
+  /// Read a movie in either form.
+  pub fn load<P:AsRef<Path>>(path:P) -> Result<Self, MovieError>
+  {
+    let bytes = fs::read(path)?;
+
+    if bytes.starts_with(BINARY_MAGIC)
+    {
+      Movie::from_binary(&bytes)
+    }
+    else
+    {
+      Movie::from_json(&String::from_utf8_lossy(&bytes))
+    }
+  }
+}
+
+/// Whether a `MovieSession` feeds recorded input or records live input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieMode
+{
+  Recording,
+  Playing,
+}
+
+/// A movie being recorded or played back, one frame at a time.
+pub struct MovieSession
+{
+  mode: MovieMode,
+  movie:Movie,
+  frame:usize, // Next frame to record or play
+}
+
+impl MovieSession
+{
+  /// Record onto the end of `movie`.
+  pub fn record(movie:Movie) -> Self
+  {
+    MovieSession { mode:MovieMode::Recording, frame:movie.len(), movie }
+  }
+
+  /// Play `movie` from its first frame.
+  pub fn play(movie:Movie) -> Self
+  {
+    MovieSession { mode:MovieMode::Playing, frame:0, movie }
+  }
+
+  pub fn mode(&self) -> MovieMode
+  {
+    self.mode
+  }
+
+  /// Index of the next frame.
+  pub fn frame(&self) -> usize
+  {
+    self.frame
+  }
+
+  pub fn movie(&self) -> &Movie
+  {
+    &self.movie
+  }
+
+  pub fn into_movie(self) -> Movie
+  {
+    self.movie
+  }
+
+  /// True once playback has used up every recorded frame.
+  pub fn finished(&self) -> bool
+  {
+    self.mode == MovieMode::Playing && self.frame >= self.movie.len()
+  }
+
+  /// The keypad mask for the next frame. While recording this is `live`,
+  /// which is stored; while playing it is the recorded mask, or `None`
+  /// once the movie is over.
+  pub fn next_frame(&mut self, live:u16) -> Option<u16>
+  {
+    let mask = match self.mode
+    {
+      MovieMode::Recording =>
+      {
+        self.movie.frames.push(live);
+        live
+      },
+      MovieMode::Playing => *self.movie.frames.get(self.frame)?,
+    };
+
+    self.frame += 1;
+
+    Some(mask)
+  }
+
+  /// Undo the last `next_frame`, for rewinding. A recording forgets the
+  /// frame.
+  pub fn step_back(&mut self)
+  {
+    if self.frame == 0
+    {
+      return;
+    }
+
+    self.frame -= 1;
+
+    if self.mode == MovieMode::Recording
+    {
+      self.movie.truncate(self.frame);
+    }
+  }
+
+  /// Take over from playback: drop the frames after the current one and
+  /// record from here on.
+  pub fn rerecord(&mut self)
+  {
+    self.movie.truncate(self.frame);
+
+    self.mode = MovieMode::Recording;
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::chip8::Chip8;
+  use crate::savestate::SaveState;
+  use crate::scheduler::Scheduler;
+
+  // Count loops run with key 5 down in V2 and draw random numbers into V0.
+  const ROM:[u8;12] = [0x61, 0x05, 0xE1, 0x9E, 0x12, 0x08, 0x72, 0x01, 0xC0, 0xFF, 0x12, 0x00];
+
+  // Run `session` from power on until it runs out of frames or `frames`
+  // have been recorded, with key 5 held every third frame.
+  fn run(session:&mut MovieSession, frames:usize) -> SaveState
+  {
+    let movie = session.movie().clone();
+
+    let mut chip8 = Chip8::with_platform(movie.platform);
+    chip8.set_quirks(movie.quirks);
+    chip8.reseed(movie.seed);
+    chip8.initialize();
+    chip8.load_rom(&ROM).unwrap();
+
+    let mut scheduler = Scheduler::new(movie.speed);
+
+    for frame in 0..frames
+    {
+      let live = if frame % 3 == 0 { 0x1 << 5 } else { 0x0 };
+
+      match session.next_frame(live)
+      {
+        Some(keypad) => chip8.set_keypad(keypad),
+        None         => break,
+      }
+
+      scheduler.run_frame(&mut chip8).unwrap();
+    }
+
+    chip8.save_state()
+  }
+
+  #[test]
+  fn replaying_a_recording_reproduces_the_session()
+  {
+    let movie = Movie::new(&ROM, Platform::CosmacVip, Quirks::legacy(), Speed::InstructionsPerFrame(9), 42);
+
+    let mut recording = MovieSession::record(movie);
+    let recorded      = run(&mut recording, 30);
+    let movie         = recording.into_movie();
+
+    assert_eq!(movie.len(), 30);
+    assert!(recorded.regs.V[0x2] > 0);
+
+    for copy in [Movie::from_json(&movie.to_json()).unwrap(), Movie::from_binary(&movie.to_binary()).unwrap()].iter()
+    {
+      assert_eq!(copy, &movie);
+
+      let mut playback = MovieSession::play(copy.clone());
+
+      assert_eq!(run(&mut playback, usize::MAX), recorded);
+      assert!(playback.finished());
+    }
+  }
+}
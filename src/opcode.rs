@@ -0,0 +1,224 @@
+use std::fmt;
+
+use crate::instruction::{decode, Instruction};
+
+/// Symbolic names for the CHIP-8, SUPER-CHIP 1.1 and XO-CHIP instructions. The name spells
+/// out the opcode pattern, e.g. `_8XY4` for "VX += VY with carry".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpCodeSymbol
+{
+  UNDEF,
+  _0NNN,
+  _00E0,
+  _00EE,
+  _00CN, // SUPER-CHIP
+  _00FB, // SUPER-CHIP
+  _00FC, // SUPER-CHIP
+  _00FD, // SUPER-CHIP
+  _00FE, // SUPER-CHIP
+  _00FF, // SUPER-CHIP
+  _1NNN,
+  _2NNN,
+  _3XNN,
+  _4XNN,
+  _5XY0,
+  _5XY2, // XO-CHIP
+  _5XY3, // XO-CHIP
+  _6XNN,
+  _7XNN,
+  _8XY0,
+  _8XY1,
+  _8XY2,
+  _8XY3,
+  _8XY4,
+  _8XY5,
+  _8XY6,
+  _8XY7,
+  _8XYE,
+  _9XY0,
+  _ANNN,
+  _BNNN,
+  _CXNN,
+  _DXYN,
+  _DXY0, // SUPER-CHIP
+  _EX9E,
+  _EXA1,
+  _F000, // XO-CHIP, followed by a 16 bit address
+  _FN01, // XO-CHIP
+  _F002, // XO-CHIP
+  _FX07,
+  _FX0A,
+  _FX15,
+  _FX18,
+  _FX1E,
+  _FX29,
+  _FX30, // SUPER-CHIP
+  _FX33,
+  _FX3A, // XO-CHIP
+  _FX55,
+  _FX65,
+  _FX75, // SUPER-CHIP
+  _FX85, // SUPER-CHIP
+}
+
+impl OpCodeSymbol
+{
+  /// True for the instructions added by SUPER-CHIP 1.1.
+  pub fn is_superchip(&self) -> bool
+  {
+    matches!(self, OpCodeSymbol::_00CN | OpCodeSymbol::_00FB | OpCodeSymbol::_00FC |
+                   OpCodeSymbol::_00FD | OpCodeSymbol::_00FE | OpCodeSymbol::_00FF |
+                   OpCodeSymbol::_DXY0 | OpCodeSymbol::_FX30 | OpCodeSymbol::_FX75 |
+                   OpCodeSymbol::_FX85)
+  }
+
+  /// True for the instructions added by XO-CHIP.
+  pub fn is_xochip(&self) -> bool
+  {
+    matches!(self, OpCodeSymbol::_5XY2 | OpCodeSymbol::_5XY3 | OpCodeSymbol::_F000 |
+                   OpCodeSymbol::_FN01 | OpCodeSymbol::_F002 | OpCodeSymbol::_FX3A)
+  }
+}
+
+/// A raw 16 bit CHIP-8 opcode as fetched from memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpCode
+{
+  pub val:u16, // 2 bytes opcode
+}
+
+impl OpCode
+{
+  pub fn new(val:u16) -> Self
+  {
+    OpCode { val }
+  }
+
+  /// Decode the opcode into an `Instruction` with its operands.
+  pub fn decode(&self) -> Instruction
+  {
+    decode(self.val)
+  }
+
+  /// Classify the opcode. Unknown patterns map to `OpCodeSymbol::UNDEF`.
+  pub fn find_opcode_symbol(&self) -> OpCodeSymbol
+  {
+    self.decode().symbol()
+  }
+}
+
+impl fmt::Display for OpCode
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    match self.find_opcode_symbol()
+    {
+      OpCodeSymbol::UNDEF
+          => {write!(f, "UNDEF")?;},
+      OpCodeSymbol::_0NNN 
+          => {write!(f, "_0NNN")?;},
+      OpCodeSymbol::_00E0
+          => {write!(f, "_00E0")?;},
+      OpCodeSymbol::_00EE
+          => {write!(f, "_00EE")?;},
+      OpCodeSymbol::_00CN
+          => {write!(f, "_00CN")?;},
+      OpCodeSymbol::_00FB
+          => {write!(f, "_00FB")?;},
+      OpCodeSymbol::_00FC
+          => {write!(f, "_00FC")?;},
+      OpCodeSymbol::_00FD
+          => {write!(f, "_00FD")?;},
+      OpCodeSymbol::_00FE
+          => {write!(f, "_00FE")?;},
+      OpCodeSymbol::_00FF
+          => {write!(f, "_00FF")?;},
+      OpCodeSymbol::_1NNN
+          => {write!(f, "_1NNN")?;},
+      OpCodeSymbol::_2NNN
+          => {write!(f, "_2NNN")?;},
+      OpCodeSymbol::_3XNN
+          => {write!(f, "_3XNN")?;},
+      OpCodeSymbol::_4XNN
+          => {write!(f, "_4XNN")?;},
+      OpCodeSymbol::_5XY0
+          => {write!(f, "_5XY0")?;},
+      OpCodeSymbol::_5XY2
+          => {write!(f, "_5XY2")?;},
+      OpCodeSymbol::_5XY3
+          => {write!(f, "_5XY3")?;},
+      OpCodeSymbol::_6XNN
+          => {write!(f, "_6XNN")?;},
+      OpCodeSymbol::_7XNN
+          => {write!(f, "_7XNN")?;},
+      OpCodeSymbol::_8XY0
+          => {write!(f, "_8XY0")?;},
+      OpCodeSymbol::_8XY1
+          => {write!(f, "_8XY1")?;},
+      OpCodeSymbol::_8XY2
+          => {write!(f, "_8XY2")?;},
+      OpCodeSymbol::_8XY3
+          => {write!(f, "_8XY3")?;},
+      OpCodeSymbol::_8XY4
+          => {write!(f, "_8XY4")?;},
+      OpCodeSymbol::_8XY5
+          => {write!(f, "_8XY5")?;},
+      OpCodeSymbol::_8XY6
+          => {write!(f, "_8XY6")?;},
+      OpCodeSymbol::_8XY7
+          => {write!(f, "_8XY7")?;},
+      OpCodeSymbol::_8XYE
+          => {write!(f, "_8XYE")?;},
+      OpCodeSymbol::_9XY0
+          => {write!(f, "_9XY0")?;},
+      OpCodeSymbol::_ANNN // Set I to the address NNN
+          => {write!(f, "_ANNN")?;},
+      OpCodeSymbol::_BNNN
+          => {write!(f, "_BNNN")?;},
+      OpCodeSymbol::_CXNN
+          => {write!(f, "_CXNN")?;},
+      OpCodeSymbol::_DXYN
+          => {write!(f, "_DXYN")?;},
+      OpCodeSymbol::_DXY0
+          => {write!(f, "_DXY0")?;},
+      OpCodeSymbol::_EX9E
+          => {write!(f, "_EX9E")?;},
+      OpCodeSymbol::_EXA1
+          => {write!(f, "_EXA1")?;},
+      OpCodeSymbol::_F000
+          => {write!(f, "_F000")?;},
+      OpCodeSymbol::_FN01
+          => {write!(f, "_FN01")?;},
+      OpCodeSymbol::_F002
+          => {write!(f, "_F002")?;},
+      OpCodeSymbol::_FX07
+          => {write!(f, "_FX07")?;},
+      OpCodeSymbol::_FX0A
+          => {write!(f, "_FX0A")?;},
+      OpCodeSymbol::_FX15
+          => {write!(f, "_FX15")?;},
+      OpCodeSymbol::_FX18
+          => {write!(f, "_FX18")?;},
+      OpCodeSymbol::_FX1E
+          => {write!(f, "_FX1E")?;},
+      OpCodeSymbol::_FX29
+          => {write!(f, "_FX29")?;},
+      OpCodeSymbol::_FX30
+          => {write!(f, "_FX30")?;},
+      OpCodeSymbol::_FX33
+          => {write!(f, "_FX33")?;},
+      OpCodeSymbol::_FX3A
+          => {write!(f, "_FX3A")?;},
+      OpCodeSymbol::_FX55
+          => {write!(f, "_FX55")?;},
+      OpCodeSymbol::_FX65
+          => {write!(f, "_FX65")?;},
+      OpCodeSymbol::_FX75
+          => {write!(f, "_FX75")?;},
+      OpCodeSymbol::_FX85
+          => {write!(f, "_FX85")?;},
+    }
+
+    write!(f," :Value={:#X}", self.val)
+  }
+}
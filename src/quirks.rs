@@ -0,0 +1,198 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::memory::{MEMORY_SIZE, XO_MEMORY_SIZE};
+use crate::opcode::OpCodeSymbol;
+
+/// The CHIP-8 family members whose behavior we can mimic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Platform
+{
+  CosmacVip,
+  Chip48,
+  SuperChipLegacy,
+  SuperChipModern,
+  XoChip,
+}
+
+impl Platform
+{
+  /// True if the platform runs the SUPER-CHIP 1.1 instructions.
+  pub fn supports_superchip(&self) -> bool
+  {
+    matches!(self, Platform::SuperChipLegacy | Platform::SuperChipModern | Platform::XoChip)
+  }
+
+  /// True if the platform runs instructions of the pattern `symbol`.
+  /// SUPER-CHIP instructions are undefined on the older platforms, where
+  /// `_DXY0` is just a `_DXYN` that draws nothing. XO-CHIP ones only exist
+  /// on XO-CHIP.
+  pub fn supports(&self, symbol:OpCodeSymbol) -> bool
+  {
+    !((symbol.is_superchip() && !self.supports_superchip() && symbol != OpCodeSymbol::_DXY0) ||
+      (symbol.is_xochip() && *self != Platform::XoChip))
+  }
+
+  /// Bytes of addressable memory: 64K on XO-CHIP, 4K everywhere else.
+  pub fn memory_size(&self) -> usize
+  {
+    if *self == Platform::XoChip { XO_MEMORY_SIZE } else { MEMORY_SIZE }
+  }
+}
+
+impl FromStr for Platform
+{
+  type Err = String;
+
+  fn from_str(s:&str) -> Result<Self, Self::Err>
+  {
+    match s.to_lowercase().as_str()
+    {
+      "vip" | "cosmac-vip" | "chip8"      => Ok(Platform::CosmacVip),
+      "chip48" | "chip-48"                => Ok(Platform::Chip48),
+      "schip-legacy" | "schip1.1"         => Ok(Platform::SuperChipLegacy),
+      "schip" | "schip-modern"            => Ok(Platform::SuperChipModern),
+      "xochip" | "xo-chip"                => Ok(Platform::XoChip),
+      _ => Err(format!("unknown platform '{}'", s)),
+    }
+  }
+}
+
+impl fmt::Display for Platform
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    let name = match self
+    {
+      Platform::CosmacVip       => "vip",
+      Platform::Chip48          => "chip48",
+      Platform::SuperChipLegacy => "schip-legacy",
+      Platform::SuperChipModern => "schip-modern",
+      Platform::XoChip          => "xochip",
+    };
+
+    write!(f, "{}", name)
+  }
+}
+
+/// Interpretations of the instructions that behave differently between
+/// CHIP-8 implementations. Each flag names the non-obvious behavior; the
+/// presets below reproduce the well known interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quirks
+{
+  /// `_8XY6`/`_8XYE` shift VY and store the result in VX, instead of
+  /// shifting VX in place.
+  pub shift_uses_vy:          bool,
+  /// `_FX55`/`_FX65` leave I pointing past the last register stored.
+  pub load_store_increments_i:bool,
+  /// `_BNNN` behaves as `BXNN`, jumping to XNN + VX instead of NNN + V0.
+  pub jump_uses_vx:           bool,
+  /// `_8XY1`, `_8XY2` and `_8XY3` reset VF to 0.
+  pub logic_resets_vf:        bool,
+  /// `_FX1E` sets VF to 1 when I overflows past 0xFFF, 0 otherwise.
+  pub index_overflow_sets_vf: bool,
+  /// `_DXYN` clips sprites at the screen edge instead of wrapping them
+  /// around. The start coordinate always wraps.
+  pub clip_sprites:           bool,
+  /// `_DXYN` waits for the next 60 Hz frame before the CPU continues.
+  pub display_wait:           bool,
+  /// `_FX0A` completes when the key is released rather than pressed.
+  pub key_wait_on_release:    bool,
+}
+
+impl Quirks
+{
+  /// This interpreter's behavior before the quirks were configurable:
+  /// shifts work on VX, `_FX55`/`_FX65` advance I, `_FX1E` sets VF on
+  /// overflow, and sprites wrap without waiting for the display. It is
+  /// the default, so existing callers and ROMs run as they always did.
+  pub fn legacy() -> Self
+  {
+    Quirks { shift_uses_vy:           false,
+             load_store_increments_i: true,
+             jump_uses_vx:            false,
+             logic_resets_vf:         false,
+             index_overflow_sets_vf:  true,
+             clip_sprites:            false,
+             display_wait:            false,
+             key_wait_on_release:     false,
+           }
+  }
+
+  /// The original COSMAC VIP interpreter.
+  pub fn cosmac_vip() -> Self
+  {
+    Quirks { shift_uses_vy:           true,
+             load_store_increments_i: true,
+             jump_uses_vx:            false,
+             logic_resets_vf:         true,
+             index_overflow_sets_vf:  false,
+             clip_sprites:            true,
+             display_wait:            true,
+             key_wait_on_release:     true,
+           }
+  }
+
+  /// CHIP-48 on the HP-48 calculators.
+  pub fn chip48() -> Self
+  {
+    Quirks { shift_uses_vy:           false,
+             load_store_increments_i: false,
+             jump_uses_vx:            true,
+             logic_resets_vf:         false,
+             index_overflow_sets_vf:  false,
+             clip_sprites:            true,
+             display_wait:            false,
+             key_wait_on_release:     false,
+           }
+  }
+
+  /// SUPER-CHIP 1.1 as shipped on the HP-48.
+  pub fn superchip_legacy() -> Self
+  {
+    Quirks { display_wait: true, ..Quirks::chip48() }
+  }
+
+  /// SUPER-CHIP as implemented by most modern interpreters (Octo et al.).
+  pub fn superchip_modern() -> Self
+  {
+    Quirks::chip48()
+  }
+
+  /// XO-CHIP as defined by Octo.
+  pub fn xo_chip() -> Self
+  {
+    Quirks { shift_uses_vy:           true,
+             load_store_increments_i: true,
+             jump_uses_vx:            false,
+             logic_resets_vf:         false,
+             index_overflow_sets_vf:  false,
+             clip_sprites:            false,
+             display_wait:            false,
+             key_wait_on_release:     true,
+           }
+  }
+
+  pub fn preset(platform:Platform) -> Self
+  {
+    match platform
+    {
+      Platform::CosmacVip       => Quirks::cosmac_vip(),
+      Platform::Chip48          => Quirks::chip48(),
+      Platform::SuperChipLegacy => Quirks::superchip_legacy(),
+      Platform::SuperChipModern => Quirks::superchip_modern(),
+      Platform::XoChip          => Quirks::xo_chip(),
+    }
+  }
+}
+
+impl Default for Quirks
+{
+  fn default() -> Self
+  {
+    Quirks::legacy()
+  }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::memory::PROGRAM_START;
+
+/// The CHIP-8 register file, including the two 60 Hz timers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Registers
+{
+  pub V:[u8;16], // V0..VE - 15 8-bit general purpose registers. 16th register carry flag 
+  pub PC:u16, // 0x000-0xFFF
+  pub I:u16,
+  pub DELAY_TIMER:u8,
+  pub SOUND_TIMER:u8,
+}
+
+impl Registers
+{
+  pub fn new() -> Self
+  {
+    Registers
+    {
+      V:[0x0;16],
+      PC:0x0,
+      I:0x0,
+      DELAY_TIMER:0,
+      SOUND_TIMER:0
+    }
+  }
+
+  pub fn clear(&mut self)
+  {
+    for idx in 0..16
+    {
+      self.V[idx] = 0x0;
+    }
+
+    self.PC = PROGRAM_START as u16; // PC starts at address 0x200
+
+    self.I = 0x0;
+
+    self.DELAY_TIMER = 0x0;
+
+    self.SOUND_TIMER = 0x0;
+  }
+}
+
+impl Default for Registers
+{
+  fn default() -> Self
+  {
+    Registers::new()
+  }
+}
+
+impl fmt::Display for Registers 
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    for idx in 0..16
+    {
+      if idx % 4 == 0
+      {
+        writeln!(f)?;
+      }
+
+      write!(f, "V[{}]={:#X} ", idx, self.V[idx])?;
+    }
+
+    writeln!(f)?;
+
+    write!(f, "PC:{:#X}, I:{:#X}, DELAY_TIMER:{:#X}, SOUND_TIMER:{:#X}", self.PC, self.I, self.DELAY_TIMER, self.SOUND_TIMER)
+  }
+}
@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::mem;
+
+use crate::savestate::SaveState;
+
+/// Memory the rewind buffer may use unless told otherwise.
+pub const DEFAULT_REWIND_BUDGET:usize = 8*1024*1024;
+
+// Bytes a run takes besides its data: its offset and the Vec holding it.
+const RUN_OVERHEAD:usize = mem::size_of::<(usize, Vec<u8>)>();
+
+// Unchanged gaps up to this long are folded into the surrounding run,
+// since starting a new run costs RUN_OVERHEAD bytes.
+const MAX_RUN_GAP:usize = RUN_OVERHEAD;
+
+// The bytes that differ between two snapshot images. Applying it to the
+// newer image gives back the older one.
+struct Delta
+{
+  len: usize, // Length of the image it produces
+  runs:Vec<(usize, Vec<u8>)>, // (offset, bytes) to write
+}
+
+impl Delta
+{
+  fn between(from:&[u8], to:&[u8]) -> Delta
+  {
+    let mut runs:Vec<(usize, Vec<u8>)> = Vec::new();
+
+    for (offset, &byte) in to.iter().enumerate()
+    {
+      if from.get(offset) == Some(&byte)
+      {
+        continue;
+      }
+
+      match runs.last_mut()
+      {
+        Some((start, bytes)) if offset - (*start + bytes.len()) <= MAX_RUN_GAP =>
+        {
+          bytes.extend_from_slice(&to[*start + bytes.len()..=offset]);
+        },
+        _ => runs.push((offset, vec![byte])),
+      }
+    }
+
+    Delta { len:to.len(), runs }
+  }
+
+  fn apply(&self, image:&mut Vec<u8>)
+  {
+    image.resize(self.len, 0x0);
+
+    for (offset, bytes) in self.runs.iter()
+    {
+      image[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+    }
+  }
+
+  fn size(&self) -> usize
+  {
+    self.runs.iter().map(|(_, bytes)| bytes.len() + RUN_OVERHEAD).sum::<usize>() + mem::size_of::<Delta>()
+  }
+}
+
+// Flatten a snapshot so that a frame's changes land at the same offsets as
+// in the previous frame: memory and framebuffer first, byte for byte, then
+// the small remainder as JSON.
+fn to_image(state:&SaveState) -> Vec<u8>
+{
+  let rest = SaveState { memory:Vec::new(), gfx:Vec::new(), ..state.clone() };
+
+  let mut image = Vec::with_capacity(8 + state.memory.len() + state.gfx.len() + 1024);
+
+  image.extend_from_slice(&(state.memory.len() as u32).to_le_bytes());
+  image.extend_from_slice(&(state.gfx.len() as u32).to_le_bytes());
+  image.extend_from_slice(&state.memory);
+  image.extend_from_slice(&state.gfx);
+  image.extend_from_slice(rest.to_json().as_bytes());
+
+  image
+}
+
+fn from_image(image:&[u8]) -> SaveState
+{
+  let memory_len  = u32::from_le_bytes(image[0..4].try_into().unwrap()) as usize;
+  let gfx_len     = u32::from_le_bytes(image[4..8].try_into().unwrap()) as usize;
+
+  let gfx_start   = 8 + memory_len;
+  let rest_start  = gfx_start + gfx_len;
+
+  // The images were built by to_image from valid states.
+  let rest:SaveState = serde_json::from_slice(&image[rest_start..]).expect("rewind image holds a save state");
+
+  SaveState { memory:image[8..gfx_start].to_vec(), gfx:image[gfx_start..rest_start].to_vec(), ..rest }
+}
+
+/// A ring buffer of per-frame snapshots for stepping backward in time.
+/// Only the newest snapshot is kept whole; every older one is stored as
+/// the bytes that differ from its successor, and the oldest frames are
+/// dropped once the buffer uses more than its memory budget.
+pub struct Rewind
+{
+  budget: usize, // Bytes the buffer may use
+  used:   usize, // Bytes used by `newest` and `deltas`
+  newest: Option<Vec<u8>>, // Image of the most recent snapshot
+  deltas: VecDeque<Delta>, // Oldest first; each leads one frame further back
+}
+
+impl Rewind
+{
+  pub fn new(budget:usize) -> Self
+  {
+    Rewind { budget, used:0, newest:None, deltas:VecDeque::new() }
+  }
+
+  pub fn budget(&self) -> usize
+  {
+    self.budget
+  }
+
+  /// Approximate bytes in use.
+  pub fn used(&self) -> usize
+  {
+    self.used
+  }
+
+  /// Number of frames `rewind` can still step back.
+  pub fn len(&self) -> usize
+  {
+    self.deltas.len()
+  }
+
+  pub fn is_empty(&self) -> bool
+  {
+    self.deltas.is_empty()
+  }
+
+  pub fn clear(&mut self)
+  {
+    self.newest = None;
+    self.deltas.clear();
+    self.used   = 0;
+  }
+
+  /// Record the state of the frame that just ran.
+  pub fn push(&mut self, state:&SaveState)
+  {
+    let image = to_image(state);
+
+    if let Some(newest) = self.newest.take()
+    {
+      let delta = Delta::between(&image, &newest);
+
+      self.used -= newest.len();
+      self.used += delta.size();
+
+      self.deltas.push_back(delta);
+    }
+
+    self.used += image.len();
+    self.newest = Some(image);
+
+    while self.used > self.budget
+    {
+      match self.deltas.pop_front()
+      {
+        Some(oldest) => self.used -= oldest.size(),
+        None         => break,
+      }
+    }
+  }
+
+  /// Step one frame back: forget the newest snapshot and return the one
+  /// before it, or `None` when there is nothing older left.
+  pub fn rewind(&mut self) -> Option<SaveState>
+  {
+    let delta   = self.deltas.pop_back()?;
+    let newest  = self.newest.as_mut()?;
+
+    self.used -= newest.len() + delta.size();
+
+    delta.apply(newest);
+
+    self.used += newest.len();
+
+    Some(from_image(newest))
+  }
+}
+
+impl Default for Rewind
+{
+  fn default() -> Self
+  {
+    Rewind::new(DEFAULT_REWIND_BUDGET)
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::chip8::Chip8;
+
+  // Count up in V0 and draw the digit, one step per frame.
+  const ROM:[u8;10] = [0x00, 0xE0, 0xF0, 0x29, 0xD1, 0x15, 0x70, 0x01, 0x12, 0x00];
+
+  // Snapshots of the first `frames` frames of ROM, one per frame.
+  fn frames(frames:usize) -> Vec<SaveState>
+  {
+    let mut chip8 = Chip8::with_seed(1);
+    chip8.initialize();
+    chip8.load_rom(&ROM).unwrap();
+
+    (0..frames).map(|_|
+    {
+      for _ in 0..5
+      {
+        chip8.emulate_cycle().unwrap();
+      }
+
+      chip8.tick_timers();
+      chip8.save_state()
+    })
+    .collect()
+  }
+
+  #[test]
+  fn rewinding_restores_each_earlier_frame()
+  {
+    let states      = frames(10);
+    let mut rewind  = Rewind::default();
+
+    for state in states.iter()
+    {
+      rewind.push(state);
+    }
+
+    assert_eq!(rewind.len(), 9);
+
+    for state in states.iter().rev().skip(1)
+    {
+      assert_eq!(rewind.rewind().as_ref(), Some(state));
+    }
+
+    assert_eq!(rewind.rewind(), None);
+    assert!(rewind.is_empty());
+  }
+
+  #[test]
+  fn oldest_frames_are_dropped_past_the_budget()
+  {
+    let states = frames(50);
+    let image  = to_image(&states[0]).len();
+
+    let mut rewind = Rewind::new(image + image/4);
+
+    for state in states.iter()
+    {
+      rewind.push(state);
+    }
+
+    assert!(rewind.used() <= rewind.budget());
+    assert!(!rewind.is_empty() && rewind.len() < 49);
+
+    let kept = rewind.len();
+
+    for state in states.iter().rev().skip(1).take(kept)
+    {
+      assert_eq!(rewind.rewind().as_ref(), Some(state));
+    }
+  }
+}
@@ -0,0 +1,71 @@
+/// The random source behind `_CXNN`. Implement it to drive the machine
+/// from a custom generator; `XorShiftRng` is used by default.
+pub trait RandomSource : Send
+{
+  /// The next random byte, uniform over the full 0x00-0xFF range.
+  fn next_byte(&mut self) -> u8;
+
+  /// The generator state, for save states. `None` if it can't be captured.
+  fn state(&self) -> Option<u64>
+  {
+    None
+  }
+
+  /// Restore a state returned by `state()`.
+  fn set_state(&mut self, _state:u64)
+  {
+  }
+}
+
+// Any non-zero seed works for xorshift; zero would stick at zero.
+const ZERO_SEED_REPLACEMENT:u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// A small, fast xorshift64* generator. The same seed always produces the
+/// same sequence, and its whole state is one `u64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XorShiftRng
+{
+  state:u64,
+}
+
+impl XorShiftRng
+{
+  pub fn new(seed:u64) -> Self
+  {
+    XorShiftRng { state:if seed == 0 { ZERO_SEED_REPLACEMENT } else { seed } }
+  }
+
+  /// A generator seeded from the operating system.
+  pub fn from_entropy() -> Self
+  {
+    XorShiftRng::new(rand::random())
+  }
+
+  pub fn next_u64(&mut self) -> u64
+  {
+    self.state ^= self.state >> 12;
+    self.state ^= self.state << 25;
+    self.state ^= self.state >> 27;
+
+    self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+}
+
+impl RandomSource for XorShiftRng
+{
+  fn next_byte(&mut self) -> u8
+  {
+    // The high bits of xorshift64* are the best distributed.
+    (self.next_u64() >> 56) as u8
+  }
+
+  fn state(&self) -> Option<u64>
+  {
+    Some(self.state)
+  }
+
+  fn set_state(&mut self, state:u64)
+  {
+    *self = XorShiftRng::new(state);
+  }
+}
@@ -0,0 +1,271 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::chip8::{KeyWait, DEFAULT_PITCH};
+use crate::graphics::PLANE_1;
+use crate::quirks::{Platform, Quirks};
+use crate::registers::Registers;
+use crate::stack::Stack;
+
+/// Identifies a file as a save state of this emulator.
+pub const SAVE_STATE_FORMAT:&str = "chip8-save-state";
+
+/// Layout version written by this build. Fields a snapshot can do without
+/// are `#[serde(default)]`, so files lacking them still load. Bump the
+/// version only for changes that can't be absorbed that way; files of an
+/// older version then need upgrading in `SaveState::from_json`.
+pub const SAVE_STATE_VERSION:u32 = 1;
+
+/// Why a save state couldn't be written or restored.
+#[derive(Debug)]
+pub enum SaveStateError
+{
+  Io(io::Error),
+  /// The file isn't valid JSON or doesn't have the expected fields.
+  Format(serde_json::Error),
+  /// Not a save state of this emulator.
+  WrongFormat { format:String },
+  /// Written by a newer build than this one.
+  UnsupportedVersion { version:u32 },
+  /// Well formed, but describes a machine that can't exist.
+  Inconsistent { reason:&'static str },
+}
+
+impl fmt::Display for SaveStateError
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    match self
+    {
+      SaveStateError::Io(err)
+          => write!(f, "{}", err),
+      SaveStateError::Format(err)
+          => write!(f, "malformed save state: {}", err),
+      SaveStateError::WrongFormat { format }
+          => write!(f, "not a save state (format '{}')", format),
+      SaveStateError::UnsupportedVersion { version }
+          => write!(f, "save state version {} is newer than the supported version {}", version, SAVE_STATE_VERSION),
+      SaveStateError::Inconsistent { reason }
+          => write!(f, "inconsistent save state: {}", reason),
+    }
+  }
+}
+
+impl Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError
+{
+  fn from(err:io::Error) -> Self
+  {
+    SaveStateError::Io(err)
+  }
+}
+
+impl From<serde_json::Error> for SaveStateError
+{
+  fn from(err:serde_json::Error) -> Self
+  {
+    SaveStateError::Format(err)
+  }
+}
+
+// Read first, so the version can be checked before the body is trusted.
+#[derive(Deserialize)]
+struct Header
+{
+  format: String,
+  version:u32,
+}
+
+fn default_planes() -> u8
+{
+  PLANE_1
+}
+
+fn default_pitch() -> u8
+{
+  DEFAULT_PITCH
+}
+
+/// A complete snapshot of a `Chip8`, taken with `Chip8::save_state` and
+/// restored with `Chip8::load_state`. Stored as JSON. Only the machine's
+/// core is required; the rest defaults to its power-on state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveState
+{
+  pub format:       String,
+  pub version:      u32,
+
+  pub platform:     Platform,
+  pub quirks:       Quirks,
+  pub regs:         Registers,
+  pub memory:       Vec<u8>,
+  pub stack:        Stack,
+  pub gfx:          Vec<u8>, // The whole 128x64 buffer
+
+  #[serde(default)]
+  pub hires:        bool,
+  #[serde(default)]
+  pub keypad:       u16, // Bit N set if key N is held
+  #[serde(default)]
+  pub key_wait:     Option<KeyWait>,
+  #[serde(default)]
+  pub rng_state:    Option<u64>, // None if the random source can't be captured
+  #[serde(default)]
+  pub curr_opcode:  u16,
+  #[serde(default)]
+  pub draw_flag:    bool,
+  #[serde(default)]
+  pub wait_vblank:  bool,
+  #[serde(default)]
+  pub halted:       bool,
+  #[serde(default)]
+  pub rpl:          [u8;16],
+  #[serde(default = "default_planes")]
+  pub planes:       u8,
+  #[serde(default)]
+  pub audio_pattern:[u8;16],
+  #[serde(default = "default_pitch")]
+  pub pitch:        u8,
+}
+
+impl SaveState
+{
+  pub fn to_json(&self) -> String
+  {
+    // A SaveState only holds plain data, so this can't fail.
+    serde_json::to_string(self).expect("save state serializes")
+  }
+
+  /// Parse a save state written by this or an older build.
+  pub fn from_json(json:&str) -> Result<Self, SaveStateError>
+  {
+    let header:Header = serde_json::from_str(json)?;
+
+    if header.format != SAVE_STATE_FORMAT
+    {
+      return Err(SaveStateError::WrongFormat { format:header.format });
+    }
+
+    if header.version > SAVE_STATE_VERSION
+    {
+      return Err(SaveStateError::UnsupportedVersion { version:header.version });
+    }
+
+    Ok(serde_json::from_str(json)?)
+  }
+
+  pub fn save<P:AsRef<Path>>(&self, path:P) -> Result<(), SaveStateError>
+  {
+    fs::write(path, self.to_json())?;
+
+    Ok(())
+  }
+
+  pub fn load<P:AsRef<Path>>(path:P) -> Result<Self, SaveStateError>
+  {
+    SaveState::from_json(&fs::read_to_string(path)?)
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use serde_json::json;
+
+  use super::*;
+  use crate::chip8::Chip8;
+  use crate::quirks::Platform;
+
+  // A version 1 snapshot with only the required fields: the machine just
+  // after loading a one instruction ROM.
+  fn v1_fixture() -> String
+  {
+    let mut memory = vec![0u8;4096];
+
+    memory[0x200] = 0x6A;
+    memory[0x201] = 0x02;
+
+    json!({
+      "format":   "chip8-save-state",
+      "version":  1,
+      "platform": "CosmacVip",
+      "quirks":   Quirks::legacy(),
+      "regs":     { "V":[0,0,0,0,0,0,0,0,0,0,7,0,0,0,0,0], "I":0x300, "PC":0x200, "DELAY_TIMER":5, "SOUND_TIMER":0 },
+      "memory":   memory,
+      "stack":    { "stack":vec![0u16;16], "sp":0 },
+      "gfx":      vec![0u8;128 * 64],
+    }).to_string()
+  }
+
+  #[test]
+  fn version_1_fixture_loads_with_defaults()
+  {
+    let state = SaveState::from_json(&v1_fixture()).unwrap();
+
+    assert_eq!(state.platform, Platform::CosmacVip);
+    assert_eq!(state.regs.V[0xA], 7);
+    assert_eq!(state.key_wait, None);
+    assert_eq!(state.planes, PLANE_1);
+    assert_eq!(state.pitch, DEFAULT_PITCH);
+
+    let mut chip8 = Chip8::new();
+
+    chip8.load_state(&state).unwrap();
+    chip8.emulate_cycle().unwrap();
+
+    assert_eq!(chip8.regs().V[0xA], 2);
+    assert_eq!(chip8.regs().PC, 0x202);
+  }
+
+  #[test]
+  fn other_formats_and_newer_versions_are_rejected()
+  {
+    let fixture = v1_fixture();
+
+    let wrong_format = fixture.replace("chip8-save-state", "chip8-movie");
+    let newer        = fixture.replace("\"version\":1", "\"version\":2");
+
+    assert!(matches!(SaveState::from_json(&wrong_format), Err(SaveStateError::WrongFormat { .. })));
+    assert!(matches!(SaveState::from_json(&newer), Err(SaveStateError::UnsupportedVersion { version:2 })));
+  }
+
+  #[test]
+  fn saved_machines_load_back_and_run_the_same()
+  {
+    // High resolution, call 0x208, which draws the digit in V0 and then
+    // loops on random numbers
+    let rom = [0x00, 0xFF, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00, 0xF0, 0x29, 0xD0, 0x15, 0xC5, 0xFF, 0x12, 0x0C];
+
+    let mut chip8 = Chip8::with_platform(Platform::SuperChipModern);
+    chip8.reseed(42);
+    chip8.initialize();
+    chip8.load_rom(&rom).unwrap();
+
+    for _ in 0..7
+    {
+      chip8.emulate_cycle().unwrap();
+    }
+
+    let state = SaveState::from_json(&chip8.save_state().to_json()).unwrap();
+
+    let mut restored = Chip8::new();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.save_state(), chip8.save_state());
+    assert_eq!(restored.stack().sp, 1);
+    assert!(restored.graphics().hires() && restored.graphics().pixel(0, 0));
+
+    for _ in 0..10
+    {
+      chip8.emulate_cycle().unwrap();
+      restored.emulate_cycle().unwrap();
+    }
+
+    assert_eq!(restored.save_state(), chip8.save_state());
+  }
+}
@@ -0,0 +1,224 @@
+use std::str::FromStr;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+
+/// Rate of the delay and sound timers, and of frame presentation.
+pub const TIMER_HZ:u32 = 60;
+
+// Never catch up more than this much emulated time in one `run`, so a
+// stalled frontend doesn't make the machine sprint afterwards.
+const MAX_CATCH_UP_SECS:f64 = 0.25;
+
+/// How fast the CPU runs, either as a rate or as a fixed number of
+/// instructions between two 60 Hz timer ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Speed
+{
+  InstructionsPerSecond(u32),
+  InstructionsPerFrame(u32),
+}
+
+impl Speed
+{
+  /// Instructions executed per 60 Hz frame. May be fractional for rates
+  /// that aren't a multiple of 60.
+  pub fn instructions_per_frame(&self) -> f64
+  {
+    match *self
+    {
+      Speed::InstructionsPerSecond(ips) => ips as f64 / TIMER_HZ as f64,
+      Speed::InstructionsPerFrame(ipf)  => ipf as f64,
+    }
+  }
+}
+
+impl Default for Speed
+{
+  fn default() -> Self
+  {
+    Speed::InstructionsPerSecond(600)
+  }
+}
+
+/// Parses "700" or "700ips" as instructions per second and "11ipf" as
+/// instructions per frame. The rate must not be 0.
+impl FromStr for Speed
+{
+  type Err = String;
+
+  fn from_str(s:&str) -> Result<Self, Self::Err>
+  {
+    let s = s.to_lowercase();
+
+    let (digits, per_frame) = if let Some(digits) = s.strip_suffix("ipf") { (digits, true) }
+                              else if let Some(digits) = s.strip_suffix("ips") { (digits, false) }
+                              else { (s.as_str(), false) };
+
+    let rate:u32 = digits.parse().map_err(|_| format!("invalid speed '{}'", s))?;
+
+    // A machine that never runs an instruction would hang anything that
+    // steps it, like the debugger.
+    if rate == 0
+    {
+      return Err("speed must be positive".to_string());
+    }
+
+    Ok(if per_frame { Speed::InstructionsPerFrame(rate) } else { Speed::InstructionsPerSecond(rate) })
+  }
+}
+
+/// Drives a `Chip8` from emulated time. The frontend reports how much time
+/// passed; the scheduler turns that into whole 60 Hz frames, each of which
+/// executes the configured number of instructions and then ticks the
+/// timers once. Game speed therefore depends only on emulated time, never
+/// on how often the frontend calls in.
+pub struct Scheduler
+{
+  speed:          Speed,
+  pending_secs:   f64, // Emulated time not yet turned into frames
+  pending_cycles: f64, // Fractional instructions carried between frames
+  frames:         u64, // Frames run so far
+  in_frame:       bool, // A frame was stopped part way by run_frame_until
+}
+
+impl Scheduler
+{
+  pub fn new(speed:Speed) -> Self
+  {
+    Scheduler { speed, pending_secs:0.0, pending_cycles:0.0, frames:0, in_frame:false }
+  }
+
+  pub fn speed(&self) -> Speed
+  {
+    self.speed
+  }
+
+  pub fn set_speed(&mut self, speed:Speed)
+  {
+    self.speed = speed;
+  }
+
+  /// Number of frames run since the scheduler was created.
+  pub fn frames(&self) -> u64
+  {
+    self.frames
+  }
+
+  /// True while a frame stopped by `run_frame_until` is unfinished.
+  pub fn in_frame(&self) -> bool
+  {
+    self.in_frame
+  }
+
+  /// Run exactly one frame: the instructions for 1/60 s, then one timer
+  /// tick.
+  pub fn run_frame(&mut self, chip8:&mut Chip8) -> Result<(), Chip8Error>
+  {
+    self.run_frame_until(chip8, |_| false).map(|_| ())
+  }
+
+  /// Like `run_frame`, but asks `stop` before every instruction. Returns
+  /// false if it stopped; the next call then finishes the same frame
+  /// instead of starting a new one.
+  pub fn run_frame_until<F>(&mut self, chip8:&mut Chip8, mut stop:F) -> Result<bool, Chip8Error>
+    where F:FnMut(&Chip8) -> bool
+  {
+    if !self.in_frame
+    {
+      self.pending_cycles += self.speed.instructions_per_frame();
+
+      self.in_frame = true;
+    }
+
+    while self.pending_cycles >= 1.0
+    {
+      if stop(chip8)
+      {
+        return Ok(false);
+      }
+
+      chip8.emulate_cycle()?;
+
+      self.pending_cycles -= 1.0;
+    }
+
+    chip8.tick_timers();
+
+    self.frames  += 1;
+
+    self.in_frame = false;
+
+    Ok(true)
+  }
+
+  /// Advance emulated time by `secs` seconds and return how many frames
+  /// became due, without running them. For frontends that do per-frame
+  /// work such as latching input, followed by `run_frame` for each.
+  pub fn frames_due(&mut self, secs:f64) -> u32
+  {
+    let frame_secs = 1.0 / TIMER_HZ as f64;
+
+    self.pending_secs = (self.pending_secs + secs).min(MAX_CATCH_UP_SECS);
+
+    let mut frames = 0;
+
+    while self.pending_secs >= frame_secs
+    {
+      self.pending_secs -= frame_secs;
+
+      frames += 1;
+    }
+
+    frames
+  }
+
+  /// Advance emulated time by `secs` seconds and run every frame that
+  /// became due. Returns the number of frames run, so the frontend knows
+  /// whether there is something new to present.
+  pub fn run(&mut self, chip8:&mut Chip8, secs:f64) -> Result<u32, Chip8Error>
+  {
+    let frames = self.frames_due(secs);
+
+    for _ in 0..frames
+    {
+      self.run_frame(chip8)?;
+    }
+
+    Ok(frames)
+  }
+}
+
+impl Default for Scheduler
+{
+  fn default() -> Self
+  {
+    Scheduler::new(Speed::default())
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  #[test]
+  fn speeds_parse()
+  {
+    assert_eq!("700".parse(), Ok(Speed::InstructionsPerSecond(700)));
+    assert_eq!("700IPS".parse(), Ok(Speed::InstructionsPerSecond(700)));
+    assert_eq!("11ipf".parse(), Ok(Speed::InstructionsPerFrame(11)));
+    assert!("fast".parse::<Speed>().is_err());
+  }
+
+  #[test]
+  fn zero_speed_is_rejected()
+  {
+    for zero in ["0", "0ips", "0ipf"].iter()
+    {
+      assert_eq!(zero.parse::<Speed>(), Err("speed must be positive".to_string()));
+    }
+  }
+}
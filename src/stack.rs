@@ -0,0 +1,45 @@
+use serde_derive::{Deserialize, Serialize};
+
+// The Chip 8 instruction set has opcodes that allow the program to jump to a certain
+// address or call a subroutine. While the specification dont mention a stack, you
+// will need to implement one as part of the interpreter yourself. The stack is used
+// to remember the current location before a jump is performed. So anytime you
+// perform a jump or call a subroutine, store the program counter in the stack before
+// proceeding. The system has 16 levels of stack and in order to remember which level
+// of the stack is used, you need to implement a stack pointer (sp).
+/// Levels of the call stack.
+pub const STACK_LEVELS:usize = 16;
+
+/// The 16 level call stack used by `_2NNN` and `_00EE`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stack
+{
+  pub stack:[u16;STACK_LEVELS],
+  pub sp:u16,
+}
+
+impl Stack
+{
+  pub fn new() -> Self
+  {
+    Stack { stack:[0x0;STACK_LEVELS], sp:0x0 }
+  }
+
+  pub fn clear(&mut self)
+  {
+    for idx in 0..STACK_LEVELS
+    {
+      self.stack[idx] = 0x0;
+    }
+
+    self.sp = 0x0;
+  }
+}
+
+impl Default for Stack
+{
+  fn default() -> Self
+  {
+    Stack::new()
+  }
+}
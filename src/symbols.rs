@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Identifies a JSON file as a symbol map.
+pub const SYMBOLS_FORMAT:&str = "chip8-symbols";
+
+/// Layout version written by this build.
+pub const SYMBOLS_VERSION:u32 = 1;
+
+/// Why a symbol map couldn't be read or written.
+#[derive(Debug)]
+pub enum SymbolsError
+{
+  Io(io::Error),
+  /// The file isn't a valid symbol map.
+  Format(String),
+  /// Written by a newer build than this one.
+  UnsupportedVersion { version:u32 },
+}
+
+impl fmt::Display for SymbolsError
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    match self
+    {
+      SymbolsError::Io(err)
+          => write!(f, "{}", err),
+      SymbolsError::Format(reason)
+          => write!(f, "malformed symbol map: {}", reason),
+      SymbolsError::UnsupportedVersion { version }
+          => write!(f, "symbol map version {} is newer than the supported version {}", version, SYMBOLS_VERSION),
+    }
+  }
+}
+
+impl Error for SymbolsError {}
+
+impl From<io::Error> for SymbolsError
+{
+  fn from(err:io::Error) -> Self
+  {
+    SymbolsError::Io(err)
+  }
+}
+
+impl From<serde_json::Error> for SymbolsError
+{
+  fn from(err:serde_json::Error) -> Self
+  {
+    SymbolsError::Format(err.to_string())
+  }
+}
+
+/// The address a source line assembled to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceLine
+{
+  pub file:String,
+  pub line:u32, // 1-based
+  pub addr:u16,
+}
+
+/// Labels and source lines of an assembled program, for debuggers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolMap
+{
+  pub format:     String,
+  pub version:    u32,
+
+  #[serde(default)]
+  pub labels:     BTreeMap<String, u16>,
+  #[serde(default)]
+  pub lines:      Vec<SourceLine>, // In address order
+  #[serde(default)]
+  pub breakpoints:BTreeMap<String, u16>, // From :breakpoint, where debuggers should stop
+}
+
+// Paths in a symbol map are as the assembler saw them, while editors use
+// absolute paths, so two paths name the same file if one ends with the
+// other.
+fn same_file(a:&str, b:&str) -> bool
+{
+  let (a, b) = (Path::new(a), Path::new(b));
+
+  a.ends_with(b) || b.ends_with(a)
+}
+
+impl SymbolMap
+{
+  pub fn new() -> Self
+  {
+    SymbolMap { format:     SYMBOLS_FORMAT.to_string(),
+                version:    SYMBOLS_VERSION,
+                labels:     BTreeMap::new(),
+                lines:      Vec::new(),
+                breakpoints:BTreeMap::new(),
+              }
+  }
+
+  /// The label at or closest before `addr`, with the offset from it.
+  pub fn label_before(&self, addr:u16) -> Option<(&str, u16)>
+  {
+    self.labels.iter()
+               .filter(|&(_, &label)| label <= addr)
+               .max_by_key(|&(_, &label)| label)
+               .map(|(name, &label)| (name.as_str(), addr - label))
+  }
+
+  /// `addr` as `label+offset`, or in hex if no label precedes it.
+  pub fn describe(&self, addr:u16) -> String
+  {
+    match self.label_before(addr)
+    {
+      Some((name, 0))      => name.to_string(),
+      Some((name, offset)) => format!("{}+{}", name, offset),
+      None                 => format!("{:#05X}", addr),
+    }
+  }
+
+  /// The source line `addr` was assembled from.
+  pub fn line_of(&self, addr:u16) -> Option<&SourceLine>
+  {
+    self.lines.iter().find(|line| line.addr == addr)
+  }
+
+  /// The first line at or after `line` in `file` that produced code, for
+  /// placing a breakpoint.
+  pub fn code_line(&self, file:&str, line:u32) -> Option<&SourceLine>
+  {
+    self.lines.iter()
+              .filter(|entry| entry.line >= line && same_file(&entry.file, file))
+              .min_by_key(|entry| (entry.line, entry.addr))
+  }
+
+  pub fn to_json(&self) -> String
+  {
+    serde_json::to_string_pretty(self).expect("symbol map serializes")
+  }
+
+  pub fn from_json(json:&str) -> Result<Self, SymbolsError>
+  {
+    let symbols:SymbolMap = serde_json::from_str(json)?;
+
+    if symbols.format != SYMBOLS_FORMAT
+    {
+      return Err(SymbolsError::Format(format!("unknown format '{}'", symbols.format)));
+    }
+
+    if symbols.version > SYMBOLS_VERSION
+    {
+      return Err(SymbolsError::UnsupportedVersion { version:symbols.version });
+    }
+
+    Ok(symbols)
+  }
+
+  pub fn save<P:AsRef<Path>>(&self, path:P) -> Result<(), SymbolsError>
+  {
+    fs::write(path, self.to_json())?;
+
+    Ok(())
+  }
+
+  pub fn load<P:AsRef<Path>>(path:P) -> Result<Self, SymbolsError>
+  {
+    SymbolMap::from_json(&fs::read_to_string(path)?)
+  }
+}
+
+impl Default for SymbolMap
+{
+  fn default() -> Self
+  {
+    Self::new()
+  }
+}
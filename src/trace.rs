@@ -0,0 +1,253 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+use crate::chip8::Chip8;
+use crate::disasm::cowgod;
+use crate::instruction::Instruction;
+
+/// One executed instruction in a trace, with the machine state after it:
+///
+/// `PC=0200 OP=6A02 V0=00 .. VF=00 I=0000 DT=00 ST=00 SP=00 ; LD VA, 0x02`
+///
+/// `PC` is where the instruction was. Fields are hex `KEY=VALUE` pairs;
+/// what follows the `;` is only for reading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceLine
+{
+  pub fields:  Vec<(String, u32)>, // In line order
+  pub mnemonic:String,
+}
+
+impl TraceLine
+{
+  /// The line for `instruction`, which `chip8` just ran from `pc`.
+  pub fn new(chip8:&Chip8, pc:u16, instruction:Instruction) -> Self
+  {
+    let regs   = chip8.regs();
+    let memory = &chip8.memory().memory;
+    let long   = memory.get(pc as usize + 2..pc as usize + 4).map_or(0, |nnnn| (nnnn[0] as u16) << 8 | nnnn[1] as u16);
+
+    let mut fields = vec![("PC".to_string(), pc as u32), ("OP".to_string(), instruction.encode() as u32)];
+
+    fields.extend(regs.V.iter().enumerate().map(|(x, &v)| (format!("V{:X}", x), v as u32)));
+    fields.push(("I".to_string(),  regs.I as u32));
+    fields.push(("DT".to_string(), regs.DELAY_TIMER as u32));
+    fields.push(("ST".to_string(), regs.SOUND_TIMER as u32));
+    fields.push(("SP".to_string(), chip8.stack().sp as u32));
+
+    TraceLine { fields, mnemonic:cowgod(instruction, long) }
+  }
+
+  pub fn get(&self, key:&str) -> Option<u32>
+  {
+    self.fields.iter().find(|(name, _)| name == key).map(|&(_, value)| value)
+  }
+
+  /// The fields both lines have, but with different values. Fields only
+  /// one side traces are ignored, so traces from emulators that log less
+  /// still compare.
+  pub fn differences(&self, other:&TraceLine) -> Vec<String>
+  {
+    self.fields.iter()
+               .filter(|(key, value)| other.get(key).is_some_and(|theirs| theirs != *value))
+               .map(|(key, _)| key.clone())
+               .collect()
+  }
+}
+
+/// Hex digits a field is written with.
+pub fn field_width(key:&str) -> usize
+{
+  match key
+  {
+    "PC" | "OP" | "I" => 4,
+    _                 => 2,
+  }
+}
+
+impl fmt::Display for TraceLine
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    for (idx, (key, value)) in self.fields.iter().enumerate()
+    {
+      let separator = if idx == 0 { "" } else { " " };
+
+      write!(f, "{}{}={:0width$X}", separator, key, value, width = field_width(key))?;
+    }
+
+    if !self.mnemonic.is_empty()
+    {
+      write!(f, " ; {}", self.mnemonic)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl FromStr for TraceLine
+{
+  type Err = String;
+
+  fn from_str(s:&str) -> Result<Self, Self::Err>
+  {
+    let (state, mnemonic) = s.split_once(';').unwrap_or((s, ""));
+
+    let fields = state.split_whitespace().map(|field|
+    {
+      let (key, value) = field.split_once(['=', ':']).ok_or_else(|| format!("expected KEY=VALUE, got '{}'", field))?;
+      let digits       = value.trim_start_matches("0x").trim_start_matches("0X");
+
+      u32::from_str_radix(digits, 16).map(|value| (key.to_uppercase(), value))
+                                     .map_err(|_| format!("'{}' isn't a hex value", value))
+    }).collect::<Result<Vec<_>, String>>()?;
+
+    if fields.is_empty()
+    {
+      return Err("empty trace line".to_string());
+    }
+
+    Ok(TraceLine { fields, mnemonic:mnemonic.trim().to_string() })
+  }
+}
+
+/// Writes a `TraceLine` per executed instruction. Hand one to
+/// `Chip8::set_trace`.
+pub struct Trace
+{
+  out:  Box<dyn Write + Send>,
+  lines:u64,
+  error:Option<io::Error>, // The first failed write, after which nothing is written
+}
+
+impl Trace
+{
+  pub fn new(out:Box<dyn Write + Send>) -> Self
+  {
+    Trace { out, lines:0, error:None }
+  }
+
+  /// Trace into a new file at `path`.
+  pub fn create(path:&str) -> io::Result<Self>
+  {
+    Ok(Trace::new(Box::new(BufWriter::new(File::create(path)?))))
+  }
+
+  pub fn record(&mut self, line:&TraceLine)
+  {
+    if self.error.is_some()
+    {
+      return;
+    }
+
+    match writeln!(self.out, "{}", line)
+    {
+      Ok(())   => self.lines += 1,
+      Err(err) => self.error = Some(err),
+    }
+  }
+
+  /// Lines written so far.
+  pub fn lines(&self) -> u64
+  {
+    self.lines
+  }
+
+  /// Flush the trace, returning the lines written or the first error.
+  pub fn finish(mut self) -> io::Result<u64>
+  {
+    if let Some(err) = self.error.take()
+    {
+      return Err(err);
+    }
+
+    self.out.flush()?;
+
+    Ok(self.lines)
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use std::sync::{Arc, Mutex};
+
+  use super::*;
+
+  // Output the test can read back after the trace wrote it.
+  #[derive(Clone, Default)]
+  struct Output(Arc<Mutex<Vec<u8>>>);
+
+  impl Write for Output
+  {
+    fn write(&mut self, buf:&[u8]) -> io::Result<usize>
+    {
+      self.0.lock().unwrap().extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn traced_lines_parse_back()
+  {
+    let output = Output::default();
+
+    let mut chip8 = Chip8::new();
+    chip8.initialize();
+    chip8.load_rom(&[0x6A, 0x02, 0xA3, 0x00, 0x22, 0x08, 0x00, 0x00, 0x7A, 0x01]).unwrap();
+    chip8.set_trace(Trace::new(Box::new(output.clone())));
+
+    for _ in 0..4
+    {
+      chip8.emulate_cycle().unwrap();
+    }
+
+    assert_eq!(chip8.take_trace().unwrap().finish().unwrap(), 4);
+
+    let text  = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+
+    assert_eq!(lines[0], "PC=0200 OP=6A02 V0=00 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00 V8=00 V9=00 \
+                          VA=02 VB=00 VC=00 VD=00 VE=00 VF=00 I=0000 DT=00 ST=00 SP=00 ; LD VA, 0x02");
+
+    for line in lines.iter()
+    {
+      let parsed:TraceLine = line.parse().unwrap();
+
+      assert_eq!(parsed.to_string(), *line);
+    }
+
+    let last:TraceLine = lines[3].parse().unwrap();
+    assert_eq!((last.get("PC"), last.get("VA"), last.get("I"), last.get("SP")), (Some(0x208), Some(0x03), Some(0x300), Some(1)));
+  }
+
+  #[test]
+  fn other_trace_formats_parse()
+  {
+    let line:TraceLine = "pc:0x200 v0=0A I=0x0300".parse().unwrap();
+
+    assert_eq!(line.fields, vec![("PC".to_string(), 0x200), ("V0".to_string(), 0x0A), ("I".to_string(), 0x300)]);
+    assert_eq!(line.mnemonic, "");
+
+    assert!("PC=zz".parse::<TraceLine>().is_err());
+    assert!("PC".parse::<TraceLine>().is_err());
+    assert!(" ; CLS".parse::<TraceLine>().is_err());
+  }
+
+  #[test]
+  fn differences_only_compare_shared_fields()
+  {
+    let ours:TraceLine   = "PC=0200 OP=6A02 VA=02 I=0000 DT=00 ; LD VA, 0x02".parse().unwrap();
+    let theirs:TraceLine = "PC=0200 VA=03 I=0001 ; something else".parse().unwrap();
+
+    assert_eq!(ours.differences(&theirs), vec!["VA".to_string(), "I".to_string()]);
+    assert!(ours.differences(&ours).is_empty());
+  }
+}
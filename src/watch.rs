@@ -0,0 +1,216 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::registers::Registers;
+
+/// How an instruction touches a memory byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access
+{
+  Read,
+  Write,
+  /// The byte is the first byte of the instruction about to execute.
+  Execute,
+}
+
+/// A register that can be watched for changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register
+{
+  V(u8),
+  I,
+  DelayTimer,
+  SoundTimer,
+}
+
+impl Register
+{
+  pub fn get(&self, regs:&Registers) -> u16
+  {
+    match *self
+    {
+      Register::V(idx)      => regs.V[(idx & 0xF) as usize] as u16,
+      Register::I           => regs.I,
+      Register::DelayTimer  => regs.DELAY_TIMER as u16,
+      Register::SoundTimer  => regs.SOUND_TIMER as u16,
+    }
+  }
+}
+
+/// Parses V0-VF, I, DT and ST.
+impl FromStr for Register
+{
+  type Err = String;
+
+  fn from_str(s:&str) -> Result<Self, Self::Err>
+  {
+    match s.to_uppercase().as_str()
+    {
+      "I"  => Ok(Register::I),
+      "DT" => Ok(Register::DelayTimer),
+      "ST" => Ok(Register::SoundTimer),
+      name => name.strip_prefix('V')
+                  .filter(|idx| idx.len() == 1)
+                  .and_then(|idx| u8::from_str_radix(idx, 16).ok())
+                  .map(Register::V)
+                  .ok_or_else(|| format!("unknown register '{}'", s)),
+    }
+  }
+}
+
+impl fmt::Display for Register
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    match self
+    {
+      Register::V(idx)      => write!(f, "V{:X}", idx),
+      Register::I           => write!(f, "I"),
+      Register::DelayTimer  => write!(f, "DT"),
+      Register::SoundTimer  => write!(f, "ST"),
+    }
+  }
+}
+
+/// What a watchpoint looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchTarget
+{
+  Memory { addr:u16, access:Access },
+  /// Fires when an instruction changes the register.
+  Register(Register),
+}
+
+/// A condition that stops execution when memory or a register is
+/// accessed. With `value` set, only accesses of that value count: the
+/// byte read or written, the opcode executed, or the register's new value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint
+{
+  pub target:WatchTarget,
+  pub value: Option<u16>,
+}
+
+impl Watchpoint
+{
+  pub fn memory(addr:u16, access:Access) -> Self
+  {
+    Watchpoint { target:WatchTarget::Memory { addr, access }, value:None }
+  }
+
+  pub fn register(register:Register) -> Self
+  {
+    Watchpoint { target:WatchTarget::Register(register), value:None }
+  }
+
+  /// Only fire when the value involved is `value`.
+  pub fn with_value(self, value:u16) -> Self
+  {
+    Watchpoint { value:Some(value), ..self }
+  }
+
+  /// True if the watchpoint's value condition, if any, holds for `value`.
+  pub fn accepts(&self, value:u16) -> bool
+  {
+    self.value.is_none_or(|v| v == value)
+  }
+
+  pub(crate) fn matches_memory(&self, addr:usize, access:Access, value:u16) -> bool
+  {
+    self.target == WatchTarget::Memory { addr:addr as u16, access } && self.accepts(value)
+  }
+}
+
+impl fmt::Display for Watchpoint
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    match self.target
+    {
+      WatchTarget::Memory { addr, access } => write!(f, "{:?} {:#05X}", access, addr)?,
+      WatchTarget::Register(register)      => write!(f, "{}", register)?,
+    }
+
+    if let Some(value) = self.value
+    {
+      write!(f, " == {:#X}", value)?;
+    }
+
+    Ok(())
+  }
+}
+
+/// A watchpoint that fired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit
+{
+  pub watchpoint:Watchpoint,
+  /// Address of the instruction that caused the hit.
+  pub pc:        u16,
+  /// The value read, written or executed, or the register's new value.
+  pub value:     u16,
+}
+
+impl fmt::Display for WatchHit
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    write!(f, "Watchpoint {} hit by {:#05X}, value {:#X}", self.watchpoint, self.pc, self.value)
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::chip8::Chip8;
+
+  #[test]
+  fn registers_parse()
+  {
+    assert_eq!("vA".parse(), Ok(Register::V(0xA)));
+    assert_eq!("dt".parse(), Ok(Register::DelayTimer));
+    assert_eq!("I".parse(), Ok(Register::I));
+    assert!("V10".parse::<Register>().is_err());
+  }
+
+  #[test]
+  fn watchpoints_fire_on_matching_accesses()
+  {
+    // I = 0x300, V0 = 0xAA, store V0, I = 0x300, load V0, V1 = 5 twice
+    let rom = [0xA3, 0x00, 0x60, 0xAA, 0xF0, 0x55, 0xA3, 0x00, 0xF0, 0x65, 0x61, 0x05, 0x61, 0x05];
+
+    let mut chip8 = Chip8::new();
+    chip8.initialize();
+    chip8.load_rom(&rom).unwrap();
+
+    let write   = Watchpoint::memory(0x300, Access::Write);
+    let read    = Watchpoint::memory(0x300, Access::Read).with_value(0xAA);
+    let other   = Watchpoint::memory(0x300, Access::Write).with_value(0xBB);
+    let exec    = Watchpoint::memory(0x20C, Access::Execute);
+    let reg     = Watchpoint::register(Register::V(0x1));
+
+    for watchpoint in [write, read, other, exec, reg].iter()
+    {
+      chip8.add_watchpoint(*watchpoint);
+    }
+
+    let mut hits = Vec::new();
+
+    for _ in 0..7
+    {
+      if let Some(hit) = chip8.execute_watch_hit()
+      {
+        hits.push(hit);
+      }
+
+      chip8.emulate_cycle().unwrap();
+      hits.extend_from_slice(chip8.watch_hits());
+    }
+
+    assert_eq!(hits, vec![WatchHit { watchpoint:write, pc:0x204, value:0xAA },
+                          WatchHit { watchpoint:read,  pc:0x208, value:0xAA },
+                          WatchHit { watchpoint:reg,   pc:0x20A, value:0x05 },
+                          WatchHit { watchpoint:exec,  pc:0x20C, value:0x6105 }]);
+  }
+}